@@ -0,0 +1,85 @@
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+
+/// One message awaiting verification as part of a batch.
+pub struct SignedItem<'a> {
+    pub message: &'a [u8],
+    pub public_key: VerifyingKey,
+    pub signature: Signature,
+}
+
+/// Verifies many signatures at once, returning one validity flag per item.
+///
+/// The whole set is first checked with a single batched multiscalar
+/// multiplication. Only if that fails are items checked one by one, so the
+/// common all-valid case costs a fraction of individual verification while a
+/// poisoned batch still pinpoints the bad entries.
+pub fn verify_batch(items: &[SignedItem<'_>]) -> Vec<bool> {
+    if items.is_empty() {
+        return Vec::new();
+    }
+
+    let messages: Vec<&[u8]> = items.iter().map(|item| item.message).collect();
+    let signatures: Vec<Signature> = items.iter().map(|item| item.signature).collect();
+    let keys: Vec<VerifyingKey> = items.iter().map(|item| item.public_key).collect();
+
+    if ed25519_dalek::verify_batch(&messages, &signatures, &keys).is_ok() {
+        return vec![true; items.len()];
+    }
+
+    items
+        .iter()
+        .map(|item| item.public_key.verify(item.message, &item.signature).is_ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::NodeIdentity;
+
+    fn signed(identities: &[NodeIdentity], messages: &[Vec<u8>]) -> Vec<Signature> {
+        identities.iter().zip(messages).map(|(id, msg)| id.sign(msg)).collect()
+    }
+
+    #[test]
+    fn test_batch_all_valid() {
+        let identities: Vec<_> = (0..16).map(|_| NodeIdentity::generate()).collect();
+        let messages: Vec<Vec<u8>> = (0..16).map(|i| format!("message {}", i).into_bytes()).collect();
+        let signatures = signed(&identities, &messages);
+
+        let items: Vec<SignedItem> = (0..16)
+            .map(|i| SignedItem {
+                message: &messages[i],
+                public_key: identities[i].public_key(),
+                signature: signatures[i],
+            })
+            .collect();
+
+        assert!(verify_batch(&items).into_iter().all(|ok| ok));
+    }
+
+    #[test]
+    fn test_batch_pinpoints_bad_signature() {
+        let identities: Vec<_> = (0..8).map(|_| NodeIdentity::generate()).collect();
+        let messages: Vec<Vec<u8>> = (0..8).map(|i| format!("message {}", i).into_bytes()).collect();
+        let mut signatures = signed(&identities, &messages);
+        signatures[5] = identities[5].sign(b"something else");
+
+        let items: Vec<SignedItem> = (0..8)
+            .map(|i| SignedItem {
+                message: &messages[i],
+                public_key: identities[i].public_key(),
+                signature: signatures[i],
+            })
+            .collect();
+
+        let results = verify_batch(&items);
+        assert_eq!(results.iter().filter(|ok| !**ok).count(), 1);
+        assert!(!results[5]);
+    }
+
+    #[test]
+    fn test_empty_batch() {
+        assert!(verify_batch(&[]).is_empty());
+    }
+}
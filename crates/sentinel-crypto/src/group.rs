@@ -0,0 +1,267 @@
+use anyhow::{Context, Result};
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key};
+use curve25519_dalek::MontgomeryPoint;
+use ed25519_dalek::VerifyingKey;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use sha2::Sha256;
+use std::collections::BTreeMap;
+use zeroize::Zeroizing;
+
+use crate::{x25519_public_key, NodeIdentity};
+
+const SEAL_CONTEXT: &[u8] = b"sentinel-seal-v1";
+const SENDER_KEY_LEN: usize = 4 + 32;
+const SEALED_OVERHEAD: usize = 32 + 16;
+
+/// Furthest a receiver will ratchet ahead to reach a message, and the most
+/// skipped message keys it keeps for late arrivals.
+pub const MAX_SKIP: u32 = 2000;
+
+/// One member's sending chain in a group (the "sender keys" scheme).
+///
+/// Every message is encrypted under a fresh message key and the chain key is
+/// ratcheted forward with HMAC-SHA256, so a leaked chain key does not expose
+/// earlier messages. Members hand their chain to each other with [`seal`].
+pub struct SenderKey {
+    chain_key: Zeroizing<[u8; 32]>,
+    iteration: u32,
+}
+
+impl SenderKey {
+    pub fn generate() -> Self {
+        let mut chain_key = Zeroizing::new([0u8; 32]);
+        OsRng.fill_bytes(chain_key.as_mut_slice());
+        Self { chain_key, iteration: 0 }
+    }
+
+    /// Iteration of the next message this chain will encrypt.
+    pub fn iteration(&self) -> u32 {
+        self.iteration
+    }
+
+    /// Message key for the current iteration, then advances the chain.
+    fn step(&mut self) -> Result<Zeroizing<[u8; 32]>> {
+        let derive = |label: u8| {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(self.chain_key.as_slice())
+                .expect("HMAC accepts any key length");
+            mac.update(&[label]);
+            Zeroizing::new(<[u8; 32]>::from(mac.finalize().into_bytes()))
+        };
+        let message_key = derive(0x01);
+        let next_chain = derive(0x02);
+
+        self.iteration = self.iteration.checked_add(1).context("Sender key chain exhausted")?;
+        self.chain_key = next_chain;
+        Ok(message_key)
+    }
+
+    /// Encrypts `plaintext`, returning the iteration it was sent at and the ciphertext.
+    pub fn encrypt(&mut self, plaintext: &[u8], aad: &[u8]) -> Result<(u32, Vec<u8>)> {
+        let iteration = self.iteration;
+        let message_key = self.step()?;
+        let ciphertext = aead(&message_key)
+            .encrypt(&ZERO_NONCE.into(), Payload { msg: plaintext, aad })
+            .map_err(|_| anyhow::anyhow!("Group encryption failed"))?;
+        Ok((iteration, ciphertext))
+    }
+
+    /// Wire layout: `iteration (u32 BE) | chain_key (32)`.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = Zeroizing::new(Vec::with_capacity(SENDER_KEY_LEN));
+        bytes.extend_from_slice(&self.iteration.to_be_bytes());
+        bytes.extend_from_slice(self.chain_key.as_slice());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != SENDER_KEY_LEN {
+            anyhow::bail!("Invalid sender key length: expected {}, got {}", SENDER_KEY_LEN, bytes.len());
+        }
+        let iteration = u32::from_be_bytes(bytes[..4].try_into().expect("length checked"));
+        let mut chain_key = Zeroizing::new([0u8; 32]);
+        chain_key.copy_from_slice(&bytes[4..]);
+        Ok(Self { chain_key, iteration })
+    }
+
+    fn duplicate(&self) -> Self {
+        Self { chain_key: self.chain_key.clone(), iteration: self.iteration }
+    }
+}
+
+/// The receiving side of another member's [`SenderKey`]. Tolerates reordering
+/// within [`MAX_SKIP`] messages; each message key can be used once.
+pub struct SenderKeyReceiver {
+    chain: SenderKey,
+    skipped: BTreeMap<u32, Zeroizing<[u8; 32]>>,
+}
+
+impl SenderKeyReceiver {
+    pub fn new(chain: SenderKey) -> Self {
+        Self { chain, skipped: BTreeMap::new() }
+    }
+
+    pub fn decrypt(&mut self, iteration: u32, ciphertext: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+        let payload = Payload { msg: ciphertext, aad };
+
+        if iteration < self.chain.iteration {
+            let message_key = self
+                .skipped
+                .get(&iteration)
+                .context("Group message key already used or expired")?;
+            let plaintext = aead(message_key)
+                .decrypt(&ZERO_NONCE.into(), payload)
+                .map_err(|_| anyhow::anyhow!("Group message failed to decrypt"))?;
+            self.skipped.remove(&iteration);
+            return Ok(plaintext);
+        }
+
+        if iteration - self.chain.iteration > MAX_SKIP {
+            anyhow::bail!("Group message is {} messages ahead of its chain", iteration - self.chain.iteration);
+        }
+
+        // Ratchet a copy so a message that fails to decrypt leaves the state untouched.
+        let mut chain = self.chain.duplicate();
+        let mut skipped = Vec::new();
+        while chain.iteration < iteration {
+            skipped.push((chain.iteration, chain.step()?));
+        }
+        let message_key = chain.step()?;
+        let plaintext = aead(&message_key)
+            .decrypt(&ZERO_NONCE.into(), payload)
+            .map_err(|_| anyhow::anyhow!("Group message failed to decrypt"))?;
+
+        self.chain = chain;
+        self.skipped.extend(skipped);
+        while self.skipped.len() > MAX_SKIP as usize {
+            self.skipped.pop_first();
+        }
+        Ok(plaintext)
+    }
+
+    /// Wire layout: sender key | `count (u32 BE)` | `(iteration (u32 BE) | key (32))*`.
+    pub fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        let mut bytes = self.chain.to_bytes();
+        bytes.extend_from_slice(&(self.skipped.len() as u32).to_be_bytes());
+        for (iteration, key) in &self.skipped {
+            bytes.extend_from_slice(&iteration.to_be_bytes());
+            bytes.extend_from_slice(key.as_slice());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < SENDER_KEY_LEN + 4 || !(bytes.len() - SENDER_KEY_LEN - 4).is_multiple_of(36) {
+            anyhow::bail!("Invalid sender key receiver state");
+        }
+        let chain = SenderKey::from_bytes(&bytes[..SENDER_KEY_LEN])?;
+        let mut skipped = BTreeMap::new();
+        for entry in bytes[SENDER_KEY_LEN + 4..].chunks_exact(36) {
+            let iteration = u32::from_be_bytes(entry[..4].try_into().expect("chunk size"));
+            let mut key = Zeroizing::new([0u8; 32]);
+            key.copy_from_slice(&entry[4..]);
+            skipped.insert(iteration, key);
+        }
+        Ok(Self { chain, skipped })
+    }
+}
+
+/// Every message key and every sealing key is used exactly once, so a fixed nonce is safe.
+const ZERO_NONCE: [u8; 12] = [0u8; 12];
+
+fn aead(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+fn seal_key(shared: &[u8; 32], ephemeral: &[u8; 32], recipient: &[u8; 32]) -> Result<Zeroizing<[u8; 32]>> {
+    if shared == &[0u8; 32] {
+        anyhow::bail!("Degenerate X25519 shared secret");
+    }
+    let mut info = Vec::with_capacity(SEAL_CONTEXT.len() + 64);
+    info.extend_from_slice(SEAL_CONTEXT);
+    info.extend_from_slice(ephemeral);
+    info.extend_from_slice(recipient);
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    Hkdf::<Sha256>::new(None, shared)
+        .expand(&info, key.as_mut_slice())
+        .map_err(|_| anyhow::anyhow!("Seal key derivation failed"))?;
+    Ok(key)
+}
+
+/// Encrypts `plaintext` so only the holder of `recipient`'s identity can read it.
+///
+/// Layout: `ephemeral X25519 public (32) | ChaCha20-Poly1305 ciphertext`. The
+/// sender is not authenticated; sign the surrounding message for that.
+pub fn seal(recipient: &VerifyingKey, aad: &[u8], plaintext: &[u8]) -> Result<Vec<u8>> {
+    let mut ephemeral = Zeroizing::new([0u8; 32]);
+    OsRng.fill_bytes(ephemeral.as_mut_slice());
+    let ephemeral_public = MontgomeryPoint::mul_base_clamped(*ephemeral).to_bytes();
+    let recipient_x = x25519_public_key(recipient);
+    let shared = Zeroizing::new(MontgomeryPoint(recipient_x).mul_clamped(*ephemeral).to_bytes());
+
+    let key = seal_key(&shared, &ephemeral_public, &recipient_x)?;
+    let ciphertext = aead(&key)
+        .encrypt(&ZERO_NONCE.into(), Payload { msg: plaintext, aad })
+        .map_err(|_| anyhow::anyhow!("Sealing failed"))?;
+
+    let mut sealed = Vec::with_capacity(SEALED_OVERHEAD + plaintext.len());
+    sealed.extend_from_slice(&ephemeral_public);
+    sealed.extend_from_slice(&ciphertext);
+    Ok(sealed)
+}
+
+impl NodeIdentity {
+    /// Opens a box made by [`seal`] for this identity.
+    pub fn open_sealed(&self, sealed: &[u8], aad: &[u8]) -> Result<Zeroizing<Vec<u8>>> {
+        if sealed.len() < SEALED_OVERHEAD {
+            anyhow::bail!("Sealed box too short");
+        }
+        let ephemeral_public: [u8; 32] = sealed[..32].try_into().expect("length checked");
+        let shared = self.x25519_agree(&ephemeral_public)?;
+
+        let key = seal_key(&shared, &ephemeral_public, &self.x25519_public())?;
+        let plaintext = aead(&key)
+            .decrypt(&ZERO_NONCE.into(), Payload { msg: &sealed[32..], aad })
+            .map_err(|_| anyhow::anyhow!("Sealed box failed to open"))?;
+        Ok(Zeroizing::new(plaintext))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sender_key_out_of_order() {
+        let mut sender = SenderKey::generate();
+        let mut receiver = SenderKeyReceiver::new(SenderKey::from_bytes(&sender.to_bytes()).unwrap());
+
+        let sent: Vec<_> = (0..4)
+            .map(|i| sender.encrypt(format!("msg {}", i).as_bytes(), b"room").unwrap())
+            .collect();
+
+        assert_eq!(receiver.decrypt(sent[2].0, &sent[2].1, b"room").unwrap(), b"msg 2");
+        assert_eq!(receiver.decrypt(sent[0].0, &sent[0].1, b"room").unwrap(), b"msg 0");
+        assert!(receiver.decrypt(sent[0].0, &sent[0].1, b"room").is_err(), "keys are single use");
+        assert!(receiver.decrypt(sent[1].0, &sent[1].1, b"other room").is_err());
+
+        let mut restored = SenderKeyReceiver::from_bytes(&receiver.to_bytes()).unwrap();
+        assert_eq!(restored.decrypt(sent[1].0, &sent[1].1, b"room").unwrap(), b"msg 1");
+        assert_eq!(restored.decrypt(sent[3].0, &sent[3].1, b"room").unwrap(), b"msg 3");
+    }
+
+    #[test]
+    fn test_seal_only_opens_for_recipient() {
+        let alice = NodeIdentity::generate();
+        let mallory = NodeIdentity::generate();
+
+        let sealed = seal(&alice.public_key(), b"aad", b"chain key").unwrap();
+        assert_eq!(alice.open_sealed(&sealed, b"aad").unwrap().as_slice(), b"chain key");
+        assert!(alice.open_sealed(&sealed, b"other aad").is_err());
+        assert!(mallory.open_sealed(&sealed, b"aad").is_err());
+    }
+}
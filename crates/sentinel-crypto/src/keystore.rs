@@ -0,0 +1,275 @@
+use anyhow::{Context, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use ed25519_dalek::VerifyingKey;
+use rand::RngCore;
+use rand::rngs::OsRng;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use zeroize::Zeroizing;
+
+use crate::{ExternalSigner, NodeIdentity};
+
+/// Where a node's identity comes from and, where supported, where it is kept.
+pub trait KeyStore: Send + Sync {
+    /// The stored identity, or `None` if the store is empty.
+    fn load(&self) -> Result<Option<NodeIdentity>>;
+
+    /// Persists `identity`, replacing whatever was stored.
+    fn store(&self, identity: &NodeIdentity) -> Result<()>;
+
+    fn load_or_generate(&self) -> Result<NodeIdentity> {
+        if let Some(identity) = self.load()? {
+            return Ok(identity);
+        }
+        let identity = NodeIdentity::generate();
+        self.store(&identity)?;
+        Ok(identity)
+    }
+}
+
+/// Plain key file in any [`KeyFormat`](crate::KeyFormat); new keys are written raw.
+pub struct FileKeyStore {
+    path: PathBuf,
+}
+
+impl FileKeyStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl KeyStore for FileKeyStore {
+    fn load(&self) -> Result<Option<NodeIdentity>> {
+        if !self.path.exists() || fs::metadata(&self.path)?.len() == 0 {
+            return Ok(None);
+        }
+        NodeIdentity::load_or_generate(&self.path).map(Some)
+    }
+
+    fn store(&self, identity: &NodeIdentity) -> Result<()> {
+        identity.save(&self.path)
+    }
+}
+
+const ENCRYPTED_MAGIC: &[u8; 8] = b"SNTLKEY\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+/// Key file sealed with a passphrase: Argon2id derives the key, ChaCha20-Poly1305 seals the seed.
+///
+/// Layout: `magic (8) | salt (16) | nonce (12) | ciphertext (32 + 16 tag)`.
+pub struct EncryptedFileKeyStore {
+    path: PathBuf,
+    passphrase: Zeroizing<String>,
+}
+
+impl EncryptedFileKeyStore {
+    pub fn new<P: Into<PathBuf>>(path: P, passphrase: impl Into<String>) -> Self {
+        Self { path: path.into(), passphrase: Zeroizing::new(passphrase.into()) }
+    }
+
+    fn cipher(&self, salt: &[u8]) -> Result<ChaCha20Poly1305> {
+        let mut key = Zeroizing::new([0u8; 32]);
+        Argon2::default()
+            .hash_password_into(self.passphrase.as_bytes(), salt, key.as_mut_slice())
+            .map_err(|e| anyhow::anyhow!("Key derivation failed: {}", e))?;
+        Ok(ChaCha20Poly1305::new(Key::from_slice(key.as_slice())))
+    }
+}
+
+impl KeyStore for EncryptedFileKeyStore {
+    fn load(&self) -> Result<Option<NodeIdentity>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(&self.path)
+            .with_context(|| format!("Failed to read {}", self.path.display()))?;
+        let header_len = ENCRYPTED_MAGIC.len() + SALT_LEN + NONCE_LEN;
+        if bytes.len() <= header_len || &bytes[..ENCRYPTED_MAGIC.len()] != ENCRYPTED_MAGIC {
+            anyhow::bail!("{} is not an encrypted Sentinel key file", self.path.display());
+        }
+
+        let salt = &bytes[ENCRYPTED_MAGIC.len()..ENCRYPTED_MAGIC.len() + SALT_LEN];
+        let nonce = &bytes[ENCRYPTED_MAGIC.len() + SALT_LEN..header_len];
+        let seed = Zeroizing::new(
+            self.cipher(salt)?
+                .decrypt(Nonce::from_slice(nonce), &bytes[header_len..])
+                .map_err(|_| anyhow::anyhow!("Wrong passphrase or corrupted key file"))?,
+        );
+        NodeIdentity::from_bytes(&seed).map(Some)
+    }
+
+    fn store(&self, identity: &NodeIdentity) -> Result<()> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+
+        let ciphertext = self
+            .cipher(&salt)?
+            .encrypt(Nonce::from_slice(&nonce), identity.seed()?.as_slice())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt identity key"))?;
+
+        let mut bytes = Vec::with_capacity(ENCRYPTED_MAGIC.len() + SALT_LEN + NONCE_LEN + ciphertext.len());
+        bytes.extend_from_slice(ENCRYPTED_MAGIC);
+        bytes.extend_from_slice(&salt);
+        bytes.extend_from_slice(&nonce);
+        bytes.extend_from_slice(&ciphertext);
+        write_private(&self.path, &bytes)
+    }
+}
+
+/// Identity held only in process memory; nothing touches disk.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    seed: Mutex<Option<Zeroizing<[u8; 32]>>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn load(&self) -> Result<Option<NodeIdentity>> {
+        let seed = self.seed.lock().expect("MemoryKeyStore lock poisoned");
+        seed.as_ref().map(|seed| NodeIdentity::from_bytes(seed.as_slice())).transpose()
+    }
+
+    fn store(&self, identity: &NodeIdentity) -> Result<()> {
+        *self.seed.lock().expect("MemoryKeyStore lock poisoned") = Some(identity.seed()?);
+        Ok(())
+    }
+}
+
+/// Read-only identity taken from an environment variable holding either the
+/// hex-encoded 32-byte seed or a PKCS#8 PEM key.
+pub struct EnvKeyStore {
+    var: String,
+}
+
+impl EnvKeyStore {
+    pub fn new(var: impl Into<String>) -> Self {
+        Self { var: var.into() }
+    }
+}
+
+impl KeyStore for EnvKeyStore {
+    fn load(&self) -> Result<Option<NodeIdentity>> {
+        let Ok(value) = std::env::var(&self.var) else { return Ok(None) };
+        let value = Zeroizing::new(value);
+        let trimmed = value.trim();
+
+        if trimmed.starts_with("-----BEGIN") {
+            return NodeIdentity::from_pkcs8_pem(trimmed).map(Some);
+        }
+        let seed = Zeroizing::new(
+            hex::decode(trimmed).with_context(|| format!("{} is neither hex nor PEM", self.var))?,
+        );
+        NodeIdentity::from_bytes(&seed).map(Some)
+    }
+
+    fn store(&self, _identity: &NodeIdentity) -> Result<()> {
+        anyhow::bail!("Environment keystore is read-only; set {} to provide an identity", self.var)
+    }
+}
+
+/// Identity backed by an external signer; the private key never enters the process.
+pub struct ExternalSignerKeyStore {
+    public_key: VerifyingKey,
+    signer: Arc<ExternalSigner>,
+}
+
+impl ExternalSignerKeyStore {
+    pub fn new(public_key: VerifyingKey, signer: Arc<ExternalSigner>) -> Self {
+        Self { public_key, signer }
+    }
+}
+
+impl KeyStore for ExternalSignerKeyStore {
+    fn load(&self) -> Result<Option<NodeIdentity>> {
+        Ok(Some(NodeIdentity::from_external_signer(self.public_key, self.signer.clone())))
+    }
+
+    fn store(&self, _identity: &NodeIdentity) -> Result<()> {
+        anyhow::bail!("External signer keystore is read-only")
+    }
+}
+
+fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut perms = fs::metadata(path)?.permissions();
+        perms.set_mode(0o600);
+        fs::set_permissions(path, perms)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_encrypted_store_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("identity.enc");
+        let store = EncryptedFileKeyStore::new(&path, "correct horse");
+
+        let id = store.load_or_generate().unwrap();
+        assert_eq!(store.load().unwrap().unwrap().node_id(), id.node_id());
+
+        let raw = fs::read(&path).unwrap();
+        assert!(!raw.windows(32).any(|w| w == id.seed().unwrap().as_slice()));
+
+        let wrong = EncryptedFileKeyStore::new(&path, "battery staple");
+        assert!(wrong.load().is_err());
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let store = MemoryKeyStore::new();
+        assert!(store.load().unwrap().is_none());
+        let id = store.load_or_generate().unwrap();
+        assert_eq!(store.load_or_generate().unwrap().node_id(), id.node_id());
+    }
+
+    #[test]
+    fn test_env_store_is_read_only() {
+        let id = NodeIdentity::generate();
+        let var = "SENTINEL_TEST_IDENTITY_KEY";
+        std::env::set_var(var, hex::encode(id.seed().unwrap().as_slice()));
+
+        let store = EnvKeyStore::new(var);
+        assert_eq!(store.load().unwrap().unwrap().node_id(), id.node_id());
+        assert!(store.store(&id).is_err());
+        std::env::remove_var(var);
+    }
+
+    #[test]
+    fn test_external_signer() {
+        let backing = NodeIdentity::generate();
+        let store = ExternalSignerKeyStore::new(backing.public_key(), backing.signer());
+
+        let id = store.load().unwrap().unwrap();
+        assert_eq!(id.node_id(), backing.node_id());
+        assert!(id.is_external());
+
+        let signature = id.sign(b"payload");
+        assert!(backing.verify(b"payload", &signature));
+        assert!(id.seed().is_err());
+        assert!(id.to_pkcs8_der().is_err());
+        assert!(id.x25519_secret().is_err());
+
+        let impostor = ExternalSignerKeyStore::new(backing.public_key(), NodeIdentity::generate().signer());
+        assert!(impostor.load().unwrap().unwrap().try_sign(b"payload").is_err());
+    }
+}
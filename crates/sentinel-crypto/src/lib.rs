@@ -1,27 +1,117 @@
 use anyhow::{Context, Result};
-use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey, SECRET_KEY_LENGTH};
+use ed25519_dalek::{SigningKey, Verifier};
 use rand::rngs::OsRng;
 use std::fs;
 use std::path::Path;
-use zeroize::Zeroize;
+use std::sync::Arc;
+use zeroize::{Zeroize, Zeroizing};
+
+pub mod batch;
+pub mod cert;
+pub mod group;
+pub mod keystore;
+pub mod membership;
+pub mod mnemonic;
+pub mod pkcs8;
+pub mod succession;
+pub mod x25519;
+
+pub use batch::{verify_batch, SignedItem};
+pub use cert::{node_id_from_certificate, public_key_from_certificate, NodeCertificate};
+pub use group::{seal, SenderKey, SenderKeyReceiver};
+pub use keystore::{
+    EncryptedFileKeyStore, EnvKeyStore, ExternalSignerKeyStore, FileKeyStore, KeyStore, MemoryKeyStore,
+};
+pub use membership::{MembershipCertificate, MAX_CERTIFICATE_LEN};
+pub use pkcs8::{public_key_from_spki_der, public_key_from_spki_pem, KeyFormat};
+pub use succession::SuccessionRecord;
+pub use x25519::x25519_public_key;
+
+/// Key types callers need to hold signatures and peer keys, or implement an
+/// [`ExternalSigner`], without depending on `ed25519-dalek`.
+pub use ed25519_dalek::{Signature, SignatureError, Signer, VerifyingKey};
+
+/// Hex node ID for a public key, matching [`NodeIdentity::node_id`].
+pub fn node_id_from_public_key(key: &VerifyingKey) -> String {
+    hex::encode(key.to_bytes())
+}
+
+/// Recovers the public key behind a hex node ID.
+pub fn public_key_from_node_id(node_id: &str) -> Result<VerifyingKey> {
+    let bytes = hex::decode(node_id).context("Node ID is not valid hex")?;
+    let array: [u8; 32] = bytes
+        .try_into()
+        .map_err(|b: Vec<u8>| anyhow::anyhow!("Invalid node ID length: expected 32 bytes, got {}", b.len()))?;
+    VerifyingKey::from_bytes(&array).context("Node ID is not a valid Ed25519 key")
+}
+
+/// Signs on behalf of an identity whose private key lives outside this process
+/// (HSM, KMS, signing daemon). `try_sign` must return an Ed25519 signature by the
+/// public key the signer was registered with.
+pub type ExternalSigner = dyn Signer<Signature> + Send + Sync;
+
+enum IdentityKey {
+    Local(SigningKey),
+    External {
+        public_key: VerifyingKey,
+        signer: Arc<ExternalSigner>,
+    },
+}
 
-#[derive(Debug)]
 pub struct NodeIdentity {
-    signing_key: SigningKey,
+    key: IdentityKey,
+}
+
+impl std::fmt::Debug for NodeIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NodeIdentity")
+            .field("node_id", &self.node_id())
+            .field("external", &self.is_external())
+            .finish()
+    }
 }
 
 impl Drop for NodeIdentity {
     fn drop(&mut self) {
-        let mut key_bytes = self.signing_key.to_bytes();
-        key_bytes.zeroize();
+        if let IdentityKey::Local(signing_key) = &self.key {
+            let mut key_bytes = signing_key.to_bytes();
+            key_bytes.zeroize();
+        }
     }
 }
 
 impl NodeIdentity {
     pub fn generate() -> Self {
         let mut csprng = OsRng;
-        let signing_key = SigningKey::generate(&mut csprng);
-        Self { signing_key }
+        Self::from_signing_key(SigningKey::generate(&mut csprng))
+    }
+
+    pub(crate) fn from_signing_key(signing_key: SigningKey) -> Self {
+        Self { key: IdentityKey::Local(signing_key) }
+    }
+
+    /// An identity whose signatures come from `signer`; the private key never enters the process.
+    /// Operations that need the secret itself (saving, PKCS#8 export, X25519) will fail.
+    pub fn from_external_signer(public_key: VerifyingKey, signer: Arc<ExternalSigner>) -> Self {
+        Self { key: IdentityKey::External { public_key, signer } }
+    }
+
+    pub fn is_external(&self) -> bool {
+        matches!(self.key, IdentityKey::External { .. })
+    }
+
+    pub(crate) fn signing_key(&self) -> Result<&SigningKey> {
+        match &self.key {
+            IdentityKey::Local(signing_key) => Ok(signing_key),
+            IdentityKey::External { .. } => {
+                anyhow::bail!("Identity key is held by an external signer and cannot be exported")
+            }
+        }
+    }
+
+    /// The raw 32-byte Ed25519 seed.
+    pub fn seed(&self) -> Result<Zeroizing<[u8; 32]>> {
+        Ok(Zeroizing::new(self.signing_key()?.to_bytes()))
     }
 
     pub fn load_or_generate<P: AsRef<Path>>(path: P) -> Result<Self> {
@@ -30,16 +120,10 @@ impl NodeIdentity {
         let exists_and_not_empty = path.exists() && fs::metadata(path)?.len() > 0;
 
         if exists_and_not_empty {
-            let bytes = fs::read(path)
-                .with_context(|| format!("Failed to read {}", path.display()))?;
-            
-            if bytes.len() != SECRET_KEY_LENGTH {
-                anyhow::bail!("Invalid key length: expected 32, got {}", bytes.len());
-            }
-            
-            let array: [u8; 32] = bytes.try_into().expect("Length checked");
-            let signing_key = SigningKey::from_bytes(&array);
-            Ok(Self { signing_key })
+            let bytes = Zeroizing::new(
+                fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?,
+            );
+            Self::from_bytes(&bytes).with_context(|| format!("Failed to load {}", path.display()))
         } else {
             let new_identity = Self::generate();
             new_identity.save(path)?;
@@ -48,19 +132,49 @@ impl NodeIdentity {
     }
 
     pub fn node_id(&self) -> String {
-        hex::encode(self.signing_key.verifying_key().to_bytes())
+        node_id_from_public_key(&self.public_key())
     }
 
     pub fn public_key(&self) -> VerifyingKey {
-        self.signing_key.verifying_key()
+        match &self.key {
+            IdentityKey::Local(signing_key) => signing_key.verifying_key(),
+            IdentityKey::External { public_key, .. } => *public_key,
+        }
     }
 
+    /// Panics if an external signer fails; use [`try_sign`](Self::try_sign) where
+    /// the failure can be reported instead.
     pub fn sign(&self, message: &[u8]) -> Signature {
-        self.signing_key.sign(message)
+        self.try_sign(message).expect("External signer failed")
+    }
+
+    /// Signs `message`, checking that an external signer answered for this identity.
+    pub fn try_sign(&self, message: &[u8]) -> Result<Signature> {
+        match &self.key {
+            IdentityKey::Local(signing_key) => Ok(signing_key.sign(message)),
+            IdentityKey::External { public_key, signer } => {
+                let signature = signer
+                    .try_sign(message)
+                    .map_err(|e| anyhow::anyhow!("External signer failed: {}", e))?;
+                public_key
+                    .verify(message, &signature)
+                    .context("External signer returned a signature by a different key")?;
+                Ok(signature)
+            }
+        }
+    }
+
+    /// A handle that signs as this identity, for consumers that must own one
+    /// (certificate issuance, TLS handshakes). External signers are shared, not copied.
+    pub fn signer(&self) -> Arc<ExternalSigner> {
+        match &self.key {
+            IdentityKey::Local(signing_key) => Arc::new(signing_key.clone()),
+            IdentityKey::External { signer, .. } => signer.clone(),
+        }
     }
 
     pub fn verify(&self, message: &[u8], signature: &Signature) -> bool {
-        self.signing_key.verifying_key().verify(message, signature).is_ok()
+        self.public_key().verify(message, signature).is_ok()
     }
 
     pub fn sign_detached(&self, message: &[u8]) -> [u8; 64] {
@@ -69,7 +183,7 @@ impl NodeIdentity {
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
         let path = path.as_ref();
-        fs::write(path, self.signing_key.to_bytes())
+        fs::write(path, self.seed()?.as_slice())
             .with_context(|| format!("Failed to write {}", path.display()))?;
         
         #[cfg(unix)]
@@ -151,4 +265,13 @@ mod tests {
 // - node_id()           // Hex identifier  
 // - public_key()        // Get public key
 // - sign() / verify()   // Crypto operations
-// - save()              // Persist to disk
\ No newline at end of file
+// - verify_batch()      // Many signatures at once, with per-item fallback
+// - save()              // Persist to disk
+// - rotate()            // Successor identity + signed succession record
+// - self_signed_certificate() // X.509 cert for TLS, keyed by the identity
+// - to/from_pkcs8_pem/der()  // PKCS#8 keys, SPKI public keys
+// - x25519_secret()     // Diffie-Hellman key derived from the identity
+// - to/from_mnemonic() // 24-word BIP39 backup phrase
+// - SenderKey / seal()  // Group channel encryption and pairwise key delivery
+// - issue_membership() // Admin-signed admission certificates
+// - KeyStore            // File, encrypted, memory, env and external-signer backends
\ No newline at end of file
@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+
+use crate::{node_id_from_public_key, NodeIdentity};
+
+const MEMBERSHIP_CONTEXT: &[u8] = b"sentinel-membership-v1";
+const ADMISSION_CONTEXT: &[u8] = b"sentinel-admission-v1";
+const MAX_ROLE_LEN: usize = u8::MAX as usize;
+/// Largest encoded certificate, leaving room for the admission proof within
+/// the 4 KiB admission message.
+pub const MAX_CERTIFICATE_LEN: usize = 4096 - SIGNATURE_LENGTH;
+
+/// An admin's statement that `node_key` may join the mesh until `expires_at`
+/// (Unix seconds) with the listed roles.
+///
+/// Wire layout: `node_key (32) | admin_key (32) | expires_at (u64 BE) |
+/// role_count (u8) | (role_len (u8) | role)* | signature (64)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MembershipCertificate {
+    node_key: VerifyingKey,
+    admin_key: VerifyingKey,
+    expires_at: u64,
+    roles: Vec<String>,
+    signature: Signature,
+}
+
+impl MembershipCertificate {
+    fn body(node_key: &VerifyingKey, admin_key: &VerifyingKey, expires_at: u64, roles: &[String]) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 * PUBLIC_KEY_LENGTH + 9 + roles.iter().map(|r| r.len() + 1).sum::<usize>());
+        bytes.extend_from_slice(node_key.as_bytes());
+        bytes.extend_from_slice(admin_key.as_bytes());
+        bytes.extend_from_slice(&expires_at.to_be_bytes());
+        bytes.push(roles.len() as u8);
+        for role in roles {
+            bytes.push(role.len() as u8);
+            bytes.extend_from_slice(role.as_bytes());
+        }
+        bytes
+    }
+
+    pub fn node_key(&self) -> &VerifyingKey { &self.node_key }
+    pub fn admin_key(&self) -> &VerifyingKey { &self.admin_key }
+    pub fn expires_at(&self) -> u64 { self.expires_at }
+    pub fn roles(&self) -> &[String] { &self.roles }
+
+    pub fn node_id(&self) -> String {
+        node_id_from_public_key(&self.node_key)
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.iter().any(|r| r == role)
+    }
+
+    /// Checks that the certificate was issued by `admin_key` for `node_id` and
+    /// has not expired at `now` (Unix seconds).
+    pub fn verify(&self, admin_key: &VerifyingKey, node_id: &str, now: u64) -> Result<()> {
+        if &self.admin_key != admin_key {
+            anyhow::bail!("Membership certificate issued by unknown admin {}", node_id_from_public_key(&self.admin_key));
+        }
+        if self.node_id() != node_id {
+            anyhow::bail!("Membership certificate belongs to {}, not {}", self.node_id(), node_id);
+        }
+        if now >= self.expires_at {
+            anyhow::bail!("Membership certificate for {} expired at {}", node_id, self.expires_at);
+        }
+
+        let mut message = MEMBERSHIP_CONTEXT.to_vec();
+        message.extend_from_slice(&Self::body(&self.node_key, &self.admin_key, self.expires_at, &self.roles));
+        self.admin_key
+            .verify_strict(&message, &self.signature)
+            .context("Invalid membership certificate signature")
+    }
+
+    /// Checks a [`NodeIdentity::sign_admission`] proof that the certificate's
+    /// holder is on the other end of the channel identified by `binding`.
+    pub fn verify_admission(&self, binding: &[u8], proof: &[u8]) -> Result<()> {
+        let signature = Signature::from_slice(proof).context("Malformed admission proof")?;
+        self.node_key
+            .verify_strict(&admission_message(binding), &signature)
+            .context("Admission proof does not match the membership certificate")
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Self::body(&self.node_key, &self.admin_key, self.expires_at, &self.roles);
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let header = 2 * PUBLIC_KEY_LENGTH + 8 + 1;
+        if bytes.len() < header + SIGNATURE_LENGTH {
+            anyhow::bail!("Membership certificate too short");
+        }
+        if bytes.len() > MAX_CERTIFICATE_LEN {
+            anyhow::bail!("Membership certificate too large ({} bytes)", bytes.len());
+        }
+        let key = |range: std::ops::Range<usize>| -> Result<VerifyingKey> {
+            VerifyingKey::from_bytes(bytes[range].try_into().expect("fixed length"))
+                .context("Invalid key in membership certificate")
+        };
+        let node_key = key(0..PUBLIC_KEY_LENGTH)?;
+        let admin_key = key(PUBLIC_KEY_LENGTH..2 * PUBLIC_KEY_LENGTH)?;
+        let expires_at = u64::from_be_bytes(bytes[2 * PUBLIC_KEY_LENGTH..header - 1].try_into().expect("8 bytes"));
+
+        let role_count = bytes[header - 1] as usize;
+        let mut roles = Vec::with_capacity(role_count);
+        let mut pos = header;
+        for _ in 0..role_count {
+            let len = *bytes.get(pos).context("Truncated membership certificate")? as usize;
+            let role = bytes.get(pos + 1..pos + 1 + len).context("Truncated membership certificate")?;
+            roles.push(String::from_utf8(role.to_vec()).context("Role is not UTF-8")?);
+            pos += 1 + len;
+        }
+
+        if bytes.len() != pos + SIGNATURE_LENGTH {
+            anyhow::bail!("Invalid membership certificate length");
+        }
+        let signature = Signature::from_slice(&bytes[pos..]).context("Invalid membership signature")?;
+        Ok(Self { node_key, admin_key, expires_at, roles, signature })
+    }
+}
+
+fn admission_message(binding: &[u8]) -> Vec<u8> {
+    let mut message = ADMISSION_CONTEXT.to_vec();
+    message.extend_from_slice(binding);
+    message
+}
+
+impl NodeIdentity {
+    /// Issues a membership certificate for `node_key`, signed with this (admin) identity.
+    pub fn issue_membership(
+        &self,
+        node_key: &VerifyingKey,
+        expires_at: u64,
+        roles: Vec<String>,
+    ) -> Result<MembershipCertificate> {
+        if roles.len() > u8::MAX as usize {
+            anyhow::bail!("Too many roles");
+        }
+        if let Some(role) = roles.iter().find(|r| r.is_empty() || r.len() > MAX_ROLE_LEN) {
+            anyhow::bail!("Invalid role name {:?}", role);
+        }
+
+        let admin_key = self.public_key();
+        let body = MembershipCertificate::body(node_key, &admin_key, expires_at, &roles);
+        if body.len() + SIGNATURE_LENGTH > MAX_CERTIFICATE_LEN {
+            anyhow::bail!("Roles make the certificate larger than {} bytes", MAX_CERTIFICATE_LEN);
+        }
+        let mut message = MEMBERSHIP_CONTEXT.to_vec();
+        message.extend_from_slice(&body);
+        Ok(MembershipCertificate {
+            node_key: *node_key,
+            admin_key,
+            expires_at,
+            roles,
+            signature: self.sign(&message),
+        })
+    }
+
+    /// Proof that this identity holds its membership certificate on the channel
+    /// identified by `binding` (a TLS exporter or Noise handshake hash), so a
+    /// certificate copied from elsewhere can't be replayed.
+    pub fn sign_admission(&self, binding: &[u8]) -> [u8; 64] {
+        self.sign_detached(&admission_message(binding))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_membership_certificate() {
+        let admin = NodeIdentity::generate();
+        let node = NodeIdentity::generate();
+        let cert = admin
+            .issue_membership(&node.public_key(), 1_000, vec!["member".into(), "relay".into()])
+            .unwrap();
+
+        let parsed = MembershipCertificate::from_bytes(&cert.to_bytes()).unwrap();
+        assert_eq!(parsed, cert);
+        assert!(parsed.has_role("relay"));
+        parsed.verify(&admin.public_key(), &node.node_id(), 999).unwrap();
+
+        assert!(parsed.verify(&admin.public_key(), &node.node_id(), 1_000).is_err(), "expired");
+        assert!(parsed.verify(&node.public_key(), &node.node_id(), 0).is_err(), "wrong admin");
+        assert!(parsed.verify(&admin.public_key(), &admin.node_id(), 0).is_err(), "wrong node");
+
+        let mut tampered = cert.to_bytes();
+        tampered[2 * PUBLIC_KEY_LENGTH + 7] ^= 1;
+        let tampered = MembershipCertificate::from_bytes(&tampered).unwrap();
+        assert!(tampered.verify(&admin.public_key(), &node.node_id(), 0).is_err());
+    }
+
+    #[test]
+    fn test_admission_proof() {
+        let admin = NodeIdentity::generate();
+        let node = NodeIdentity::generate();
+        let cert = admin.issue_membership(&node.public_key(), u64::MAX, vec![]).unwrap();
+
+        let proof = node.sign_admission(b"channel-a");
+        cert.verify_admission(b"channel-a", &proof).unwrap();
+        assert!(cert.verify_admission(b"channel-b", &proof).is_err());
+        assert!(cert.verify_admission(b"channel-a", &admin.sign_admission(b"channel-a")).is_err());
+    }
+
+    #[test]
+    fn test_oversized_certificate_rejected() {
+        let admin = NodeIdentity::generate();
+        let node = NodeIdentity::generate();
+        let roles = vec!["r".repeat(MAX_ROLE_LEN); 16];
+        assert!(admin.issue_membership(&node.public_key(), u64::MAX, roles.clone()).is_err());
+
+        let fitting = admin.issue_membership(&node.public_key(), u64::MAX, roles[..15].to_vec()).unwrap();
+        assert!(fitting.to_bytes().len() <= MAX_CERTIFICATE_LEN);
+        MembershipCertificate::from_bytes(&fitting.to_bytes()).unwrap();
+
+        let mut oversized = fitting.to_bytes();
+        oversized.resize(MAX_CERTIFICATE_LEN + 1, 0);
+        assert!(MembershipCertificate::from_bytes(&oversized).is_err());
+    }
+}
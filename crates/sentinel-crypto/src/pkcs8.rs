@@ -0,0 +1,161 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::pkcs8::{
+    spki::der::pem::LineEnding, DecodePrivateKey, DecodePublicKey, EncodePrivateKey, EncodePublicKey,
+};
+use ed25519_dalek::{SigningKey, VerifyingKey, SECRET_KEY_LENGTH};
+use std::fs;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use crate::NodeIdentity;
+
+const PEM_PREFIX: &[u8] = b"-----BEGIN";
+
+/// On-disk encodings accepted for an identity key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFormat {
+    /// The bare 32-byte Ed25519 seed written by [`NodeIdentity::save`].
+    Raw,
+    Pkcs8Der,
+    Pkcs8Pem,
+}
+
+impl KeyFormat {
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.trim_ascii_start().starts_with(PEM_PREFIX) {
+            KeyFormat::Pkcs8Pem
+        } else if bytes.len() == SECRET_KEY_LENGTH {
+            KeyFormat::Raw
+        } else {
+            KeyFormat::Pkcs8Der
+        }
+    }
+}
+
+impl NodeIdentity {
+    /// Decodes a key in any [`KeyFormat`], detecting which one from its contents.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match KeyFormat::detect(bytes) {
+            KeyFormat::Raw => {
+                let array: [u8; SECRET_KEY_LENGTH] = bytes.try_into().expect("Length checked");
+                Ok(Self::from_signing_key(SigningKey::from_bytes(&array)))
+            }
+            KeyFormat::Pkcs8Der => Self::from_pkcs8_der(bytes),
+            KeyFormat::Pkcs8Pem => {
+                let pem = std::str::from_utf8(bytes).context("PEM key is not valid UTF-8")?;
+                Self::from_pkcs8_pem(pem)
+            }
+        }
+    }
+
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        let signing_key = SigningKey::from_pkcs8_der(der)
+            .map_err(|e| anyhow::anyhow!("Invalid PKCS#8 DER key: {}", e))?;
+        Ok(Self::from_signing_key(signing_key))
+    }
+
+    pub fn from_pkcs8_pem(pem: &str) -> Result<Self> {
+        let signing_key = SigningKey::from_pkcs8_pem(pem)
+            .map_err(|e| anyhow::anyhow!("Invalid PKCS#8 PEM key: {}", e))?;
+        Ok(Self::from_signing_key(signing_key))
+    }
+
+    pub fn to_pkcs8_der(&self) -> Result<Zeroizing<Vec<u8>>> {
+        let document = self
+            .signing_key()?
+            .to_pkcs8_der()
+            .map_err(|e| anyhow::anyhow!("Failed to encode PKCS#8 DER: {}", e))?;
+        Ok(Zeroizing::new(document.as_bytes().to_vec()))
+    }
+
+    pub fn to_pkcs8_pem(&self) -> Result<Zeroizing<String>> {
+        self.signing_key()?
+            .to_pkcs8_pem(LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("Failed to encode PKCS#8 PEM: {}", e))
+    }
+
+    /// The public key as a DER `SubjectPublicKeyInfo`.
+    pub fn public_key_der(&self) -> Result<Vec<u8>> {
+        self.public_key()
+            .to_public_key_der()
+            .map(|document| document.into_vec())
+            .map_err(|e| anyhow::anyhow!("Failed to encode SPKI DER: {}", e))
+    }
+
+    /// The public key as a PEM `SubjectPublicKeyInfo` (`BEGIN PUBLIC KEY`).
+    pub fn public_key_pem(&self) -> Result<String> {
+        self.public_key()
+            .to_public_key_pem(LineEnding::LF)
+            .map_err(|e| anyhow::anyhow!("Failed to encode SPKI PEM: {}", e))
+    }
+
+    /// Like [`save`](Self::save), but in the requested encoding.
+    pub fn save_as<P: AsRef<Path>>(&self, path: P, format: KeyFormat) -> Result<()> {
+        let path = path.as_ref();
+        let bytes = match format {
+            KeyFormat::Raw => return self.save(path),
+            KeyFormat::Pkcs8Der => self.to_pkcs8_der()?,
+            KeyFormat::Pkcs8Pem => Zeroizing::new(self.to_pkcs8_pem()?.as_bytes().to_vec()),
+        };
+        fs::write(path, bytes.as_slice())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mut perms = fs::metadata(path)?.permissions();
+            perms.set_mode(0o600);
+            fs::set_permissions(path, perms)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn public_key_from_spki_der(der: &[u8]) -> Result<VerifyingKey> {
+    VerifyingKey::from_public_key_der(der).map_err(|e| anyhow::anyhow!("Invalid SPKI DER key: {}", e))
+}
+
+pub fn public_key_from_spki_pem(pem: &str) -> Result<VerifyingKey> {
+    VerifyingKey::from_public_key_pem(pem).map_err(|e| anyhow::anyhow!("Invalid SPKI PEM key: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_pkcs8_roundtrip() {
+        let id = NodeIdentity::generate();
+
+        let from_der = NodeIdentity::from_pkcs8_der(&id.to_pkcs8_der().unwrap()).unwrap();
+        let from_pem = NodeIdentity::from_pkcs8_pem(&id.to_pkcs8_pem().unwrap()).unwrap();
+
+        assert_eq!(from_der.node_id(), id.node_id());
+        assert_eq!(from_pem.node_id(), id.node_id());
+    }
+
+    #[test]
+    fn test_spki_roundtrip() {
+        let id = NodeIdentity::generate();
+
+        assert_eq!(public_key_from_spki_der(&id.public_key_der().unwrap()).unwrap(), id.public_key());
+        assert_eq!(public_key_from_spki_pem(&id.public_key_pem().unwrap()).unwrap(), id.public_key());
+    }
+
+    #[test]
+    fn test_load_detects_format() {
+        let id = NodeIdentity::generate();
+
+        for format in [KeyFormat::Raw, KeyFormat::Pkcs8Der, KeyFormat::Pkcs8Pem] {
+            let temp_file = NamedTempFile::new().unwrap();
+            id.save_as(temp_file.path(), format).unwrap();
+
+            let bytes = fs::read(temp_file.path()).unwrap();
+            assert_eq!(KeyFormat::detect(&bytes), format);
+
+            let loaded = NodeIdentity::load_or_generate(temp_file.path()).unwrap();
+            assert_eq!(loaded.node_id(), id.node_id(), "{:?} should load", format);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use anyhow::{Context, Result};
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{node_id_from_public_key, NodeIdentity};
+
+const SUCCESSION_CONTEXT: &[u8] = b"sentinel-succession-v1";
+const TIMESTAMP_LEN: usize = 8;
+
+pub const SUCCESSION_RECORD_LEN: usize =
+    PUBLIC_KEY_LENGTH + PUBLIC_KEY_LENGTH + TIMESTAMP_LEN + SIGNATURE_LENGTH;
+
+/// Statement, signed by a retiring key, that `new_key` is its successor.
+///
+/// Wire layout: `old_key (32) | new_key (32) | issued_at (u64 BE) | signature (64)`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SuccessionRecord {
+    old_key: VerifyingKey,
+    new_key: VerifyingKey,
+    issued_at: u64,
+    signature: Signature,
+}
+
+impl SuccessionRecord {
+    fn signed_bytes(old_key: &VerifyingKey, new_key: &VerifyingKey, issued_at: u64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SUCCESSION_CONTEXT.len() + SUCCESSION_RECORD_LEN);
+        bytes.extend_from_slice(SUCCESSION_CONTEXT);
+        bytes.extend_from_slice(old_key.as_bytes());
+        bytes.extend_from_slice(new_key.as_bytes());
+        bytes.extend_from_slice(&issued_at.to_be_bytes());
+        bytes
+    }
+
+    pub fn old_key(&self) -> &VerifyingKey { &self.old_key }
+    pub fn new_key(&self) -> &VerifyingKey { &self.new_key }
+    pub fn issued_at(&self) -> u64 { self.issued_at }
+
+    pub fn old_node_id(&self) -> String {
+        node_id_from_public_key(&self.old_key)
+    }
+
+    pub fn new_node_id(&self) -> String {
+        node_id_from_public_key(&self.new_key)
+    }
+
+    /// Checks that the record was signed by the key it retires.
+    pub fn verify(&self) -> Result<()> {
+        if self.old_key == self.new_key {
+            anyhow::bail!("Succession record names the same key twice");
+        }
+        let message = Self::signed_bytes(&self.old_key, &self.new_key, self.issued_at);
+        self.old_key
+            .verify_strict(&message, &self.signature)
+            .context("Invalid succession signature")
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(SUCCESSION_RECORD_LEN);
+        bytes.extend_from_slice(self.old_key.as_bytes());
+        bytes.extend_from_slice(self.new_key.as_bytes());
+        bytes.extend_from_slice(&self.issued_at.to_be_bytes());
+        bytes.extend_from_slice(&self.signature.to_bytes());
+        bytes
+    }
+
+    /// Parses a record without checking its signature; call [`verify`](Self::verify) next.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() != SUCCESSION_RECORD_LEN {
+            anyhow::bail!(
+                "Invalid succession record length: expected {}, got {}",
+                SUCCESSION_RECORD_LEN,
+                bytes.len()
+            );
+        }
+        let (old_key, rest) = bytes.split_at(PUBLIC_KEY_LENGTH);
+        let (new_key, rest) = rest.split_at(PUBLIC_KEY_LENGTH);
+        let (issued_at, signature) = rest.split_at(TIMESTAMP_LEN);
+
+        Ok(Self {
+            old_key: VerifyingKey::from_bytes(old_key.try_into().expect("Length checked"))
+                .context("Invalid old key in succession record")?,
+            new_key: VerifyingKey::from_bytes(new_key.try_into().expect("Length checked"))
+                .context("Invalid new key in succession record")?,
+            issued_at: u64::from_be_bytes(issued_at.try_into().expect("Length checked")),
+            signature: Signature::from_bytes(signature.try_into().expect("Length checked")),
+        })
+    }
+}
+
+impl NodeIdentity {
+    /// Signs a record handing this identity over to `successor`.
+    pub fn succession_to(&self, successor: &VerifyingKey) -> SuccessionRecord {
+        let old_key = self.public_key();
+        let issued_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let message = SuccessionRecord::signed_bytes(&old_key, successor, issued_at);
+
+        SuccessionRecord {
+            old_key,
+            new_key: *successor,
+            issued_at,
+            signature: self.sign(&message),
+        }
+    }
+
+    /// Generates a fresh identity and the record that links it to this one.
+    pub fn rotate(&self) -> (NodeIdentity, SuccessionRecord) {
+        let successor = NodeIdentity::generate();
+        let record = self.succession_to(&successor.public_key());
+        (successor, record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_record_roundtrip() {
+        let old = NodeIdentity::generate();
+        let (new, record) = old.rotate();
+
+        assert_eq!(record.old_node_id(), old.node_id());
+        assert_eq!(record.new_node_id(), new.node_id());
+        record.verify().expect("Fresh record must verify");
+
+        let decoded = SuccessionRecord::from_bytes(&record.to_bytes()).unwrap();
+        assert_eq!(decoded, record);
+        decoded.verify().unwrap();
+    }
+
+    #[test]
+    fn test_tampered_record_rejected() {
+        let old = NodeIdentity::generate();
+        let (_, record) = old.rotate();
+        let attacker = NodeIdentity::generate();
+
+        let mut bytes = record.to_bytes();
+        bytes[PUBLIC_KEY_LENGTH..2 * PUBLIC_KEY_LENGTH]
+            .copy_from_slice(attacker.public_key().as_bytes());
+
+        let forged = SuccessionRecord::from_bytes(&bytes).unwrap();
+        assert!(forged.verify().is_err());
+    }
+
+    #[test]
+    fn test_invalid_record_length() {
+        assert!(SuccessionRecord::from_bytes(&[0u8; 10]).is_err());
+    }
+}
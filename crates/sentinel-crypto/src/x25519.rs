@@ -0,0 +1,52 @@
+use anyhow::Result;
+use curve25519_dalek::MontgomeryPoint;
+use ed25519_dalek::VerifyingKey;
+use zeroize::Zeroizing;
+
+use crate::NodeIdentity;
+
+impl NodeIdentity {
+    /// X25519 secret derived from the identity key, for Diffie-Hellman based
+    /// protocols (Noise, sealed boxes). Its public half is [`x25519_public_key`]
+    /// of this node's Ed25519 key, so peers can derive it from the node ID alone.
+    pub fn x25519_secret(&self) -> Result<Zeroizing<[u8; 32]>> {
+        Ok(Zeroizing::new(self.signing_key()?.to_scalar_bytes()))
+    }
+
+    pub fn x25519_public(&self) -> [u8; 32] {
+        x25519_public_key(&self.public_key())
+    }
+
+    /// X25519 shared secret between this identity and `their_public`.
+    pub fn x25519_agree(&self, their_public: &[u8; 32]) -> Result<Zeroizing<[u8; 32]>> {
+        let secret = self.x25519_secret()?;
+        Ok(Zeroizing::new(MontgomeryPoint(*their_public).mul_clamped(*secret).to_bytes()))
+    }
+}
+
+/// The X25519 public key matching an Ed25519 node key (birational map to Montgomery form).
+pub fn x25519_public_key(key: &VerifyingKey) -> [u8; 32] {
+    key.to_montgomery().to_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_x25519_public_matches_secret() {
+        let id = NodeIdentity::generate();
+        let derived = MontgomeryPoint::mul_base_clamped(*id.x25519_secret().unwrap()).to_bytes();
+        assert_eq!(derived, id.x25519_public());
+    }
+
+    #[test]
+    fn test_x25519_agreement_is_symmetric() {
+        let alice = NodeIdentity::generate();
+        let bob = NodeIdentity::generate();
+        assert_eq!(
+            *alice.x25519_agree(&bob.x25519_public()).unwrap(),
+            *bob.x25519_agree(&alice.x25519_public()).unwrap()
+        );
+    }
+}
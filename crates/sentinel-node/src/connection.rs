@@ -0,0 +1,586 @@
+use crate::engine::{SentinelNode, HANDSHAKE_TIMEOUT, VERIFY_BATCH_SIZE};
+use crate::known_peers::PeerTrust;
+use crate::succession::continues;
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use clap::ValueEnum;
+use futures::{SinkExt, Stream, StreamExt};
+use sentinel_crypto::cert::NODE_DNS_NAME;
+use sentinel_crypto::public_key_from_node_id;
+use sentinel_protocol::{
+    frame::Frame,
+    messages::{MessageContent, SentinelMessage},
+    ProtocolError,
+};
+use sentinel_transport::{
+    Authenticated, BoxedTransport, Connection, ConnectionPermit, MemoryListener, NoiseTransport, Rewind,
+    SentinelTransport, TlsTransport, TransportError, TransportResult, Unauthenticated,
+};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::io::AsyncReadExt;
+use tokio::sync::mpsc;
+
+/// First byte of a TLS handshake record. Noise connections start with the
+/// big-endian length of the first handshake message, whose high byte is 0.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
+
+/// Chat message a dialing node opens with.
+const GREETING: &str = "v2-dial";
+
+/// mDNS TXT key naming the channel a node asks to be dialed over.
+pub const CHANNEL_TXT_KEY: &str = "channel";
+
+/// Most 0-RTT data accepted from a peer resuming a TLS session.
+pub const EARLY_DATA_LIMIT: u32 = 16 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SecureChannel {
+    /// TLS 1.3 with the identity-derived certificate
+    Tls,
+    /// Noise XX keyed directly by the node identity
+    Noise,
+}
+
+impl SecureChannel {
+    /// The name advertised in mDNS TXT records and gossip.
+    pub fn name(self) -> &'static str {
+        match self {
+            SecureChannel::Tls => "tls",
+            SecureChannel::Noise => "noise",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tls" => Some(SecureChannel::Tls),
+            "noise" => Some(SecureChannel::Noise),
+            _ => None,
+        }
+    }
+}
+
+impl SentinelNode {
+    /// The channel peers should dial us over. Noise needs the raw identity key,
+    /// so a node whose key is held by an external signer asks for TLS.
+    pub fn advertised_channel(&self) -> SecureChannel {
+        if self.identity.is_external() { SecureChannel::Tls } else { self.channel }
+    }
+
+    /// Records the channel node `node_id` advertised, ignoring names we don't know.
+    pub fn learn_channel(&self, node_id: &str, channel: Option<&str>) {
+        if public_key_from_node_id(node_id).is_err() {
+            return;
+        }
+        if let Some(channel) = channel.and_then(SecureChannel::from_name) {
+            self.peer_channels.insert(node_id.to_string(), channel);
+        }
+    }
+
+    /// The channel to dial a peer over: the one it advertised, if known, unless
+    /// that is Noise and our own key can't run it.
+    fn dial_channel(&self, expected_node_id: Option<&str>) -> SecureChannel {
+        let advertised = expected_node_id.and_then(|node_id| self.peer_channels.get(node_id).map(|c| *c));
+        match advertised.unwrap_or(self.channel) {
+            SecureChannel::Noise if self.identity.is_external() => SecureChannel::Tls,
+            channel => channel,
+        }
+    }
+
+    /// Secures an inbound connection with whichever channel the peer opened
+    /// with, then serves it until it closes.
+    pub async fn accept_connection<S>(self: Arc<Self>, mut stream: S, addr: String) -> Result<()>
+    where
+        S: SentinelTransport + 'static,
+    {
+        let first = tokio::time::timeout(HANDSHAKE_TIMEOUT, stream.read_u8())
+            .await
+            .context("Peer sent nothing")??;
+        let stream = Rewind::new(vec![first], stream);
+
+        if first == TLS_HANDSHAKE_RECORD {
+            let mut tls = self.acceptor.accept(stream).await?;
+            let early_data = tls.take_early_data();
+            let node_id = tls.peer_node_id();
+            let conn = self.admit_peer(Connection::new(tls), node_id, &addr, false).await?;
+            self.handle_early_data(early_data, &addr).await;
+            self.run_peer(conn, addr, false).await
+        } else {
+            let noise = self.noise_handshake(NoiseTransport::respond(stream, &self.identity)).await?;
+            let node_id = noise.remote_node_id().to_string();
+            let conn = self.admit_peer(Connection::new(noise), Some(node_id), &addr, false).await?;
+            self.run_peer(conn, addr, false).await
+        }
+    }
+
+    /// Dials `addr` and serves the connection. With `expected_node_id` (e.g. from
+    /// an mDNS advertisement), the handshake fails unless the peer proves that ID.
+    pub async fn dial_peer(self: Arc<Self>, expected_node_id: Option<String>, addr: String) -> Result<()> {
+        let stream = self.open_stream(&addr).await?;
+        self.dial_over(stream, expected_node_id, addr).await
+    }
+
+    /// Secures a connection to `addr` the caller has already opened, as the
+    /// dialing side, and serves it like [`dial_peer`](Self::dial_peer) would.
+    pub async fn dial_over<S>(self: Arc<Self>, stream: S, expected_node_id: Option<String>, addr: String) -> Result<()>
+    where
+        S: SentinelTransport + 'static,
+    {
+        match self.dial_channel(expected_node_id.as_deref()) {
+            SecureChannel::Tls => {
+                let mut greet = true;
+                let tls = match (&expected_node_id, &self.ca_path) {
+                    // The greeting is safe to replay, so it may go as 0-RTT data.
+                    (Some(node_id), None) if self.zero_rtt => {
+                        let greeting = self.new_message(MessageContent::Chat(GREETING.into()));
+                        let mut early = BytesMut::new();
+                        Frame::new(1, 0, greeting.to_bytes().into())?.encode(&mut early)?;
+                        let (tls, accepted) = self.connector.connect_to_node_early(node_id, stream, &early).await?;
+                        greet = !accepted;
+                        tls
+                    }
+                    (Some(node_id), None) => self.connector.connect_to_node_over(node_id, stream).await?,
+                    _ => {
+                        let tls = self.connector.connect(NODE_DNS_NAME, stream).await?;
+                        TlsTransport::new(tls.into()).metered(&self.metrics.transport)
+                    }
+                };
+                let node_id = tls.peer_node_id();
+                self.check_tls_peer(&addr, expected_node_id.as_deref(), node_id.as_deref())?;
+                let conn = self.admit_peer(Connection::new(tls), node_id, &addr, true).await?;
+                tokio::spawn(self.run_peer(conn, addr, greet));
+            }
+            SecureChannel::Noise => {
+                let noise = self.noise_handshake(NoiseTransport::initiate(stream, &self.identity)).await?;
+                let node_id = noise.remote_node_id().to_string();
+                if let Some(expected) = &expected_node_id {
+                    if *expected != node_id {
+                        anyhow::bail!("{} is {}, not the advertised {}", addr, node_id, expected);
+                    }
+                }
+                self.check_peer_key(&addr, &node_id)?;
+                let conn = self.admit_peer(Connection::new(noise), Some(node_id), &addr, true).await?;
+                tokio::spawn(self.run_peer(conn, addr, true));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs a Noise handshake with a time limit, recording it like the TLS handshakes.
+    pub(crate) async fn noise_handshake<S: SentinelTransport>(
+        &self,
+        handshake: impl Future<Output = TransportResult<NoiseTransport<S>>>,
+    ) -> Result<NoiseTransport<S>> {
+        let started = Instant::now();
+        let result = tokio::time::timeout(HANDSHAKE_TIMEOUT, handshake)
+            .await
+            .unwrap_or(Err(TransportError::HandshakeTimeout));
+        self.metrics.transport.record_handshake_result(started, &result);
+        Ok(result.context("Noise handshake failed")?.metered(&self.metrics.transport))
+    }
+
+    /// Opens a plain connection to `addr` over TCP (through the proxy, if any), or over the in-memory network if the node has one.
+    pub(crate) async fn open_stream(&self, addr: &str) -> Result<BoxedTransport> {
+        Ok(match &self.network {
+            Some(network) => Box::new(network.connect(addr.parse()?).await?),
+            None => Box::new(self.connector.open(addr).await?),
+        })
+    }
+
+    /// Checks an inbound connection from `addr` against the acceptor's limits, before
+    /// anything is read from it. Refusals are counted in the transport metrics rather
+    /// than logged, so a flood of connections does not also flood the log.
+    pub fn admit_inbound(&self, addr: SocketAddr) -> Option<ConnectionPermit> {
+        self.acceptor.admit(addr.ip()).ok()
+    }
+
+    /// Accepts connections from an in-memory network until the listener closes.
+    // Only tests run nodes on an in-memory network so far.
+    #[cfg_attr(not(test), allow(dead_code))]
+    pub async fn serve_memory(self: Arc<Self>, mut listener: MemoryListener) {
+        while let Ok((stream, addr)) = listener.accept().await {
+            let Some(permit) = self.admit_inbound(addr) else { continue };
+            let node = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Err(e) = node.accept_connection(stream, addr.to_string()).await {
+                    eprintln!("Inbound connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    /// Handles the messages a peer sent as 0-RTT data. Anyone who captured
+    /// them can replay them, so only idempotent ones are acted on.
+    async fn handle_early_data(self: &Arc<Self>, early_data: Vec<u8>, addr: &str) {
+        if early_data.is_empty() {
+            return;
+        }
+        let mut buf = BytesMut::from(&early_data[..]);
+        let mut frames = Vec::new();
+        while let Some(frame) = Frame::decode(&mut buf).transpose() {
+            let failed = frame.is_err();
+            frames.push(frame);
+            if failed {
+                break;
+            }
+        }
+        let (msgs, _) = SentinelNode::decode_frames(frames);
+        let (msgs, unsafe_msgs): (Vec<_>, Vec<_>) = msgs.into_iter().partition(|msg| msg.content.is_idempotent());
+        if !unsafe_msgs.is_empty() {
+            eprintln!("Dropped {} 0-RTT messages from {} that are unsafe to replay", unsafe_msgs.len(), addr);
+        }
+        self.handle_incoming_batch(msgs, addr).await;
+    }
+
+    /// Runs the message pipeline over an authenticated connection until it
+    /// closes, greeting the peer first if `greet` is set.
+    pub async fn run_peer<T>(self: Arc<Self>, conn: Connection<T, Authenticated>, addr: String, greet: bool) -> Result<()>
+    where
+        T: SentinelTransport + 'static,
+    {
+        if let Some(node_id) = conn.node_id() {
+            self.peer_ids.insert(node_id.to_string(), addr.clone());
+        }
+        let (mut sink, stream) = conn.split();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.peers.insert(addr.clone(), tx.clone());
+
+        let addr_out = addr.clone();
+        let node = Arc::clone(&self);
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                if let Ok(f) = Frame::new(1, 0, msg.to_bytes().into()) {
+                    if let Err(e) = sink.send(f).await {
+                        eprintln!("Write error to {}: {}", addr_out, e);
+                        break;
+                    }
+                    node.metrics.message_sent();
+                }
+            }
+        });
+
+        self.serve_frames(stream, tx, addr, greet).await
+    }
+
+    /// Greets a peer registered under `addr` with `tx` if `greet` is set, then
+    /// dispatches its frames until they run out or one is malformed.
+    pub async fn serve_frames<S>(
+        self: Arc<Self>,
+        frames: S,
+        tx: mpsc::UnboundedSender<SentinelMessage>,
+        addr: String,
+        greet: bool,
+    ) -> Result<()>
+    where
+        S: Stream<Item = Result<Frame, ProtocolError>> + Unpin,
+    {
+        if greet {
+            self.send_to_peer(&addr, MessageContent::Chat(GREETING.into())).await?;
+        }
+        self.announce_successions(&addr).await?;
+
+        let mut bursts = frames.ready_chunks(VERIFY_BATCH_SIZE);
+        while let Some(frames) = bursts.next().await {
+            let (msgs, healthy) = SentinelNode::decode_frames(frames);
+            self.handle_incoming_batch(msgs, &addr).await;
+            if !healthy { break; }
+        }
+        // Another connection to the same address may have replaced this one meanwhile.
+        self.peers.remove_if(&addr, |_, current| current.same_channel(&tx));
+        if !self.peers.contains_key(&addr) {
+            self.peer_ids.retain(|_, peer_addr| *peer_addr != addr);
+            self.close_circuits_via(&addr);
+        }
+        println!("Connection closed: {}", addr);
+        Ok(())
+    }
+
+    /// Checks the node ID a dialed TLS or QUIC peer proved against what we expected of it.
+    pub fn check_tls_peer(&self, addr: &str, expected: Option<&str>, node_id: Option<&str>) -> Result<()> {
+        // A CA-issued certificate may carry a non-Ed25519 key and so no node ID;
+        // without a CA the identity key in the certificate is all we have to go on.
+        match (node_id, &self.ca_path) {
+            (Some(node_id), _) => self.check_peer_key(addr, node_id)?,
+            (None, None) => anyhow::bail!("{} presented a certificate without a node ID", addr),
+            (None, Some(_)) => {}
+        }
+        if let (Some(expected), Some(actual)) = (expected, node_id) {
+            if expected != actual {
+                anyhow::bail!("{} is {}, not the advertised {}", addr, actual, expected);
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the membership exchange with a time limit and reports who was admitted.
+    /// `authenticated` is the node ID the secure channel proved, if any.
+    pub async fn admit_peer<T: SentinelTransport>(
+        &self,
+        mut conn: Connection<T, Unauthenticated>,
+        authenticated: Option<String>,
+        addr: &str,
+        dialer: bool,
+    ) -> Result<Connection<T, Authenticated>> {
+        let exchange = self.admit(conn.transport_mut(), authenticated.as_deref(), dialer);
+        let admitted = tokio::time::timeout(HANDSHAKE_TIMEOUT, exchange)
+            .await
+            .context("Membership exchange timed out")?
+            .with_context(|| format!("Refused {}", addr))?;
+        if let Some(cert) = admitted {
+            println!("Admitted {} as {} (roles: {})", addr, cert.node_id(), cert.roles().join(", "));
+        }
+        Ok(conn.authenticate(authenticated))
+    }
+
+    /// Trust-on-first-use check of the key a dialed peer authenticated with. A
+    /// changed key is accepted if a stored succession record links it to the pin.
+    fn check_peer_key(&self, addr: &str, node_id: &str) -> Result<()> {
+        match self.known_peers.check(addr, node_id)? {
+            PeerTrust::FirstContact => println!("Pinned new peer {} as {}", addr, node_id),
+            PeerTrust::Matches => {}
+            // A rotated identity signed its successor over, so the new key carries the pin.
+            PeerTrust::Changed { pinned } if continues(&self.db, node_id, &pinned)? => {
+                self.known_peers.trust(addr, node_id)?;
+                println!("Re-pinned {} as {}, which continues {}", addr, node_id, pinned);
+            }
+            PeerTrust::Changed { pinned } => {
+                eprintln!("WARNING: PEER KEY CHANGED FOR {}", addr);
+                eprintln!("  pinned:    {}", pinned);
+                eprintln!("  presented: {}", node_id);
+                eprintln!("Someone may be impersonating this peer. If the change is expected, run:");
+                eprintln!("  sentinel-node peers trust {}", addr);
+                anyhow::bail!("Refusing {}: key does not match pinned key", addr);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NodeConfig;
+    use crate::relay::{relay_key, RelayLimits};
+    use crate::succession::record_succession;
+    use sentinel_crypto::NodeIdentity;
+    use sentinel_crypto::{ExternalSignerKeyStore, MemoryKeyStore};
+    use sentinel_transport::{ConnectionLimits, MemoryNetwork};
+    use std::time::Duration;
+
+    async fn start_node(network: &MemoryNetwork, dir: &std::path::Path, addr: &str, channel: SecureChannel) -> Arc<SentinelNode> {
+        start_configured_node(network, dir, addr, channel, |_| {}).await
+    }
+
+    async fn start_configured_node(
+        network: &MemoryNetwork,
+        dir: &std::path::Path,
+        addr: &str,
+        channel: SecureChannel,
+        configure: impl FnOnce(&mut NodeConfig),
+    ) -> Arc<SentinelNode> {
+        let mut config = NodeConfig { channel, ..NodeConfig::for_test(dir, network) };
+        configure(&mut config);
+        let node = Arc::new(SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap());
+        let listener = network.bind(addr.parse().unwrap()).unwrap();
+        tokio::spawn(Arc::clone(&node).serve_memory(listener));
+        node
+    }
+
+    /// Broadcasts a chat from `from` until every node in `to` has seen it. Peers
+    /// register only once admission completes, so early copies may go nowhere.
+    async fn deliver(from: &SentinelNode, to: &[&SentinelNode], what: &str) {
+        let msg = from.new_message(MessageContent::Chat(what.into()));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            for node in to {
+                while !node.seen_messages.lock().await.contains(&msg.id) {
+                    from.broadcast(msg.clone()).await;
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("{} was not delivered", what));
+    }
+
+    #[tokio::test]
+    async fn test_nodes_exchange_messages_over_memory_network() {
+        for channel in [SecureChannel::Tls, SecureChannel::Noise] {
+            let network = MemoryNetwork::new();
+            let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+            let a = start_node(&network, dirs[0].path(), "10.0.0.1:8443", channel).await;
+            let b = start_node(&network, dirs[1].path(), "10.0.0.2:8443", channel).await;
+            let c = start_node(&network, dirs[2].path(), "10.0.0.3:8443", channel).await;
+
+            // A line: a - b - c. A dial expecting the wrong node is refused.
+            let impostor = c.identity.node_id();
+            assert!(Arc::clone(&a).dial_peer(Some(impostor), "10.0.0.2:8443".into()).await.is_err());
+            Arc::clone(&a).dial_peer(Some(b.identity.node_id()), "10.0.0.2:8443".into()).await.unwrap();
+            Arc::clone(&b).dial_peer(Some(c.identity.node_id()), "10.0.0.3:8443".into()).await.unwrap();
+
+            // Chat isn't relayed, so check each link: a to b, and b to both its peers.
+            deliver(&a, &[&b], "a to b").await;
+            deliver(&b, &[&a, &c], "b to a and c").await;
+
+            // Both of b's links count into its transport metrics, whichever side dialed.
+            let metrics = b.metrics.transport.snapshot();
+            assert!(metrics.handshakes_completed >= 2);
+            assert!(metrics.active_connections >= 2);
+            assert!(metrics.frames_sent > 0 && metrics.frames_received > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dials_use_the_channel_the_peer_advertised() {
+        let network = MemoryNetwork::new();
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let a = start_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Noise).await;
+
+        // b's key sits behind an external signer, so it can't run Noise and asks for TLS.
+        let backing = NodeIdentity::generate();
+        let keystore = ExternalSignerKeyStore::new(backing.public_key(), backing.signer());
+        let config = NodeConfig { channel: SecureChannel::Noise, ..NodeConfig::for_test(dirs[1].path(), &network) };
+        let b = Arc::new(SentinelNode::new(config, &keystore).await.unwrap());
+        tokio::spawn(Arc::clone(&b).serve_memory(network.bind("10.0.0.2:8443".parse().unwrap()).unwrap()));
+        assert_eq!(b.advertised_channel(), SecureChannel::Tls);
+
+        let b_id = b.identity.node_id();
+        assert!(Arc::clone(&a).dial_peer(Some(b_id.clone()), "10.0.0.2:8443".into()).await.is_err());
+        a.learn_channel(&b_id, Some(b.advertised_channel().name()));
+        Arc::clone(&a).dial_peer(Some(b_id), "10.0.0.2:8443".into()).await.unwrap();
+        deliver(&a, &[&b], "over tls").await;
+    }
+
+    #[tokio::test]
+    async fn test_connections_over_the_per_ip_limit_are_refused() {
+        let network = MemoryNetwork::new();
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let limits = ConnectionLimits { max_per_ip: Some(1), ..Default::default() };
+        let a = start_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Tls).await;
+        let b = start_configured_node(&network, dirs[1].path(), "10.0.0.2:8443", SecureChannel::Tls, |config| {
+            config.limits = limits;
+        })
+        .await;
+        let c = start_node(&network, dirs[2].path(), "10.0.0.3:8443", SecureChannel::Tls).await;
+
+        // Every dial on the memory network comes from 127.0.0.1, so b takes only the first.
+        Arc::clone(&a).dial_peer(Some(b.identity.node_id()), "10.0.0.2:8443".into()).await.unwrap();
+        assert!(Arc::clone(&c).dial_peer(Some(b.identity.node_id()), "10.0.0.2:8443".into()).await.is_err());
+        let metrics = b.metrics.transport.snapshot();
+        assert_eq!((metrics.connections_refused, metrics.handshakes_completed), (1, 1));
+        deliver(&a, &[&b], "still connected").await;
+    }
+
+    const RELAY: RelayLimits =
+        RelayLimits { max_circuits: 1, max_duration: Duration::from_secs(60), bytes_per_second: 1024 * 1024 };
+
+    /// Polls `done` until it holds, failing the test after five seconds.
+    async fn wait_until(what: &str, done: impl Fn() -> bool) {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !done() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap_or_else(|_| panic!("timed out waiting until {}", what));
+    }
+
+    #[tokio::test]
+    async fn test_peers_introduced_by_rendezvous_fall_back_to_circuit() {
+        let network = MemoryNetwork::new();
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let rendezvous = start_configured_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Tls, |config| {
+            config.relay = Some(RELAY);
+        })
+        .await;
+        let a = start_node(&network, dirs[1].path(), "10.0.0.2:8443", SecureChannel::Tls).await;
+        let b = start_node(&network, dirs[2].path(), "10.0.0.3:8443", SecureChannel::Tls).await;
+        for node in [&a, &b] {
+            Arc::clone(node).dial_rendezvous(rendezvous.identity.node_id(), "10.0.0.1:8443".into()).await.unwrap();
+        }
+        wait_until("both peers reach the rendezvous", || rendezvous.peer_ids.len() == 2).await;
+
+        // Nothing can be punched on an in-memory network, so the peers open a circuit.
+        a.request_punch(&b.identity.node_id()).await.unwrap();
+        deliver(&a, &[&b], "via rendezvous").await;
+        deliver(&b, &[&a], "and back").await;
+        assert!(a.peers.contains_key(&relay_key(&b.identity.node_id())));
+        assert!(b.peers.contains_key(&relay_key(&a.identity.node_id())));
+        assert_eq!(rendezvous.relay.as_ref().unwrap().open_circuits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_relay_enforces_circuit_limits() {
+        let network = MemoryNetwork::new();
+        let dirs: Vec<_> = (0..4).map(|_| tempfile::tempdir().unwrap()).collect();
+        let relay = start_configured_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Tls, |config| {
+            config.relay = Some(RelayLimits { max_duration: Duration::from_millis(500), ..RELAY });
+        })
+        .await;
+        let a = start_node(&network, dirs[1].path(), "10.0.0.2:8443", SecureChannel::Tls).await;
+        let b = start_node(&network, dirs[2].path(), "10.0.0.3:8443", SecureChannel::Tls).await;
+        let c = start_node(&network, dirs[3].path(), "10.0.0.4:8443", SecureChannel::Tls).await;
+        for node in [&a, &b, &c] {
+            Arc::clone(node).dial_peer(Some(relay.identity.node_id()), "10.0.0.1:8443".into()).await.unwrap();
+        }
+        wait_until("every peer reaches the relay", || relay.peer_ids.len() == 3).await;
+
+        let via = "10.0.0.1:8443".to_string();
+        Arc::clone(&a).dial_via_relay(via.clone(), b.identity.node_id()).await.unwrap();
+        deliver(&a, &[&b], "over the circuit").await;
+        // The relay carries one circuit at a time.
+        assert!(Arc::clone(&c).dial_via_relay(via.clone(), b.identity.node_id()).await.is_err());
+        // Peers that don't relay refuse to.
+        assert!(Arc::clone(&a).dial_via_relay(via.replace(".1:", ".3:"), c.identity.node_id()).await.is_err());
+
+        let circuit_to_b = relay_key(&b.identity.node_id());
+        wait_until("the circuit times out", || !a.peers.contains_key(&circuit_to_b)).await;
+        assert_eq!(relay.relay.as_ref().unwrap().open_circuits(), 0);
+        assert!(relay.render_metrics().lines().any(|line| line == "sentinel_relay_circuits_total 1"));
+    }
+
+    #[tokio::test]
+    async fn test_redial_resumes_session_and_greets_early() {
+        let network = MemoryNetwork::new();
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let zero_rtt = |config: &mut NodeConfig| config.zero_rtt = true;
+        let a = start_configured_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Tls, zero_rtt).await;
+        let b = start_configured_node(&network, dirs[1].path(), "10.0.0.2:8443", SecureChannel::Tls, zero_rtt).await;
+
+        Arc::clone(&a).dial_peer(Some(b.identity.node_id()), "10.0.0.2:8443".into()).await.unwrap();
+        // A takes in its session tickets while reading this.
+        deliver(&b, &[&a], "first").await;
+        Arc::clone(&a).dial_peer(Some(b.identity.node_id()), "10.0.0.2:8443".into()).await.unwrap();
+        deliver(&a, &[&b], "second").await;
+
+        let (dialer, listener) = (a.metrics.transport.snapshot(), b.metrics.transport.snapshot());
+        assert_eq!((dialer.handshakes_resumed, listener.handshakes_resumed), (1, 1));
+        assert!(listener.early_data_accepted > 0);
+    }
+
+    #[tokio::test]
+    async fn test_redial_after_rotation_follows_succession() {
+        let network = MemoryNetwork::new();
+        let dirs = [tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap()];
+        let a = start_node(&network, dirs[0].path(), "10.0.0.1:8443", SecureChannel::Tls).await;
+        let b = start_node(&network, dirs[1].path(), "10.0.0.2:8443", SecureChannel::Tls).await;
+        // A knew B before B rotated into its current identity.
+        let old_b = NodeIdentity::generate();
+        record_succession(&b.db, &old_b.succession_to(&b.identity.public_key())).unwrap();
+        a.known_peers.trust("10.0.0.2:8443", &old_b.node_id()).unwrap();
+
+        // Until A holds the record, B's new key looks like an impostor.
+        assert!(Arc::clone(&a).dial_peer(None, "10.0.0.2:8443".into()).await.is_err());
+        assert_eq!(a.known_peers.last_rejected("10.0.0.2:8443").unwrap(), Some(b.identity.node_id()));
+
+        // B announces its lineage to peers it connects to.
+        Arc::clone(&b).dial_peer(Some(a.identity.node_id()), "10.0.0.1:8443".into()).await.unwrap();
+        wait_until("A learns of the rotation", || continues(&a.db, &b.identity.node_id(), &old_b.node_id()).unwrap()).await;
+
+        Arc::clone(&a).dial_peer(None, "10.0.0.2:8443".into()).await.unwrap();
+        assert_eq!(a.known_peers.get("10.0.0.2:8443").unwrap().unwrap().node_id, b.identity.node_id());
+        assert_eq!(a.known_peers.last_rejected("10.0.0.2:8443").unwrap(), None);
+    }
+}
@@ -1,17 +1,26 @@
+use crate::connection::CHANNEL_TXT_KEY;
 use crate::engine::SentinelNode;
-use anyhow::Result;
+use crate::quic::QUIC_TXT_KEY;
+use anyhow::{Context, Result};
 use mdns_sd::{ServiceEvent, ServiceInfo};
+use sentinel_crypto::public_key_from_node_id;
 use std::sync::Arc;
 
 impl SentinelNode {
     pub fn start_discovery(self: &Arc<Self>, port: u16) -> Result<()> {
-        let mdns = self.mdns.clone();
+        let mdns = self.mdns.clone().context("mDNS is off on an in-memory network")?;
         let node_id = self.identity.node_id();
         let service_type = "_sentinel._tcp.local.";
         
         let instance_name = format!("{}.sentinel", node_id);
+        // Peers dial us over the advertised channel. Those that can speak QUIC
+        // prefer it; the TCP port stays the fallback.
+        let mut properties = vec![(CHANNEL_TXT_KEY, self.advertised_channel().name().to_string())];
+        if let Some(quic) = &self.quic {
+            properties.push((QUIC_TXT_KEY, quic.local_addr()?.port().to_string()));
+        }
         let my_service = ServiceInfo::new(
-            service_type, &instance_name, "sentinel-node.local.", "", port, None,
+            service_type, &instance_name, "sentinel-node.local.", "", port, &properties[..],
         )?;
         mdns.register(my_service)?;
 
@@ -27,15 +36,36 @@ impl SentinelNode {
                     if name.contains(&node_id) { continue; }
 
                     let addr_list = info.get_addresses().clone();
-                    let port = info.get_port();
+                    let quic_port = info
+                        .get_property_val_str(QUIC_TXT_KEY)
+                        .and_then(|port| port.parse::<u16>().ok())
+                        .filter(|_| node_inner.quic.is_some() && node_inner.connector.proxy().is_none());
+                    let port = quic_port.unwrap_or(info.get_port());
+
+                    // Instances are advertised as "<node-id>.sentinel"; dial expecting that ID.
+                    let advertised = name
+                        .split('.')
+                        .next()
+                        .filter(|id| public_key_from_node_id(id).is_ok())
+                        .map(str::to_string);
+                    if let Some(node_id) = &advertised {
+                        node_inner.learn_channel(node_id, info.get_property_val_str(CHANNEL_TXT_KEY));
+                    }
 
                     if let Some(ip) = addr_list.iter().next() {
                         let target = format!("{}:{}", ip, port);
-                        println!("mDNS: Discovered Peer at {}", target);
+                        let transport = if quic_port.is_some() { "QUIC" } else { "TCP" };
+                        println!("mDNS: Discovered Peer at {} ({})", target, transport);
+                        node_inner.metrics.peer_discovered();
                         
                         let node_to_dial = Arc::clone(&node_inner);
                         tokio::spawn(async move {
-                            if let Err(e) = node_to_dial.dial_peer(target).await {
+                            let dialed = match quic_port {
+                                Some(_) => Arc::clone(&node_to_dial).dial_quic(advertised, target).await,
+                                None => Arc::clone(&node_to_dial).dial_peer(advertised, target).await,
+                            };
+                            if let Err(e) = dialed {
+                                node_to_dial.metrics.dial_failed();
                                 // Silent error if peer is already connected or offline
                                 eprintln!("Dial error: {}", e);
                             }
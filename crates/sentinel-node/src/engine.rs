@@ -1,66 +1,361 @@
 use anyhow::{Context, Result};
-use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use dashmap::DashMap;
 use tokio::sync::{mpsc, Mutex};
 use uuid::Uuid;
 use std::sync::Arc;
-use futures::{StreamExt, SinkExt};
-use tokio_util::codec::Framed;
 use lru::LruCache;
 
-use sentinel_crypto::NodeIdentity;
+use sentinel_crypto::{
+    node_id_from_certificate, public_key_from_node_id, verify_batch, KeyStore, NodeIdentity, Signature, SignedItem,
+    VerifyingKey,
+};
 use sentinel_protocol::{
-    SentinelCodec, 
+    ProtocolError,
     frame::Frame,
     messages::{SentinelMessage, MessageContent, PeerInfo}
 };
-use sentinel_transport::{SentinelAcceptor, SentinelConnector};
+use sentinel_transport::{
+    is_onion, ClientAuth, ConnectionLimits, MemoryNetwork, QuicEndpoint, SentinelAcceptor, SentinelConnector,
+    Socks5Proxy, TlsCredentials,
+};
+use sentinel_transport::tls_config::load_certs;
 use mdns_sd::ServiceDaemon;
 
+use crate::connection::{SecureChannel, EARLY_DATA_LIMIT};
+use crate::groups::Groups;
+use crate::known_peers::KnownPeers;
+use crate::membership::Admission;
+use crate::metrics::NodeMetrics;
+use crate::relay::{CircuitEnd, Relay, RelayLimits};
+use crate::rendezvous::Rendezvous;
+
+/// Upper bound on frames pulled off a connection and verified together.
+pub const VERIFY_BATCH_SIZE: usize = 256;
+
+/// Time allowed for a TLS, Noise or QUIC handshake to complete.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct NodeConfig {
+    /// Directory holding the identity, certificates and message store.
+    pub data_dir: PathBuf,
+    /// Secure channel used when dialing; inbound connections may use either.
+    pub channel: SecureChannel,
+    /// Admin key that peers' membership certificates must be issued by. `None` admits anyone.
+    pub admin_key: Option<VerifyingKey>,
+    /// CA bundle for mutual TLS. When set, TLS peers in both directions must present a
+    /// certificate chaining to it, and `node.crt` must be issued by it.
+    pub ca_path: Option<PathBuf>,
+    /// UDP address to accept and dial QUIC on. `None` disables QUIC.
+    pub quic_addr: Option<SocketAddr>,
+    /// In-process network to dial instead of TCP, for tests and simulations.
+    /// mDNS is off on such a network.
+    pub network: Option<MemoryNetwork>,
+    /// Checked for every inbound connection before its handshake.
+    pub limits: ConnectionLimits,
+    /// SOCKS5 proxy, e.g. Tor, to dial every TCP peer through. QUIC is not dialed while set.
+    pub proxy: Option<Socks5Proxy>,
+    /// Tor onion service (`<name>.onion:<port>`) forwarding to this node, gossiped to peers.
+    pub onion_address: Option<String>,
+    /// Carry circuits between other peers within these limits. `None` relays nothing.
+    pub relay: Option<RelayLimits>,
+    /// Resume TLS sessions with 0-RTT data: send the greeting early when
+    /// dialing, and accept idempotent messages sent early by peers.
+    pub zero_rtt: bool,
+}
+
+#[cfg(test)]
+impl NodeConfig {
+    /// A TLS node on `network` with everything optional turned off; tests adjust the rest.
+    pub(crate) fn for_test(data_dir: &Path, network: &MemoryNetwork) -> Self {
+        Self {
+            data_dir: data_dir.to_path_buf(),
+            channel: SecureChannel::Tls,
+            admin_key: None,
+            ca_path: None,
+            quic_addr: None,
+            network: Some(network.clone()),
+            limits: ConnectionLimits::default(),
+            proxy: None,
+            onion_address: None,
+            relay: None,
+            zero_rtt: false,
+        }
+    }
+}
+
 pub struct SentinelNode {
     pub identity: NodeIdentity,
     pub acceptor: SentinelAcceptor,
+    pub connector: SentinelConnector,
+    pub quic: Option<QuicEndpoint>,
+    pub metrics: NodeMetrics,
     pub db: sled::Db,
-    pub mdns: ServiceDaemon,
+    pub mdns: Option<ServiceDaemon>,
+    pub network: Option<MemoryNetwork>,
     pub peers: DashMap<String, mpsc::UnboundedSender<SentinelMessage>>,
+    /// Address each authenticated peer is connected at, by node ID.
+    pub peer_ids: DashMap<String, String>,
     pub seen_messages: Mutex<LruCache<Uuid, ()>>,
+    pub known_peers: KnownPeers,
+    pub groups: Groups,
+    pub admission: Admission,
+    pub channel: SecureChannel,
+    /// Channels peers asked to be dialed over, by node ID. Dials to them use it
+    /// instead of `channel`.
+    pub peer_channels: DashMap<String, SecureChannel>,
+    pub ca_path: Option<PathBuf>,
+    pub onion_address: Option<String>,
+    pub zero_rtt: bool,
+    pub cert_path: PathBuf,
+    pub key_path: PathBuf,
+    /// Other acceptors presenting the node's certificate, e.g. for WebSocket, reloaded along with the node's own.
+    pub extra_acceptors: std::sync::Mutex<Vec<SentinelAcceptor>>,
+    /// The peer this node asks for introductions to NATed peers, once dialed.
+    pub rendezvous: std::sync::Mutex<Option<Rendezvous>>,
+    /// Circuits carried for other peers, if this node relays.
+    pub relay: Option<Relay>,
+    /// Our ends of circuits through relays.
+    pub circuits: DashMap<Uuid, CircuitEnd>,
 }
 
 impl SentinelNode {
-    pub async fn new(data_dir: PathBuf) -> Result<Self> {
-        let identity = NodeIdentity::load_or_generate(data_dir.join("identity.key"))?;
+    pub async fn new(config: NodeConfig, keystore: &dyn KeyStore) -> Result<Self> {
+        let data_dir = config.data_dir;
+        std::fs::create_dir_all(&data_dir)
+            .with_context(|| format!("Failed to create {}", data_dir.display()))?;
+        let identity = keystore.load_or_generate()?;
         let db = sled::open(data_dir.join("storage.db"))?;
-        let acceptor = SentinelAcceptor::new(
-            &data_dir.join("node.crt"),
-            &data_dir.join("node.key"),
-            Duration::from_secs(10),
-        )?;
-        let mdns = ServiceDaemon::new().context("Failed to start mDNS")?;
+
+        let cert_path = data_dir.join("node.crt");
+        let key_path = data_dir.join("node.key");
+        let credentials = if config.ca_path.is_none() && Self::cert_is_stale(&cert_path, &identity) {
+            eprintln!(
+                "Ignoring {}: it was issued for another identity. Delete it and {} to silence this.",
+                cert_path.display(),
+                key_path.display()
+            );
+            TlsCredentials::from_identity(&identity)?
+        } else {
+            Self::load_credentials(&identity, &cert_path, &key_path, config.ca_path.as_deref())?
+        };
+        // The acceptor, connector, QUIC endpoint and Noise channels share the transport metrics.
+        let metrics = NodeMetrics::default();
+        let client_auth = match config.ca_path {
+            Some(_) => ClientAuth::Ca,
+            None => ClientAuth::NodeId,
+        };
+        let acceptor = SentinelAcceptor::from_credentials(&credentials, client_auth, HANDSHAKE_TIMEOUT)?;
+        let connector = SentinelConnector::from_credentials(&credentials)?;
+        let acceptor = acceptor.with_metrics(metrics.transport.clone()).with_limits(config.limits);
+        let connector = connector.with_metrics(metrics.transport.clone());
+        let (acceptor, connector) = match config.zero_rtt {
+            true => (acceptor.with_early_data(EARLY_DATA_LIMIT), connector.with_early_data()),
+            false => (acceptor, connector),
+        };
+        let connector = match config.proxy {
+            Some(proxy) => connector.with_proxy(proxy),
+            None => connector,
+        };
+        let quic = config
+            .quic_addr
+            .map(|addr| QuicEndpoint::bind(addr, &acceptor, &connector))
+            .transpose()
+            .context("Failed to start QUIC")?;
+        let mdns = match config.network {
+            Some(_) => None,
+            None => Some(ServiceDaemon::new().context("Failed to start mDNS")?),
+        };
         let seen_messages = Mutex::new(LruCache::new(std::num::NonZeroUsize::new(1000).unwrap()));
+        let known_peers = KnownPeers::open(&db)?;
+        let groups = Groups::open(&db)?;
+        let admission = Admission::load(&data_dir, config.admin_key, &identity)?;
+
+        Ok(Self {
+            identity,
+            acceptor,
+            connector,
+            quic,
+            metrics,
+            db,
+            mdns,
+            network: config.network,
+            peers: DashMap::new(),
+            peer_ids: DashMap::new(),
+            seen_messages,
+            known_peers,
+            groups,
+            admission,
+            channel: config.channel,
+            peer_channels: DashMap::new(),
+            ca_path: config.ca_path,
+            onion_address: config.onion_address,
+            zero_rtt: config.zero_rtt,
+            cert_path,
+            key_path,
+            extra_acceptors: std::sync::Mutex::default(),
+            rendezvous: std::sync::Mutex::default(),
+            relay: config.relay.map(Relay::new),
+            circuits: DashMap::new(),
+        })
+    }
+
+    /// TLS credentials: `node.crt`/`node.key` if the operator supplied both, otherwise
+    /// a certificate derived from the identity and held only in memory, so no private
+    /// key is written to disk. Under `--ca` the files are required, plus the CA bundle.
+    pub(crate) fn load_credentials(
+        identity: &NodeIdentity,
+        cert_path: &Path,
+        key_path: &Path,
+        ca_path: Option<&Path>,
+    ) -> Result<TlsCredentials> {
+        let supplied = cert_path.exists() && key_path.exists();
+        match ca_path {
+            Some(ca_path) => {
+                if !supplied {
+                    anyhow::bail!(
+                        "--ca requires node.crt and node.key issued by that CA in {}",
+                        cert_path.parent().unwrap_or(cert_path).display()
+                    );
+                }
+                TlsCredentials::from_files(cert_path, key_path)?.with_ca(ca_path)
+            }
+            None if supplied && Self::cert_is_stale(cert_path, identity) => {
+                anyhow::bail!("{} was not issued for node {}", cert_path.display(), identity.node_id())
+            }
+            None if supplied => TlsCredentials::from_files(cert_path, key_path),
+            None => TlsCredentials::from_identity(identity).context("Cannot derive a TLS certificate; provide node.crt/node.key"),
+        }
+    }
 
-        Ok(Self { identity, acceptor, db, mdns, peers: DashMap::new(), seen_messages })
+    /// True if `node.crt` was derived from a different identity key (e.g. before a rotation).
+    /// Operator-supplied certificates with non-Ed25519 keys are left alone.
+    pub(crate) fn cert_is_stale(cert_path: &Path, identity: &NodeIdentity) -> bool {
+        let Ok(certs) = load_certs(cert_path) else { return false };
+        match certs.first().map(|cert| node_id_from_certificate(cert)) {
+            Some(Ok(cert_node_id)) => cert_node_id != identity.node_id(),
+            _ => false,
+        }
+    }
+
+    /// Builds a message from this node, signed with its identity.
+    pub fn new_message(&self, content: MessageContent) -> SentinelMessage {
+        let mut msg = SentinelMessage::new(self.identity.node_id(), content);
+        msg.signature = self.identity.sign_detached(&msg.signing_bytes()).to_vec();
+        msg
+    }
+
+    /// Decodes a burst of frames read together. The flag is `false` if the
+    /// stream failed partway, in which case the connection should be dropped.
+    pub fn decode_frames(frames: Vec<Result<Frame, ProtocolError>>) -> (Vec<SentinelMessage>, bool) {
+        let mut messages = Vec::with_capacity(frames.len());
+        for frame in frames {
+            match frame {
+                Ok(frame) => {
+                    if let Ok(msg) = SentinelMessage::from_bytes(frame.payload()) {
+                        messages.push(msg);
+                    }
+                }
+                Err(_) => return (messages, false),
+            }
+        }
+        (messages, true)
+    }
+
+    /// Verifies a burst of inbound messages in one batch and dispatches the valid ones.
+    /// Messages already seen are skipped before paying for verification.
+    pub async fn handle_incoming_batch(self: &Arc<Self>, msgs: Vec<SentinelMessage>, addr: &str) {
+        let received = msgs.len();
+        let msgs: Vec<SentinelMessage> = {
+            let seen = self.seen_messages.lock().await;
+            msgs.into_iter().filter(|msg| !seen.contains(&msg.id)).collect()
+        };
+        self.metrics.duplicates_dropped(received - msgs.len());
+
+        for (valid, msg) in Self::verify_messages(&msgs).into_iter().zip(msgs) {
+            if !valid {
+                self.metrics.invalid_signature();
+                eprintln!("Dropped message {} from {}: invalid signature", msg.id, addr);
+                continue;
+            }
+            let _ = self.clone().handle_incoming_message(msg, addr.to_string()).await;
+        }
+    }
+
+    fn verify_messages(msgs: &[SentinelMessage]) -> Vec<bool> {
+        let signing_bytes: Vec<Vec<u8>> = msgs.iter().map(SentinelMessage::signing_bytes).collect();
+
+        // Messages with a malformed sender or signature can't join the batch at all.
+        let mut results = vec![false; msgs.len()];
+        let mut items = Vec::with_capacity(msgs.len());
+        let mut positions = Vec::with_capacity(msgs.len());
+        for (i, msg) in msgs.iter().enumerate() {
+            let Ok(public_key) = public_key_from_node_id(&msg.sender) else { continue };
+            let Ok(signature) = Signature::from_slice(&msg.signature) else { continue };
+            items.push(SignedItem { message: &signing_bytes[i], public_key, signature });
+            positions.push(i);
+        }
+
+        for (i, valid) in positions.into_iter().zip(verify_batch(&items)) {
+            results[i] = valid;
+        }
+        results
     }
 
     pub async fn handle_incoming_message(self: Arc<Self>, msg: SentinelMessage, addr: String) -> Result<()> {
         {
             let mut seen = self.seen_messages.lock().await;
-            if seen.contains(&msg.id) { return Ok(()); }
+            if seen.contains(&msg.id) {
+                self.metrics.duplicates_dropped(1);
+                return Ok(());
+            }
             seen.put(msg.id, ());
         }
+        self.metrics.message_received();
 
         match msg.content {
             MessageContent::Chat(ref text) => {
-                println!("[{}] (Chat): {}", msg.sender, text);
+                println!("[{}] (Chat): {}", self.describe_sender(&msg.sender), text);
                 self.persist_message(&msg)?;
             }
             MessageContent::PeerDiscovery(ref new_peers) => {
                 for peer in new_peers {
                     if peer.node_id != self.identity.node_id() {
                         println!("Gossip discovery: {} at {}", peer.node_name, peer.address);
+                        self.learn_channel(&peer.node_id, peer.channel.as_deref());
+                        self.dial_onion_peer(peer);
                     }
                 }
             }
+            MessageContent::KeySuccession(ref record) => {
+                if let Err(e) = self.handle_succession(&msg, record, &addr) {
+                    eprintln!("Rejected succession record from {}: {}", addr, e);
+                }
+            }
+            MessageContent::GroupMembership { .. }
+            | MessageContent::GroupKey { .. }
+            | MessageContent::GroupMessage { .. } => {
+                if let Err(e) = self.handle_group(&msg, &addr).await {
+                    eprintln!("Group message from {} rejected: {}", addr, e);
+                }
+            }
+            MessageContent::PunchRequest { ref target } => {
+                self.introduce(&msg.sender, &addr, target).await;
+            }
+            MessageContent::PunchOffer { ref peer, ref address } => {
+                self.accept_punch_offer(&msg.sender, peer, address);
+            }
+            MessageContent::CircuitOpen { circuit, ref target } => {
+                self.handle_circuit_open(&msg, circuit, target, &addr);
+            }
+            MessageContent::CircuitData { circuit, ref data } => {
+                self.handle_circuit_data(&msg, circuit, data, &addr).await;
+            }
+            MessageContent::CircuitClose { circuit, ref reason } => {
+                self.handle_circuit_close(&msg, circuit, reason, &addr);
+            }
             MessageContent::Ping => {
                 let _ = self.send_to_peer(&addr, MessageContent::Pong).await;
             }
@@ -69,58 +364,31 @@ impl SentinelNode {
         Ok(())
     }
 
-    pub async fn dial_peer(self: Arc<Self>, addr: String) -> Result<()> {
-        let connector = SentinelConnector::new(&PathBuf::from("./node.crt"))?;
-        let stream = tokio::net::TcpStream::connect(&addr).await?;
-        let tls = connector.connect("sentinel-node.local", stream).await?;
-        let (mut sink, mut stream) = Framed::new(tls, SentinelCodec::new()).split();
-
-        let handshake = SentinelMessage::new(self.identity.node_id(), MessageContent::Chat("v2-dial".into()));
-        sink.send(Frame::new(1, 0, handshake.to_bytes().into())?).await?;
-
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        self.peers.insert(addr.clone(), tx);
-
-        let addr_out = addr.clone();
-        tokio::spawn(async move {
-            while let Some(msg) = rx.recv().await {
-                if let Ok(f) = Frame::new(1, 0, msg.to_bytes().into()) {
-                    if let Err(e) = sink.send(f).await {
-                        eprintln!("Write error to {}: {}", addr_out, e);
-                        break; 
-                    }
-                }
-            }
-        });
-
-        let node_inner = Arc::clone(&self);
-        let addr_in = addr.clone();
-        tokio::spawn(async move {
-            while let Some(Ok(frame)) = stream.next().await {
-                if let Ok(msg) = SentinelMessage::from_bytes(frame.payload()) {
-                    let _ = node_inner.clone().handle_incoming_message(msg, addr_in.clone()).await;
-                }
-            }
-            node_inner.peers.remove(&addr_in);
-            println!("Connection closed: {}", addr_in);
-        });
-        Ok(())
-    }
-
     pub async fn start_gossip_service(self: Arc<Self>) {
         let mut interval = tokio::time::interval(Duration::from_secs(30));
         loop {
             interval.tick().await;
-            let peer_list: Vec<PeerInfo> = self.peers.iter().filter_map(|entry| {
-                entry.key().parse().ok().map(|addr| PeerInfo {
+            let mut peer_list: Vec<PeerInfo> = self.peers.iter().filter_map(|entry| {
+                entry.key().parse::<SocketAddr>().ok().map(|addr| PeerInfo {
                     node_id: "unknown".into(),
-                    address: addr,
+                    address: addr.to_string(),
                     node_name: "mesh-node".into(),
                     last_seen: 0,
+                    channel: None,
                 })
             }).collect();
+            if let Some(onion) = &self.onion_address {
+                peer_list.push(PeerInfo {
+                    node_id: self.identity.node_id(),
+                    address: onion.clone(),
+                    node_name: "mesh-node".into(),
+                    last_seen: 0,
+                    channel: Some(self.advertised_channel().name().into()),
+                });
+            }
 
             if !peer_list.is_empty() {
+                self.metrics.gossip_round();
                 let msg = MessageContent::PeerDiscovery(peer_list);
                 for entry in self.peers.iter() {
                     let _ = self.send_to_peer(entry.key(), msg.clone()).await;
@@ -129,13 +397,43 @@ impl SentinelNode {
         }
     }
 
+    /// Dials a gossiped onion service through the proxy, unless there is no proxy
+    /// or the peer is already connected. Only peers that name their node ID are
+    /// dialed, and the handshake must prove it.
+    fn dial_onion_peer(self: &Arc<Self>, peer: &PeerInfo) {
+        if !is_onion(&peer.address)
+            || self.connector.proxy().is_none()
+            || public_key_from_node_id(&peer.node_id).is_err()
+            || self.peers.contains_key(&peer.address)
+        {
+            return;
+        }
+        let node = Arc::clone(self);
+        let (node_id, address) = (peer.node_id.clone(), peer.address.clone());
+        tokio::spawn(async move {
+            if let Err(e) = Arc::clone(&node).dial_peer(Some(node_id), address).await {
+                node.metrics.dial_failed();
+                eprintln!("Dial error: {}", e);
+            }
+        });
+    }
+
     pub async fn send_to_peer(&self, addr: &str, content: MessageContent) -> Result<()> {
         if let Some(tx) = self.peers.get(addr) {
-            tx.send(SentinelMessage::new(self.identity.node_id(), content))?;
+            tx.send(self.new_message(content))?;
         }
         Ok(())
     }
 
+    /// Sends a message of our own to every connected peer. It is marked seen
+    /// first so copies flooded back to us are ignored.
+    pub async fn broadcast(&self, msg: SentinelMessage) {
+        self.seen_messages.lock().await.put(msg.id, ());
+        for peer in self.peers.iter() {
+            let _ = peer.value().send(msg.clone());
+        }
+    }
+
     pub fn persist_message(&self, msg: &SentinelMessage) -> Result<()> {
         let tree = self.db.open_tree("messages")?;
         tree.insert(format!("{}:{}", msg.timestamp, msg.sender), msg.to_bytes())?;
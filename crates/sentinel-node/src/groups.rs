@@ -0,0 +1,670 @@
+use crate::engine::SentinelNode;
+use anyhow::{Context, Result};
+use sentinel_crypto::{public_key_from_node_id, seal, SenderKey, SenderKeyReceiver};
+use sentinel_protocol::messages::{MessageContent, SentinelMessage};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const GROUPS_TREE: &str = "groups";
+/// Keys that arrived ahead of the roster that makes them usable, per group.
+const MAX_PENDING_KEYS: usize = 256;
+/// Keys held for all groups together, so made-up group names can't grow the map.
+const MAX_PENDING_TOTAL: usize = 4096;
+/// How long an early key waits for its roster before it is dropped.
+const PENDING_KEY_TTL: Duration = Duration::from_secs(600);
+
+/// This node's view of a group channel it belongs to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupState {
+    /// Node that created the group; only it may change the roster.
+    pub owner: String,
+    pub revision: u64,
+    pub epoch: u64,
+    pub members: Vec<String>,
+    /// Our own sending chain for `epoch`.
+    sender_key: Vec<u8>,
+    /// Other members' chains for `epoch`, by node ID.
+    receivers: BTreeMap<String, Vec<u8>>,
+}
+
+impl GroupState {
+    fn is_member(&self, node_id: &str) -> bool {
+        self.members.iter().any(|member| member == node_id)
+    }
+}
+
+struct PendingKey {
+    sender: String,
+    epoch: u64,
+    sealed: Vec<u8>,
+}
+
+/// Group channels this node is a member of, persisted in sled. Chain keys are
+/// stored as plainly as `identity.key`.
+pub struct Groups {
+    tree: sled::Tree,
+    /// Serialises read-modify-write of group state.
+    lock: Mutex<()>,
+    /// Early keys by group, with the time each arrived.
+    pending: Mutex<HashMap<String, Vec<(Instant, PendingKey)>>>,
+}
+
+impl Groups {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            tree: db.open_tree(GROUPS_TREE)?,
+            lock: Mutex::new(()),
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn get(&self, group: &str) -> Result<Option<GroupState>> {
+        self.tree
+            .get(group)?
+            .map(|bytes| bincode::deserialize(&bytes).context("Corrupt group entry"))
+            .transpose()
+    }
+
+    pub fn list(&self) -> Result<Vec<(String, GroupState)>> {
+        self.tree
+            .iter()
+            .map(|entry| {
+                let (name, bytes) = entry?;
+                Ok((String::from_utf8(name.to_vec())?, bincode::deserialize(&bytes)?))
+            })
+            .collect()
+    }
+
+    /// Applies `f` to the stored state of `group` under the lock. `f` returns the
+    /// new state, or `None` to delete the group.
+    fn update<T>(
+        &self,
+        group: &str,
+        f: impl FnOnce(Option<GroupState>) -> Result<(Option<GroupState>, T)>,
+    ) -> Result<T> {
+        let _guard = self.lock.lock().expect("groups lock poisoned");
+        let (state, out) = f(self.get(group)?)?;
+        match state {
+            Some(state) => self.tree.insert(group, bincode::serialize(&state)?)?,
+            None => self.tree.remove(group)?,
+        };
+        Ok(out)
+    }
+
+    fn stash(&self, group: &str, key: PendingKey) {
+        self.stash_at(group, key, Instant::now());
+    }
+
+    /// Holds `key` until its roster arrives, first dropping keys that waited too long.
+    /// Past the per-group or overall limit, new keys are dropped.
+    fn stash_at(&self, group: &str, key: PendingKey, now: Instant) {
+        let mut pending = self.pending.lock().expect("pending keys lock poisoned");
+        pending.retain(|_, keys| {
+            keys.retain(|(received, _)| now.duration_since(*received) < PENDING_KEY_TTL);
+            !keys.is_empty()
+        });
+        if pending.values().map(Vec::len).sum::<usize>() >= MAX_PENDING_TOTAL {
+            return;
+        }
+        let keys = pending.entry(group.to_string()).or_default();
+        if keys.len() < MAX_PENDING_KEYS {
+            keys.push((now, key));
+        }
+    }
+
+    fn take_pending(&self, group: &str) -> Vec<PendingKey> {
+        let keys = self.pending.lock().expect("pending keys lock poisoned").remove(group).unwrap_or_default();
+        keys.into_iter()
+            .filter(|(received, _)| received.elapsed() < PENDING_KEY_TTL)
+            .map(|(_, key)| key)
+            .collect()
+    }
+}
+
+fn key_aad(group: &str, epoch: u64, sender: &str, recipient: &str) -> Vec<u8> {
+    bincode::serialize(&("sentinel-group-key", group, epoch, sender, recipient)).expect("Serialization failed")
+}
+
+fn message_aad(group: &str, epoch: u64, sender: &str) -> Vec<u8> {
+    bincode::serialize(&("sentinel-group-message", group, epoch, sender)).expect("Serialization failed")
+}
+
+/// A group's ID on the wire and in storage: its name qualified by the owner's node ID.
+/// Rosters are only taken from the owner the ID names, so nobody else can claim it.
+pub fn group_id(name: &str, owner: &str) -> String {
+    format!("{}@{}", name, owner)
+}
+
+/// The owner a group ID names, if it is well formed.
+fn group_owner(group: &str) -> Option<&str> {
+    group.rsplit_once('@').map(|(_, owner)| owner)
+}
+
+/// The group's name with its owner shortened, for display.
+pub fn group_label(group: &str) -> String {
+    match group.rsplit_once('@') {
+        Some((name, owner)) => format!("{}@{}", name, &owner[..owner.len().min(8)]),
+        None => group.to_string(),
+    }
+}
+
+impl Groups {
+    /// Finds a group by ID, or by name if only one group of ours has it.
+    pub fn resolve(&self, name_or_id: &str) -> Result<String> {
+        let matches: Vec<String> = self
+            .list()?
+            .into_iter()
+            .map(|(group, _)| group)
+            .filter(|group| group == name_or_id || group.rsplit_once('@').is_some_and(|(name, _)| name == name_or_id))
+            .collect();
+        match matches.as_slice() {
+            [group] => Ok(group.clone()),
+            [] => anyhow::bail!("No group named {}", name_or_id),
+            _ => anyhow::bail!("Several groups are named {}; use one of: {}", name_or_id, matches.join(", ")),
+        }
+    }
+}
+
+impl SentinelNode {
+    /// Creates a group owned by this node and returns its ID.
+    pub fn create_group(&self, name: &str) -> Result<String> {
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            anyhow::bail!("Group names must be a single word");
+        }
+        let me = self.identity.node_id();
+        let group = group_id(name, &me);
+        self.groups.update(&group, |state| {
+            if state.is_some() {
+                anyhow::bail!("Group {} already exists", name);
+            }
+            let state = GroupState {
+                owner: me.clone(),
+                revision: 1,
+                epoch: 0,
+                members: vec![me],
+                sender_key: SenderKey::generate().to_bytes().to_vec(),
+                receivers: BTreeMap::new(),
+            };
+            Ok((Some(state), ()))
+        })?;
+        Ok(group)
+    }
+
+    pub async fn add_group_member(&self, group: &str, node_id: &str) -> Result<()> {
+        public_key_from_node_id(node_id)?;
+        let me = self.identity.node_id();
+        let state = self.groups.update(group, |state| {
+            let mut state = state.with_context(|| format!("No group named {}", group))?;
+            if state.owner != me {
+                anyhow::bail!("Only the owner of {} can change its members", group);
+            }
+            if state.is_member(node_id) {
+                anyhow::bail!("{} is already a member of {}", node_id, group);
+            }
+            state.members.push(node_id.to_string());
+            state.revision += 1;
+            Ok((Some(state.clone()), state))
+        })?;
+
+        self.publish_roster(group, &state).await;
+        self.send_sender_key(group, &state, &[node_id.to_string()]).await
+    }
+
+    /// Removes a member and starts a new epoch, so nothing sent from now on is
+    /// readable with keys the removed member holds.
+    pub async fn remove_group_member(&self, group: &str, node_id: &str) -> Result<()> {
+        let me = self.identity.node_id();
+        let state = self.groups.update(group, |state| {
+            let mut state = state.with_context(|| format!("No group named {}", group))?;
+            if state.owner != me {
+                anyhow::bail!("Only the owner of {} can change its members", group);
+            }
+            if node_id == me {
+                anyhow::bail!("The owner cannot leave {}", group);
+            }
+            if !state.is_member(node_id) {
+                anyhow::bail!("{} is not a member of {}", node_id, group);
+            }
+            state.members.retain(|member| member != node_id);
+            state.revision += 1;
+            state.epoch += 1;
+            state.sender_key = SenderKey::generate().to_bytes().to_vec();
+            state.receivers.clear();
+            Ok((Some(state.clone()), state))
+        })?;
+
+        self.publish_roster(group, &state).await;
+        let others: Vec<String> = state.members.iter().filter(|m| **m != me).cloned().collect();
+        self.send_sender_key(group, &state, &others).await
+    }
+
+    pub async fn send_group_message(&self, group: &str, text: &str) -> Result<()> {
+        let content = self.seal_group_message(group, text)?;
+        self.broadcast(self.new_message(content)).await;
+        Ok(())
+    }
+
+    /// Encrypts `text` with the next key in our chain for `group`.
+    fn seal_group_message(&self, group: &str, text: &str) -> Result<MessageContent> {
+        let me = self.identity.node_id();
+        self.groups.update(group, |state| {
+            let mut state = state.with_context(|| format!("Not a member of {}", group))?;
+            let mut chain = SenderKey::from_bytes(&state.sender_key)?;
+            let (iteration, ciphertext) =
+                chain.encrypt(text.as_bytes(), &message_aad(group, state.epoch, &me))?;
+            state.sender_key = chain.to_bytes().to_vec();
+
+            let content = MessageContent::GroupMessage {
+                group: group.to_string(),
+                epoch: state.epoch,
+                iteration,
+                ciphertext,
+            };
+            Ok((Some(state), content))
+        })
+    }
+
+    async fn publish_roster(&self, group: &str, state: &GroupState) {
+        let content = MessageContent::GroupMembership {
+            group: group.to_string(),
+            revision: state.revision,
+            epoch: state.epoch,
+            members: state.members.clone(),
+        };
+        self.broadcast(self.new_message(content)).await;
+    }
+
+    /// Seals our chain for the current epoch to each of `recipients`.
+    async fn send_sender_key(&self, group: &str, state: &GroupState, recipients: &[String]) -> Result<()> {
+        let me = self.identity.node_id();
+        for recipient in recipients {
+            let public_key = public_key_from_node_id(recipient)?;
+            let sealed = seal(&public_key, &key_aad(group, state.epoch, &me, recipient), &state.sender_key)?;
+            let content = MessageContent::GroupKey {
+                group: group.to_string(),
+                epoch: state.epoch,
+                recipient: recipient.clone(),
+                sealed,
+            };
+            self.broadcast(self.new_message(content)).await;
+        }
+        Ok(())
+    }
+
+    /// Processes a group message addressed to the mesh and relays it onward.
+    /// Nodes outside the group only relay.
+    pub async fn handle_group(&self, msg: &SentinelMessage, from_addr: &str) -> Result<()> {
+        for peer in self.peers.iter() {
+            if peer.key() != from_addr {
+                let _ = peer.value().send(msg.clone());
+            }
+        }
+
+        match &msg.content {
+            MessageContent::GroupMembership { group, revision, epoch, members } => {
+                self.apply_roster(msg, group, *revision, *epoch, members).await
+            }
+            MessageContent::GroupKey { group, epoch, recipient, sealed } => {
+                if *recipient != self.identity.node_id() {
+                    return Ok(());
+                }
+                let key = PendingKey { sender: msg.sender.clone(), epoch: *epoch, sealed: sealed.clone() };
+                self.accept_sender_key(group, key, true)
+            }
+            MessageContent::GroupMessage { group, epoch, iteration, ciphertext } => {
+                if let Some(text) = self.read_group_message(msg, group, *epoch, *iteration, ciphertext)? {
+                    println!("[{}] {}: {}", group_label(group), self.describe_sender(&msg.sender), text);
+                }
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    async fn apply_roster(
+        &self,
+        msg: &SentinelMessage,
+        group: &str,
+        revision: u64,
+        epoch: u64,
+        members: &[String],
+    ) -> Result<()> {
+        let me = self.identity.node_id();
+        let listed = members.contains(&me);
+
+        let outcome = self.groups.update(group, |state| {
+            let (previous, owner) = match state {
+                // Groups we were never part of are only relayed, as are rosters
+                // from anyone but the owner the group ID names.
+                None if !listed || group_owner(group) != Some(msg.sender.as_str()) => return Ok((None, None)),
+                None => (None, msg.sender.clone()),
+                Some(state)
+                    if msg.sender != state.owner || revision <= state.revision || epoch < state.epoch =>
+                {
+                    return Ok((Some(state), None));
+                }
+                Some(_) if !listed => {
+                    println!("[{}] You were removed from the group", group_label(group));
+                    return Ok((None, None));
+                }
+                Some(state) => {
+                    let owner = state.owner.clone();
+                    (Some(state), owner)
+                }
+            };
+
+            // Our chain survives additions; joining or a new epoch starts a fresh one,
+            // which every other member needs. Otherwise only newcomers do.
+            let (mut next, old_members) = match previous {
+                Some(state) if state.epoch == epoch => {
+                    let old_members = state.members.clone();
+                    (state, old_members)
+                }
+                _ => {
+                    let state = GroupState {
+                        owner,
+                        revision,
+                        epoch,
+                        members: Vec::new(),
+                        sender_key: SenderKey::generate().to_bytes().to_vec(),
+                        receivers: BTreeMap::new(),
+                    };
+                    (state, Vec::new())
+                }
+            };
+            next.revision = revision;
+            next.members = members.to_vec();
+            next.receivers.retain(|member, _| members.contains(member));
+
+            let recipients: Vec<String> = members
+                .iter()
+                .filter(|m| **m != me && !old_members.contains(m))
+                .cloned()
+                .collect();
+            Ok((Some(next.clone()), Some((next, recipients))))
+        })?;
+
+        let Some((state, recipients)) = outcome else { return Ok(()) };
+        println!("[{}] Members (epoch {}): {}", group_label(group), state.epoch, state.members.join(", "));
+        self.send_sender_key(group, &state, &recipients).await?;
+
+        for key in self.groups.take_pending(group) {
+            if let Err(e) = self.accept_sender_key(group, key, false) {
+                eprintln!("[{}] Dropped group key: {}", group_label(group), e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Installs a member's chain. Keys for a later epoch, or from a sender the
+    /// roster doesn't list yet, wait for the roster when `stash_early` is set.
+    fn accept_sender_key(&self, group: &str, key: PendingKey, stash_early: bool) -> Result<()> {
+        let me = self.identity.node_id();
+        self.groups.update(group, |state| {
+            let Some(mut state) = state else {
+                self.groups.stash(group, key);
+                return Ok((None, ()));
+            };
+            if key.epoch > state.epoch {
+                self.groups.stash(group, key);
+                return Ok((Some(state), ()));
+            }
+            if key.epoch < state.epoch || state.receivers.contains_key(&key.sender) {
+                return Ok((Some(state), ()));
+            }
+            if !state.is_member(&key.sender) {
+                if stash_early {
+                    self.groups.stash(group, key);
+                    return Ok((Some(state), ()));
+                }
+                anyhow::bail!("{} sent a key for {} but is not a member", key.sender, group);
+            }
+
+            let chain = self
+                .identity
+                .open_sealed(&key.sealed, &key_aad(group, key.epoch, &key.sender, &me))?;
+            let receiver = SenderKeyReceiver::new(SenderKey::from_bytes(&chain)?);
+            state.receivers.insert(key.sender, receiver.to_bytes().to_vec());
+            Ok((Some(state), ()))
+        })
+    }
+
+    /// Decrypts a group message, or returns `None` if we hold no key for it.
+    fn read_group_message(
+        &self,
+        msg: &SentinelMessage,
+        group: &str,
+        epoch: u64,
+        iteration: u32,
+        ciphertext: &[u8],
+    ) -> Result<Option<String>> {
+        self.groups.update(group, |state| {
+            let Some(mut state) = state else { return Ok((None, None)) };
+            if epoch != state.epoch {
+                return Ok((Some(state), None));
+            }
+            let Some(bytes) = state.receivers.get(&msg.sender) else {
+                eprintln!("[{}] No key yet for {}; message unreadable", group_label(group), msg.sender);
+                return Ok((Some(state), None));
+            };
+
+            let mut receiver = SenderKeyReceiver::from_bytes(bytes)?;
+            let plaintext =
+                receiver.decrypt(iteration, ciphertext, &message_aad(group, epoch, &msg.sender))?;
+            state.receivers.insert(msg.sender.clone(), receiver.to_bytes().to_vec());
+            Ok((Some(state), Some(String::from_utf8_lossy(&plaintext).into_owned())))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NodeConfig;
+    use sentinel_crypto::MemoryKeyStore;
+    use sentinel_transport::MemoryNetwork;
+    use tokio::sync::mpsc;
+
+    /// Stand-in peer that captures everything a node sends.
+    const OUTBOX: &str = "outbox";
+
+    struct TestNode {
+        node: SentinelNode,
+        outbox: mpsc::UnboundedReceiver<SentinelMessage>,
+        _dir: tempfile::TempDir,
+    }
+
+    async fn start() -> TestNode {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap();
+        let (tx, outbox) = mpsc::unbounded_channel();
+        node.peers.insert(OUTBOX.into(), tx);
+        TestNode { node, outbox, _dir: dir }
+    }
+
+    impl TestNode {
+        fn id(&self) -> String {
+            self.node.identity.node_id()
+        }
+
+        fn sent(&mut self) -> Vec<SentinelMessage> {
+            std::iter::from_fn(|| self.outbox.try_recv().ok()).collect()
+        }
+
+        async fn receive(&self, messages: &[SentinelMessage]) {
+            for msg in messages {
+                self.node.handle_group(msg, OUTBOX).await.unwrap();
+            }
+        }
+
+        fn read(&self, msg: &SentinelMessage) -> Option<String> {
+            let MessageContent::GroupMessage { group, epoch, iteration, ciphertext } = &msg.content else {
+                panic!("not a group message");
+            };
+            self.node.read_group_message(msg, group, *epoch, *iteration, ciphertext).unwrap()
+        }
+
+        fn seal(&self, group: &str, text: &str) -> SentinelMessage {
+            self.node.new_message(self.node.seal_group_message(group, text).unwrap())
+        }
+    }
+
+    /// Delivers everything the nodes send to every other node until they go quiet.
+    async fn settle(nodes: &mut [&mut TestNode]) {
+        loop {
+            let batches: Vec<_> = nodes.iter_mut().map(|node| node.sent()).collect();
+            if batches.iter().all(Vec::is_empty) {
+                return;
+            }
+            for (from, batch) in batches.iter().enumerate() {
+                for (to, node) in nodes.iter().enumerate() {
+                    if to != from {
+                        node.receive(batch).await;
+                    }
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_removed_member_cannot_read_after_rekey() {
+        let (mut a, mut b, mut c) = (start().await, start().await, start().await);
+        let team = a.node.create_group("team").unwrap();
+        a.node.add_group_member(&team, &b.id()).await.unwrap();
+        a.node.add_group_member(&team, &c.id()).await.unwrap();
+        assert!(b.node.add_group_member(&team, &c.id()).await.is_err());
+        settle(&mut [&mut a, &mut b, &mut c]).await;
+
+        for node in [&a, &b, &c] {
+            let state = node.node.groups.get(&team).unwrap().unwrap();
+            assert_eq!((state.revision, state.epoch, state.members.len()), (3, 0, 3));
+            assert_eq!(state.receivers.len(), 2);
+        }
+        let hello = a.seal(&team, "hello");
+        assert_eq!(b.read(&hello).as_deref(), Some("hello"));
+        assert_eq!(c.read(&hello).as_deref(), Some("hello"));
+        assert_eq!(a.read(&c.seal(&team, "hi")).as_deref(), Some("hi"));
+
+        let kept = c.node.groups.get(&team).unwrap().unwrap();
+        a.node.remove_group_member(&team, &c.id()).await.unwrap();
+        settle(&mut [&mut a, &mut b, &mut c]).await;
+
+        assert!(c.node.groups.get(&team).unwrap().is_none());
+        for node in [&a, &b] {
+            let state = node.node.groups.get(&team).unwrap().unwrap();
+            assert_eq!((state.epoch, state.members.len()), (1, 2));
+        }
+        let secret = a.seal(&team, "secret");
+        assert_eq!(b.read(&secret).as_deref(), Some("secret"));
+        assert_eq!(a.read(&b.seal(&team, "ack")).as_deref(), Some("ack"));
+        assert_eq!(c.read(&secret), None);
+
+        // Even with the keys it held before removal, the new epoch's chain is out of reach.
+        let MessageContent::GroupMessage { iteration, ciphertext, .. } = &secret.content else { unreachable!() };
+        let mut old_chain = SenderKeyReceiver::from_bytes(&kept.receivers[&a.id()]).unwrap();
+        for epoch in [0, 1] {
+            assert!(old_chain.decrypt(*iteration, ciphertext, &message_aad(&team, epoch, &a.id())).is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_arriving_before_the_roster_are_kept() {
+        let (mut a, mut b, mut c) = (start().await, start().await, start().await);
+        let team = a.node.create_group("team").unwrap();
+        a.node.add_group_member(&team, &c.id()).await.unwrap();
+        settle(&mut [&mut a, &mut c]).await;
+
+        // b hears of its addition and keys c before c sees the new roster.
+        a.node.add_group_member(&team, &b.id()).await.unwrap();
+        let from_a = a.sent();
+        b.receive(&from_a).await;
+        let from_b = b.sent();
+        c.receive(&from_b).await;
+        assert!(!c.node.groups.get(&team).unwrap().unwrap().receivers.contains_key(&b.id()));
+
+        c.receive(&from_a).await;
+        assert!(c.node.groups.get(&team).unwrap().unwrap().receivers.contains_key(&b.id()));
+        settle(&mut [&mut a, &mut b, &mut c]).await;
+        assert_eq!(c.read(&b.seal(&team, "early")).as_deref(), Some("early"));
+        assert_eq!(b.read(&c.seal(&team, "welcome")).as_deref(), Some("welcome"));
+    }
+
+    #[tokio::test]
+    async fn test_keys_from_non_members_are_dropped_after_a_newer_roster() {
+        let (mut a, b, c) = (start().await, start().await, start().await);
+        let team = a.node.create_group("team").unwrap();
+        a.node.add_group_member(&team, &b.id()).await.unwrap();
+        b.receive(&a.sent()).await;
+
+        // c's key waits in case a roster adding c is still on its way.
+        let stranger = c.node.new_message(MessageContent::GroupKey {
+            group: team.clone(),
+            epoch: 0,
+            recipient: b.id(),
+            sealed: seal(&b.node.identity.public_key(), &key_aad(&team, 0, &c.id(), &b.id()), &[0; 32]).unwrap(),
+        });
+        b.receive(std::slice::from_ref(&stranger)).await;
+        assert_eq!(b.node.groups.pending.lock().unwrap()[&team].len(), 1);
+
+        // A newer roster that still leaves c out drops its key.
+        let d = start().await;
+        a.node.add_group_member(&team, &d.id()).await.unwrap();
+        b.receive(&a.sent()).await;
+        assert!(b.node.groups.pending.lock().unwrap().get(&team).is_none());
+        assert!(!b.node.groups.get(&team).unwrap().unwrap().receivers.contains_key(&c.id()));
+    }
+
+    #[tokio::test]
+    async fn test_pending_keys_are_capped_and_expire() {
+        let node = start().await;
+        let groups = &node.node.groups;
+        let key = || PendingKey { sender: "sender".into(), epoch: 0, sealed: Vec::new() };
+        let start = Instant::now();
+
+        for i in 0..MAX_PENDING_TOTAL + 1 {
+            groups.stash_at(&format!("group-{}", i), key(), start);
+        }
+        let pending = groups.pending.lock().unwrap();
+        assert_eq!(pending.values().map(Vec::len).sum::<usize>(), MAX_PENDING_TOTAL);
+        assert!(!pending.contains_key(&format!("group-{}", MAX_PENDING_TOTAL)));
+        drop(pending);
+
+        // Once the early keys have waited out their time, they make room again.
+        groups.stash_at("late", key(), start + PENDING_KEY_TTL);
+        let pending = groups.pending.lock().unwrap();
+        assert_eq!(pending.keys().collect::<Vec<_>>(), ["late"]);
+    }
+
+    #[tokio::test]
+    async fn test_only_the_named_owner_can_claim_a_group() {
+        let (mut a, mut b, mut c) = (start().await, start().await, start().await);
+        let team = group_id("team", &a.id());
+
+        // c gets to b first with a roster for a's group, signed by c.
+        let squat = c.node.new_message(MessageContent::GroupMembership {
+            group: team.clone(),
+            revision: 1,
+            epoch: 0,
+            members: vec![c.id(), b.id()],
+        });
+        b.receive(std::slice::from_ref(&squat)).await;
+        assert!(b.node.groups.get(&team).unwrap().is_none());
+
+        assert_eq!(a.node.create_group("team").unwrap(), team);
+        a.node.add_group_member(&team, &b.id()).await.unwrap();
+        settle(&mut [&mut a, &mut b]).await;
+        assert_eq!(b.node.groups.get(&team).unwrap().unwrap().owner, a.id());
+
+        // c's own group of that name is a different group.
+        let other = c.node.create_group("team").unwrap();
+        c.node.add_group_member(&other, &b.id()).await.unwrap();
+        settle(&mut [&mut b, &mut c]).await;
+        assert_eq!(b.node.groups.get(&other).unwrap().unwrap().owner, c.id());
+        assert!(b.node.groups.resolve("team").is_err());
+        assert_eq!(b.node.groups.resolve(&team).unwrap(), team);
+    }
+}
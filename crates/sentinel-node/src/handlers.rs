@@ -1,22 +1,60 @@
 use crate::engine::SentinelNode;
+use crate::groups::group_label;
+use crate::known_peers::PeersCommand;
 use anyhow::Result;
 use std::sync::Arc;
 use tokio::io::{self, AsyncBufReadExt, BufReader};
-use sentinel_protocol::messages::{SentinelMessage, MessageContent};
-use uuid::Uuid;
-use std::time::{SystemTime, UNIX_EPOCH};
+use sentinel_protocol::messages::MessageContent;
+
+const GROUP_USAGE: &str = "Usage: /group create|list | /group add|remove <name> <node-id> | /g <name> <message>";
 
 pub async fn spawn_stdin_handler(node: Arc<SentinelNode>) -> Result<()> {
     let mut lines = BufReader::new(io::stdin()).lines();
     println!("READY TO CHAT. Type and hit Enter.");
 
     while let Ok(Some(line)) = lines.next_line().await {
-        let msg = SentinelMessage {
-            id: Uuid::new_v4(),
-            sender: node.identity.node_id(),
-            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
-            content: MessageContent::Chat(line.clone()),
-        };
+        if line.starts_with("/g ") || line.starts_with("/group") {
+            if let Err(e) = handle_group_command(&node, &line).await {
+                eprintln!("{}", e);
+            }
+            continue;
+        }
+        if let Some(target) = line.strip_prefix("/punch ") {
+            if let Err(e) = node.request_punch(target.trim()).await {
+                eprintln!("{}", e);
+            }
+            continue;
+        }
+        if let Some(args) = line.strip_prefix("/circuit ") {
+            let mut args = args.split_whitespace();
+            let (Some(relay), Some(target), None) = (args.next(), args.next(), args.next()) else {
+                eprintln!("Usage: /circuit <relay-node-id> <node-id>");
+                continue;
+            };
+            let Some(via) = node.peer_ids.get(relay).map(|addr| addr.clone()) else {
+                eprintln!("Not connected to relay {}", relay);
+                continue;
+            };
+            if let Err(e) = Arc::clone(&node).dial_via_relay(via, target.to_string()).await {
+                eprintln!("Circuit to {} failed: {:#}", target, e);
+            }
+            continue;
+        }
+        if let Some(command) = PeersCommand::from_prompt(&line) {
+            if let Err(e) = command.and_then(|command| command.apply(&node.known_peers)) {
+                eprintln!("{:#}", e);
+            }
+            continue;
+        }
+        if line == "/reload" {
+            match node.reload_certificates() {
+                Ok(()) => println!("Reloaded TLS certificates"),
+                Err(e) => eprintln!("Kept current TLS certificates: {:#}", e),
+            }
+            continue;
+        }
+
+        let msg = node.new_message(MessageContent::Chat(line.clone()));
 
         // 1. Save locally
         node.persist_message(&msg)?;
@@ -24,7 +62,7 @@ pub async fn spawn_stdin_handler(node: Arc<SentinelNode>) -> Result<()> {
         // 2. Broadcast to all connected peers
         for peer in node.peers.iter() {
             let sender = peer.value();
-            if let Err(_) = sender.send(msg.clone()) {
+            if sender.send(msg.clone()).is_err() {
                 // If send fails, the peer might be disconnected
             }
         }
@@ -32,4 +70,33 @@ pub async fn spawn_stdin_handler(node: Arc<SentinelNode>) -> Result<()> {
         println!("[YOU]: {}", line);
     }
     Ok(())
-}
\ No newline at end of file
+}
+
+async fn handle_group_command(node: &SentinelNode, line: &str) -> Result<()> {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next(), words.next(), words.next()) {
+        (Some("/g"), Some(group), Some(_), _) => {
+            let group = node.groups.resolve(group)?;
+            let text = line.splitn(3, char::is_whitespace).nth(2).unwrap_or_default().trim();
+            node.send_group_message(&group, text).await?;
+            println!("[{}] YOU: {}", group_label(&group), text);
+        }
+        (Some("/group"), Some("create"), Some(name), None) => {
+            let group = node.create_group(name)?;
+            println!("Created group {}", group);
+        }
+        (Some("/group"), Some("add"), Some(group), Some(node_id)) => {
+            node.add_group_member(&node.groups.resolve(group)?, node_id).await?;
+        }
+        (Some("/group"), Some("remove"), Some(group), Some(node_id)) => {
+            node.remove_group_member(&node.groups.resolve(group)?, node_id).await?;
+        }
+        (Some("/group"), Some("list"), None, None) => {
+            for (group, state) in node.groups.list()? {
+                println!("{} (epoch {}): {}", group, state.epoch, state.members.join(", "));
+            }
+        }
+        _ => anyhow::bail!(GROUP_USAGE),
+    }
+    Ok(())
+}
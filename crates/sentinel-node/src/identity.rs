@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use clap::{Subcommand, ValueEnum};
+use sentinel_crypto::{
+    EncryptedFileKeyStore, EnvKeyStore, FileKeyStore, KeyStore, MemoryKeyStore, NodeIdentity,
+};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+use crate::succession::record_succession;
+
+/// Passphrase for `--keystore encrypted`.
+pub const PASSPHRASE_ENV: &str = "SENTINEL_KEY_PASSPHRASE";
+const KEY_FILE: &str = "identity.key";
+const ENCRYPTED_KEY_FILE: &str = "identity.key.enc";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum KeyStoreKind {
+    /// `identity.key` in the data directory
+    File,
+    /// `identity.key.enc` in the data directory, sealed with $SENTINEL_KEY_PASSPHRASE
+    Encrypted,
+    /// Read-only key from an environment variable (hex seed or PKCS#8 PEM)
+    Env,
+    /// Fresh identity that lives only as long as the process
+    Memory,
+}
+
+fn passphrase() -> Result<String> {
+    std::env::var(PASSPHRASE_ENV).with_context(|| format!("{} must be set for the encrypted keystore", PASSPHRASE_ENV))
+}
+
+pub fn open_keystore(kind: KeyStoreKind, data_dir: &Path, key_env: &str) -> Result<Box<dyn KeyStore>> {
+    Ok(match kind {
+        KeyStoreKind::File => Box::new(FileKeyStore::new(data_dir.join(KEY_FILE))),
+        KeyStoreKind::Encrypted => {
+            // Generating a fresh encrypted identity here would silently replace the node's ID.
+            let (plain, sealed) = (data_dir.join(KEY_FILE), data_dir.join(ENCRYPTED_KEY_FILE));
+            if plain.exists() && !sealed.exists() {
+                anyhow::bail!(
+                    "{} holds an unencrypted identity; run `sentinel-node identity encrypt` to move it into {}",
+                    plain.display(),
+                    sealed.display()
+                );
+            }
+            Box::new(EncryptedFileKeyStore::new(sealed, passphrase()?))
+        }
+        KeyStoreKind::Env => Box::new(EnvKeyStore::new(key_env)),
+        KeyStoreKind::Memory => Box::new(MemoryKeyStore::new()),
+    })
+}
+
+#[derive(Debug, Subcommand)]
+pub enum IdentityCommand {
+    /// Print this node's ID
+    Show,
+    /// Replace the identity key, signing a succession record with the old one.
+    /// Run while the node is stopped; peers learn the new ID on next contact.
+    Rotate,
+    /// Print the identity as PKCS#8 PEM, or the public key as SPKI PEM with --public
+    Export {
+        #[arg(long)]
+        public: bool,
+    },
+    /// Replace the identity with a key file (raw, PKCS#8 DER or PKCS#8 PEM)
+    Import {
+        path: PathBuf,
+        /// Replace an existing, different identity
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the identity as a 24-word recovery phrase
+    Backup,
+    /// Restore the identity from a recovery phrase read from stdin
+    Restore {
+        /// Replace an existing, different identity
+        #[arg(long)]
+        force: bool,
+    },
+    /// Move identity.key into identity.key.enc, sealed with $SENTINEL_KEY_PASSPHRASE,
+    /// for use with --keystore encrypted. Ignores --keystore.
+    Encrypt,
+}
+
+impl IdentityCommand {
+    /// Refuses keystores the command cannot act on meaningfully. `memory` holds a fresh
+    /// identity that ends with the process, so showing, backing up or rotating it would
+    /// describe, or hand a succession to, a key no node will ever run with. `env` is
+    /// read-only, so it cannot take the key from rotate, import or restore.
+    pub fn check_keystore(&self, kind: KeyStoreKind) -> Result<()> {
+        match (kind, self) {
+            (KeyStoreKind::Memory, _) => anyhow::bail!(
+                "--keystore memory holds a throwaway identity; identity commands need file, encrypted or env"
+            ),
+            (KeyStoreKind::Env, IdentityCommand::Rotate | IdentityCommand::Import { .. } | IdentityCommand::Restore { .. }) => {
+                anyhow::bail!("--keystore env is read-only; use file or encrypted to replace the identity")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Seals the plaintext identity into the encrypted keystore, then deletes the plaintext.
+pub fn encrypt(data_dir: &Path) -> Result<()> {
+    let (plain, sealed) = (data_dir.join(KEY_FILE), data_dir.join(ENCRYPTED_KEY_FILE));
+    if sealed.exists() {
+        anyhow::bail!("{} already exists", sealed.display());
+    }
+    let identity = FileKeyStore::new(&plain)
+        .load()?
+        .with_context(|| format!("No identity at {}", plain.display()))?;
+
+    let store = EncryptedFileKeyStore::new(&sealed, passphrase()?);
+    store.store(&identity)?;
+    // Only drop the plaintext once the sealed copy is known to open.
+    if store.load()?.map(|stored| stored.node_id()) != Some(identity.node_id()) {
+        anyhow::bail!("{} did not read back as {}", sealed.display(), identity.node_id());
+    }
+    std::fs::remove_file(&plain).with_context(|| format!("Failed to remove {}", plain.display()))?;
+    println!("Encrypted identity {} into {}", identity.node_id(), sealed.display());
+    Ok(())
+}
+
+/// Fails if `keystore` already holds an identity other than `identity`, unless `force` is set.
+fn refuse_overwrite(keystore: &dyn KeyStore, identity: &NodeIdentity, force: bool) -> Result<()> {
+    if let Some(existing) = keystore.load()? {
+        if existing.node_id() != identity.node_id() && !force {
+            anyhow::bail!("An identity ({}) already exists; pass --force to replace it", existing.node_id());
+        }
+    }
+    Ok(())
+}
+
+pub fn run(command: IdentityCommand, data_dir: &Path, keystore: &dyn KeyStore) -> Result<()> {
+    std::fs::create_dir_all(data_dir)
+        .with_context(|| format!("Failed to create {}", data_dir.display()))?;
+
+    match command {
+        IdentityCommand::Show => {
+            let identity = keystore.load_or_generate()?;
+            println!("{}", identity.node_id());
+        }
+        IdentityCommand::Rotate => {
+            let old = keystore.load_or_generate()?;
+            let (new, record) = old.rotate();
+
+            // Store the new key first: a recorded succession to a key we failed to keep
+            // would hand the identity to nobody.
+            keystore.store(&new)?;
+            let db = sled::open(data_dir.join("storage.db"))?;
+            record_succession(&db, &record)?;
+            db.flush()?;
+
+            println!("Rotated identity {} -> {}", record.old_node_id(), record.new_node_id());
+        }
+        IdentityCommand::Export { public } => {
+            let identity = keystore.load_or_generate()?;
+            if public {
+                print!("{}", identity.public_key_pem()?);
+            } else {
+                print!("{}", identity.to_pkcs8_pem()?.as_str());
+            }
+        }
+        IdentityCommand::Import { path, force } => {
+            let bytes = Zeroizing::new(
+                std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?,
+            );
+            let identity = NodeIdentity::from_bytes(&bytes)?;
+            refuse_overwrite(keystore, &identity, force)?;
+            keystore.store(&identity)?;
+            println!("Imported identity {}", identity.node_id());
+        }
+        IdentityCommand::Backup => {
+            let identity = keystore.load_or_generate()?;
+            eprintln!("Recovery phrase for {}. Anyone with these words can act as this node:", identity.node_id());
+            println!("{}", identity.to_mnemonic()?.as_str());
+        }
+        IdentityCommand::Restore { force } => {
+            eprintln!("Enter the 24-word recovery phrase:");
+            let mut phrase = Zeroizing::new(String::new());
+            std::io::stdin().read_line(&mut phrase)?;
+            let identity = NodeIdentity::from_mnemonic(&phrase)?;
+            refuse_overwrite(keystore, &identity, force)?;
+            keystore.store(&identity)?;
+            println!("Restored identity {}", identity.node_id());
+        }
+        IdentityCommand::Encrypt => encrypt(data_dir)?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_throwaway_and_read_only_keystores_refused() {
+        for command in [IdentityCommand::Show, IdentityCommand::Rotate, IdentityCommand::Backup] {
+            assert!(command.check_keystore(KeyStoreKind::Memory).is_err());
+            assert!(command.check_keystore(KeyStoreKind::File).is_ok());
+        }
+        assert!(IdentityCommand::Rotate.check_keystore(KeyStoreKind::Env).is_err());
+        assert!(IdentityCommand::Restore { force: false }.check_keystore(KeyStoreKind::Env).is_err());
+        assert!(IdentityCommand::Show.check_keystore(KeyStoreKind::Env).is_ok());
+        assert!(IdentityCommand::Rotate.check_keystore(KeyStoreKind::Encrypted).is_ok());
+    }
+}
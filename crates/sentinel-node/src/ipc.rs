@@ -0,0 +1,53 @@
+use crate::engine::SentinelNode;
+use anyhow::{Context, Result};
+use sentinel_transport::{Connection, UnixSocketTransport};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::net::UnixListener;
+
+impl SentinelNode {
+    /// Serves processes on this host over a Unix socket at `path`, with the same
+    /// framing and signed messages as network peers but no TLS or admission.
+    /// Only processes running as the node's own user are let in.
+    pub async fn serve_ipc(self: Arc<Self>, path: PathBuf) -> Result<()> {
+        // A socket left behind by a previous run would make bind fail; anything else is not ours to delete.
+        if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+            if !metadata.file_type().is_socket() {
+                anyhow::bail!("{} exists and is not a socket", path.display());
+            }
+            std::fs::remove_file(&path)?;
+        }
+        let listener = UnixListener::bind(&path).with_context(|| format!("Failed to bind {}", path.display()))?;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+        // The socket was just created by this process, so its owner is our effective UID.
+        let own_uid = std::fs::metadata(&path)?.uid();
+        println!("Local IPC on {}", path.display());
+
+        let mut accepted = 0u64;
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let transport = UnixSocketTransport::new(stream);
+            let cred = match transport.peer_credentials() {
+                Ok(cred) => cred,
+                Err(e) => {
+                    eprintln!("Local connection without credentials refused: {}", e);
+                    continue;
+                }
+            };
+            if cred.uid() != own_uid {
+                eprintln!("Refused local connection from uid {}", cred.uid());
+                continue;
+            }
+
+            accepted += 1;
+            let pid = cred.pid().map_or_else(|| "?".to_string(), |pid| pid.to_string());
+            let addr = format!("unix:{}#{}", pid, accepted);
+            println!("Local process {} connected", pid);
+            // The socket's permissions and the UID check are the authentication here.
+            let conn = Connection::new(transport).authenticate(None);
+            tokio::spawn(Arc::clone(&self).run_peer(conn, addr, false));
+        }
+    }
+}
+
@@ -0,0 +1,243 @@
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const KNOWN_PEERS_TREE: &str = "known_peers";
+/// Keys that were refused because they differ from the pin, kept so
+/// `peers trust <addr>` can accept the last one without retyping it.
+const REJECTED_PEERS_TREE: &str = "rejected_peers";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownPeer {
+    /// Node ID of the key in the peer's TLS certificate.
+    pub node_id: String,
+    pub first_seen: u64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PeerTrust {
+    /// Never seen before; the key is now pinned.
+    FirstContact,
+    Matches,
+    /// The peer presented a different key than the one pinned for its address.
+    Changed { pinned: String },
+}
+
+/// Trust-on-first-use store mapping peer addresses to the key they first presented.
+#[derive(Clone)]
+pub struct KnownPeers {
+    known: sled::Tree,
+    rejected: sled::Tree,
+}
+
+impl KnownPeers {
+    pub fn open(db: &sled::Db) -> Result<Self> {
+        Ok(Self {
+            known: db.open_tree(KNOWN_PEERS_TREE)?,
+            rejected: db.open_tree(REJECTED_PEERS_TREE)?,
+        })
+    }
+
+    pub fn get(&self, addr: &str) -> Result<Option<KnownPeer>> {
+        self.known
+            .get(addr)?
+            .map(|bytes| bincode::deserialize(&bytes).context("Corrupt known_peers entry"))
+            .transpose()
+    }
+
+    /// Compares `node_id` against the pin for `addr`, pinning it on first contact.
+    pub fn check(&self, addr: &str, node_id: &str) -> Result<PeerTrust> {
+        match self.get(addr)? {
+            None => {
+                self.trust(addr, node_id)?;
+                Ok(PeerTrust::FirstContact)
+            }
+            Some(peer) if peer.node_id == node_id => Ok(PeerTrust::Matches),
+            Some(peer) => {
+                self.rejected.insert(addr, node_id.as_bytes())?;
+                Ok(PeerTrust::Changed { pinned: peer.node_id })
+            }
+        }
+    }
+
+    /// Pins `node_id` for `addr`, replacing any previous pin.
+    pub fn trust(&self, addr: &str, node_id: &str) -> Result<()> {
+        let entry = KnownPeer {
+            node_id: node_id.to_string(),
+            first_seen: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        self.known.insert(addr, bincode::serialize(&entry)?)?;
+        self.rejected.remove(addr)?;
+        Ok(())
+    }
+
+    pub fn last_rejected(&self, addr: &str) -> Result<Option<String>> {
+        Ok(self
+            .rejected
+            .get(addr)?
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    pub fn forget(&self, addr: &str) -> Result<bool> {
+        self.rejected.remove(addr)?;
+        Ok(self.known.remove(addr)?.is_some())
+    }
+
+    pub fn list(&self) -> Result<Vec<(String, KnownPeer)>> {
+        self.known
+            .iter()
+            .map(|item| {
+                let (addr, bytes) = item?;
+                Ok((String::from_utf8_lossy(&addr).into_owned(), bincode::deserialize(&bytes)?))
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum PeersCommand {
+    /// List pinned peer keys
+    List,
+    /// Pin a key for an address. Without NODE_ID, accepts the key last refused for it.
+    Trust {
+        addr: String,
+        node_id: Option<String>,
+    },
+    /// Remove the pin for an address; the next connection pins whatever it presents
+    Forget {
+        addr: String,
+    },
+}
+
+impl PeersCommand {
+    /// Parses the node's `/peers`, `/trust <addr> [node-id]` and `/forget <addr>` prompt
+    /// commands, which act on the running node's pins.
+    pub fn from_prompt(line: &str) -> Option<Result<Self>> {
+        let mut words = line.split_whitespace();
+        let command = match (words.next()?, words.next(), words.next(), words.next()) {
+            ("/peers", None, _, _) => Ok(PeersCommand::List),
+            ("/trust", Some(addr), node_id, None) => {
+                Ok(PeersCommand::Trust { addr: addr.to_string(), node_id: node_id.map(str::to_string) })
+            }
+            ("/forget", Some(addr), None, _) => Ok(PeersCommand::Forget { addr: addr.to_string() }),
+            ("/peers" | "/trust" | "/forget", ..) => {
+                Err(anyhow::anyhow!("Usage: /peers | /trust <addr> [node-id] | /forget <addr>"))
+            }
+            _ => return None,
+        };
+        Some(command)
+    }
+
+    pub fn apply(self, known_peers: &KnownPeers) -> Result<()> {
+        match self {
+            PeersCommand::List => {
+                for (addr, peer) in known_peers.list()? {
+                    println!("{}  {}", addr, peer.node_id);
+                }
+            }
+            PeersCommand::Trust { addr, node_id } => {
+                let node_id = match node_id {
+                    Some(node_id) => node_id,
+                    None => known_peers
+                        .last_rejected(&addr)?
+                        .with_context(|| format!("No refused key recorded for {}; pass a NODE_ID", addr))?,
+                };
+                sentinel_crypto::public_key_from_node_id(&node_id)?;
+                known_peers.trust(&addr, &node_id)?;
+                println!("Pinned {} for {}", node_id, addr);
+            }
+            PeersCommand::Forget { addr } => {
+                if known_peers.forget(&addr)? {
+                    println!("Forgot {}", addr);
+                } else {
+                    println!("{} was not pinned", addr);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Runs `command` against a stopped node's store. A running node holds the store
+/// exclusively; its prompt takes the same commands instead.
+pub fn run(command: PeersCommand, data_dir: &Path) -> Result<()> {
+    let path = data_dir.join("storage.db");
+    let db = sled::open(&path).with_context(|| {
+        format!(
+            "Failed to open {}. If the node is running, stop it first, or use /peers, /trust and /forget at its prompt",
+            path.display()
+        )
+    })?;
+    command.apply(&KnownPeers::open(&db)?)?;
+    db.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn open_peers() -> KnownPeers {
+        KnownPeers::open(&sled::Config::new().temporary(true).open().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_check_pins_first_key_and_refuses_changes() {
+        let peers = open_peers();
+        assert_eq!(peers.check("10.0.0.1:8443", "aa").unwrap(), PeerTrust::FirstContact);
+        assert_eq!(peers.check("10.0.0.1:8443", "aa").unwrap(), PeerTrust::Matches);
+        assert_eq!(peers.last_rejected("10.0.0.1:8443").unwrap(), None);
+
+        let changed = peers.check("10.0.0.1:8443", "bb").unwrap();
+        assert_eq!(changed, PeerTrust::Changed { pinned: "aa".into() });
+        // The refusal leaves the pin alone and remembers the refused key.
+        assert_eq!(peers.get("10.0.0.1:8443").unwrap().unwrap().node_id, "aa");
+        assert_eq!(peers.last_rejected("10.0.0.1:8443").unwrap(), Some("bb".into()));
+        // Pins are per address.
+        assert_eq!(peers.check("10.0.0.2:8443", "bb").unwrap(), PeerTrust::FirstContact);
+    }
+
+    #[test]
+    fn test_trust_and_forget_replace_pins() {
+        let peers = open_peers();
+        peers.check("10.0.0.1:8443", "aa").unwrap();
+        peers.check("10.0.0.1:8443", "bb").unwrap();
+
+        peers.trust("10.0.0.1:8443", "bb").unwrap();
+        assert_eq!(peers.check("10.0.0.1:8443", "bb").unwrap(), PeerTrust::Matches);
+        assert_eq!(peers.last_rejected("10.0.0.1:8443").unwrap(), None);
+
+        peers.check("10.0.0.1:8443", "cc").unwrap();
+        assert!(peers.forget("10.0.0.1:8443").unwrap());
+        assert!(!peers.forget("10.0.0.1:8443").unwrap());
+        assert_eq!(peers.last_rejected("10.0.0.1:8443").unwrap(), None);
+        assert!(peers.list().unwrap().is_empty());
+        assert_eq!(peers.check("10.0.0.1:8443", "cc").unwrap(), PeerTrust::FirstContact);
+    }
+
+    #[tokio::test]
+    async fn test_running_node_takes_pin_changes_at_its_prompt() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = crate::engine::NodeConfig::for_test(dir.path(), &sentinel_transport::MemoryNetwork::new());
+        let node = crate::engine::SentinelNode::new(config, &sentinel_crypto::MemoryKeyStore::new()).await.unwrap();
+        let node_id = node.identity.node_id();
+
+        let offline = PeersCommand::Trust { addr: "10.0.0.1:8443".into(), node_id: Some(node_id.clone()) };
+        let error = run(offline, dir.path()).unwrap_err();
+        assert!(format!("{:#}", error).contains("stop it first"), "{:#}", error);
+
+        let line = format!("/trust 10.0.0.1:8443 {}", node_id);
+        PeersCommand::from_prompt(&line).unwrap().unwrap().apply(&node.known_peers).unwrap();
+        assert_eq!(node.known_peers.get("10.0.0.1:8443").unwrap().unwrap().node_id, node_id);
+
+        PeersCommand::from_prompt("/forget 10.0.0.1:8443").unwrap().unwrap().apply(&node.known_peers).unwrap();
+        assert!(node.known_peers.get("10.0.0.1:8443").unwrap().is_none());
+        assert!(PeersCommand::from_prompt("/trust").unwrap().is_err());
+        assert!(PeersCommand::from_prompt("hello").is_none());
+    }
+}
@@ -1,32 +1,307 @@
 mod engine;
+mod connection;
 mod discovery;
+mod groups;
 mod handlers;
+mod identity;
+#[cfg(unix)]
+mod ipc;
+mod known_peers;
+mod membership;
+mod metrics;
+mod quic;
+mod relay;
+mod reload;
+mod rendezvous;
+mod succession;
+mod websocket;
 
 use anyhow::Result;
+use clap::{Parser, Subcommand};
 use std::sync::Arc;
+use std::net::SocketAddr;
 use std::path::PathBuf;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_util::codec::Framed;
-use futures::{StreamExt, SinkExt};
-use sentinel_protocol::{
-    SentinelCodec, 
-    frame::Frame, 
-    messages::SentinelMessage
+use crate::connection::SecureChannel;
+use crate::engine::{NodeConfig, SentinelNode, HANDSHAKE_TIMEOUT};
+use crate::identity::{IdentityCommand, KeyStoreKind};
+use crate::known_peers::PeersCommand;
+use crate::membership::MembershipCommand;
+use crate::relay::RelayLimits;
+use sentinel_crypto::public_key_from_node_id;
+use sentinel_transport::{
+    Cidr, ClientAuth, ConnectionLimits, HandshakeRate, RawTcpTransport, SentinelAcceptor, Socks5Proxy,
 };
-use crate::engine::SentinelNode;
+
+#[derive(Parser)]
+#[command(name = "sentinel-node", about = "Sentinel mesh node")]
+struct Cli {
+    /// Directory holding the identity, certificates and message store
+    #[arg(long, default_value = "./.sentinel")]
+    data_dir: PathBuf,
+
+    /// Where the identity key is kept
+    #[arg(long, value_enum, default_value = "file")]
+    keystore: KeyStoreKind,
+
+    /// Environment variable holding the key for `--keystore env`
+    #[arg(long, default_value = "SENTINEL_IDENTITY_KEY")]
+    key_env: String,
+
+    /// Node ID of the mesh admin key; peers without a membership certificate
+    /// from it are refused
+    #[arg(long)]
+    admin_key: Option<String>,
+
+    /// CA bundle for mutual TLS; node.crt/node.key must then be issued by this CA
+    #[arg(long)]
+    ca: Option<PathBuf>,
+
+    /// Secure channel to dial peers over unless they advertise one, and to ask
+    /// peers to dial this node over (inbound accepts both)
+    #[arg(long, value_enum, default_value = "tls")]
+    channel: SecureChannel,
+
+    /// Don't accept or dial QUIC; peers are then reached over TCP only
+    #[arg(long)]
+    no_quic: bool,
+
+    /// Unix socket on which local processes running as the same user can
+    /// exchange frames with the node, without TLS
+    #[arg(long)]
+    ipc_socket: Option<PathBuf>,
+
+    /// Address to accept WebSocket peers (browsers) on, e.g. 127.0.0.1:8080
+    #[arg(long)]
+    ws: Option<SocketAddr>,
+
+    /// Serve WebSocket peers over TLS (wss://) with the node certificate
+    #[arg(long, requires = "ws")]
+    ws_tls: bool,
+
+    /// Most inbound connections open at once
+    #[arg(long, default_value_t = 1024)]
+    max_connections: usize,
+
+    /// Most inbound connections open at once from one IP address
+    #[arg(long, default_value_t = 32)]
+    max_connections_per_ip: usize,
+
+    /// Inbound handshakes started per second on average; 0 for no limit
+    #[arg(long, default_value_t = 20.0)]
+    handshake_rate: f64,
+
+    /// Inbound handshakes that may start at once after a quiet spell
+    #[arg(long, default_value_t = 40)]
+    handshake_burst: u32,
+
+    /// Accept connections only from these networks (repeatable), e.g. 192.168.1.0/24
+    #[arg(long = "allow", value_name = "CIDR")]
+    allow: Vec<Cidr>,
+
+    /// Refuse connections from these networks (repeatable), even if allowed
+    #[arg(long = "deny", value_name = "CIDR")]
+    deny: Vec<Cidr>,
+
+    /// Address to serve Prometheus metrics on at /metrics, e.g. 127.0.0.1:9184
+    #[arg(long)]
+    metrics: Option<SocketAddr>,
+
+    /// SOCKS5 proxy to dial peers through, e.g. 127.0.0.1:9050 for Tor.
+    /// Host names are resolved by the proxy, and QUIC is not dialed
+    #[arg(long, value_name = "HOST:PORT")]
+    socks5: Option<String>,
+
+    /// Username for the SOCKS5 proxy
+    #[arg(long, requires_all = ["socks5", "socks5_password"])]
+    socks5_user: Option<String>,
+
+    /// Password for the SOCKS5 proxy
+    #[arg(long, requires = "socks5_user")]
+    socks5_password: Option<String>,
+
+    /// Tor onion service forwarding to this node, e.g. <name>.onion:8443, to
+    /// advertise to peers
+    #[arg(long, value_name = "HOST.onion:PORT")]
+    onion: Option<String>,
+
+    /// Peer to dial at startup (repeatable), e.g. <node-id>@<name>.onion:8443
+    #[arg(long = "peer", value_name = "NODE_ID@HOST:PORT", value_parser = parse_peer)]
+    peers: Vec<(String, String)>,
+
+    /// Carry circuits between peers that can't reach each other directly
+    #[arg(long)]
+    relay: bool,
+
+    /// Most circuits relayed at once
+    #[arg(long, default_value_t = 64, requires = "relay")]
+    relay_max_circuits: usize,
+
+    /// Seconds a relayed circuit may stay open
+    #[arg(long, default_value_t = 1800, requires = "relay")]
+    relay_max_duration: u64,
+
+    /// Bytes per second relayed on each circuit, or 0 for no limit
+    #[arg(long, default_value_t = 256 * 1024, requires = "relay")]
+    relay_rate: u64,
+
+    /// Send the greeting as TLS 0-RTT data when resuming a session, and accept
+    /// idempotent messages peers send that way
+    #[arg(long)]
+    zero_rtt: bool,
+
+    /// Publicly reachable peer to dial at startup and ask for introductions to
+    /// peers behind NATs (`/punch <node-id>`)
+    #[arg(long, value_name = "NODE_ID@HOST:PORT", value_parser = parse_peer)]
+    rendezvous: Option<(String, String)>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Manage the node identity
+    #[command(subcommand)]
+    Identity(IdentityCommand),
+    /// Manage pinned peer keys (trust on first use)
+    #[command(subcommand)]
+    Peers(PeersCommand),
+    /// Issue and install mesh membership certificates
+    #[command(subcommand)]
+    Membership(MembershipCommand),
+}
+
+/// Parses `NODE_ID@HOST:PORT`.
+fn parse_peer(s: &str) -> Result<(String, String), String> {
+    let (node_id, addr) = s.split_once('@').ok_or("expected NODE_ID@HOST:PORT")?;
+    public_key_from_node_id(node_id).map_err(|e| format!("invalid node ID: {}", e))?;
+    Ok((node_id.to_string(), addr.to_string()))
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    // Pins don't involve our own key, so `peers` works without a passphrase or key variable.
+    // `identity encrypt` migrates between keystores, so it opens both itself.
+    match cli.command {
+        Some(Command::Peers(command)) => return known_peers::run(command, &cli.data_dir),
+        Some(Command::Identity(IdentityCommand::Encrypt)) => return identity::encrypt(&cli.data_dir),
+        Some(Command::Identity(ref command)) => command.check_keystore(cli.keystore)?,
+        _ => {}
+    }
+    let keystore = identity::open_keystore(cli.keystore, &cli.data_dir, &cli.key_env)?;
+    match cli.command {
+        Some(Command::Identity(command)) => return identity::run(command, &cli.data_dir, keystore.as_ref()),
+        Some(Command::Membership(command)) => {
+            return membership::run(command, &cli.data_dir, keystore.as_ref())
+        }
+        Some(Command::Peers(_)) | None => {}
+    }
+
     rustls::crypto::aws_lc_rs::default_provider().install_default().ok();
 
-    let node = Arc::new(SentinelNode::new(PathBuf::from("./.sentinel")).await?);
+    let ipc_socket = cli.ipc_socket;
+    let node = Arc::new(SentinelNode::new(NodeConfig {
+        data_dir: cli.data_dir,
+        channel: cli.channel,
+        admin_key: cli.admin_key.as_deref().map(public_key_from_node_id).transpose()?,
+        ca_path: cli.ca,
+        quic_addr: (!cli.no_quic).then(|| ([0, 0, 0, 0], 8443).into()),
+        network: None,
+        limits: ConnectionLimits {
+            max_connections: Some(cli.max_connections),
+            max_per_ip: Some(cli.max_connections_per_ip),
+            handshake_rate: (cli.handshake_rate > 0.0)
+                .then_some(HandshakeRate { per_second: cli.handshake_rate, burst: cli.handshake_burst }),
+            allow: cli.allow,
+            deny: cli.deny,
+        },
+        proxy: cli.socks5.map(|addr| match (cli.socks5_user, cli.socks5_password) {
+            (Some(user), Some(password)) => Socks5Proxy::new(addr).with_credentials(user, password),
+            _ => Socks5Proxy::new(addr),
+        }),
+        onion_address: cli.onion,
+        relay: cli.relay.then_some(RelayLimits {
+            max_circuits: cli.relay_max_circuits,
+            max_duration: Duration::from_secs(cli.relay_max_duration),
+            bytes_per_second: cli.relay_rate,
+        }),
+        zero_rtt: cli.zero_rtt,
+    }, keystore.as_ref()).await?);
     node.print_history()?;
     node.start_discovery(8443)?;
 
+    for (node_id, addr) in cli.peers {
+        let dial_node = Arc::clone(&node);
+        tokio::spawn(async move {
+            if let Err(e) = Arc::clone(&dial_node).dial_peer(Some(node_id), addr.clone()).await {
+                dial_node.metrics.dial_failed();
+                eprintln!("Dial error for {}: {}", addr, e);
+            }
+        });
+    }
+    if let Some((node_id, addr)) = cli.rendezvous {
+        let dial_node = Arc::clone(&node);
+        tokio::spawn(async move {
+            if let Err(e) = dial_node.dial_rendezvous(node_id, addr.clone()).await {
+                eprintln!("Rendezvous {} unreachable: {}", addr, e);
+            }
+        });
+    }
+
     let gossip_node = Arc::clone(&node);
     tokio::spawn(async move { gossip_node.start_gossip_service().await });
 
+    tokio::spawn(Arc::clone(&node).serve_quic());
+
+    if let Some(addr) = cli.ws {
+        // Browsers can't present client certificates, so only the server authenticates.
+        let tls = cli
+            .ws_tls
+            .then(|| {
+                let credentials = node.tls_credentials()?;
+                SentinelAcceptor::from_credentials(&credentials, ClientAuth::None, HANDSHAKE_TIMEOUT)
+            })
+            .transpose()?;
+        if let Some(tls) = &tls {
+            node.reload_with(tls);
+        }
+        let ws_node = Arc::clone(&node);
+        tokio::spawn(async move {
+            if let Err(e) = ws_node.serve_websocket(addr, tls).await {
+                eprintln!("WebSocket listener stopped: {}", e);
+            }
+        });
+    }
+
+    if let Some(path) = ipc_socket {
+        #[cfg(unix)]
+        {
+            let ipc_node = Arc::clone(&node);
+            tokio::spawn(async move {
+                if let Err(e) = ipc_node.serve_ipc(path).await {
+                    eprintln!("Local IPC stopped: {}", e);
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        anyhow::bail!("--ipc-socket needs Unix domain sockets ({})", path.display());
+    }
+
+    if let Some(addr) = cli.metrics {
+        let listener = TcpListener::bind(addr).await?;
+        let metrics_node = Arc::clone(&node);
+        tokio::spawn(async move {
+            if let Err(e) = metrics_node.serve_metrics(listener).await {
+                eprintln!("Metrics endpoint stopped: {}", e);
+            }
+        });
+    }
+
+    tokio::spawn(Arc::clone(&node).watch_certificates());
+
     let stdin_node = Arc::clone(&node);
     tokio::spawn(async move { let _ = handlers::spawn_stdin_handler(stdin_node).await; });
 
@@ -35,35 +310,15 @@ async fn main() -> Result<()> {
 
     loop {
         let (stream, addr) = listener.accept().await?;
-        let acceptor = node.acceptor.clone();
+        // Limits are checked before the handshake, so refused peers cost almost nothing.
+        let Some(permit) = node.admit_inbound(addr) else { continue };
         let node_inner = Arc::clone(&node);
-        let addr_str = addr.to_string();
 
         tokio::spawn(async move {
-            if let Ok(tls) = acceptor.accept(stream).await {
-                let (mut sink, mut stream) = Framed::new(tls, SentinelCodec::new()).split();
-                let (tx, mut rx) = mpsc::unbounded_channel::<SentinelMessage>();
-                let mut peer_id = String::from("unknown");
-
-                tokio::spawn(async move {
-                    while let Some(msg) = rx.recv().await {
-                        if let Ok(f) = Frame::new(1, 0, msg.to_bytes().into()) {
-                            if sink.send(f).await.is_err() { break; }
-                        }
-                    }
-                });
-
-                while let Some(Ok(frame)) = stream.next().await {
-                    if let Ok(msg) = SentinelMessage::from_bytes(frame.payload()) {
-                        if peer_id == "unknown" {
-                            peer_id = msg.sender.clone();
-                            node_inner.peers.insert(addr_str.clone(), tx.clone());
-                        }
-                        let _ = node_inner.clone().handle_incoming_message(msg, addr_str.clone()).await;
-                    }
-                }
-                node_inner.peers.remove(&addr_str);
+            let _permit = permit;
+            if let Err(e) = node_inner.accept_connection(RawTcpTransport::new(stream), addr.to_string()).await {
+                eprintln!("Inbound connection from {} failed: {}", addr, e);
             }
         });
     }
-}
\ No newline at end of file
+}
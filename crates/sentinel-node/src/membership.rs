@@ -0,0 +1,185 @@
+use crate::engine::SentinelNode;
+use anyhow::{Context, Result};
+use clap::Subcommand;
+use sentinel_crypto::{
+    node_id_from_public_key, public_key_from_node_id, KeyStore, MembershipCertificate, NodeIdentity, VerifyingKey,
+    MAX_CERTIFICATE_LEN,
+};
+use sentinel_transport::SentinelTransport;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use zeroize::Zeroizing;
+
+/// This node's certificate, installed with `membership install`.
+pub const MEMBERSHIP_FILE: &str = "membership.cert";
+
+const PROOF_LEN: usize = 64;
+/// Upper bound on an admission message: the largest certificate plus its proof.
+const MAX_ADMISSION_LEN: usize = MAX_CERTIFICATE_LEN + PROOF_LEN;
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+/// Admission policy for a node: the admin key certificates must chain to, and
+/// the certificate this node presents. Without an admin key, anyone is admitted.
+pub struct Admission {
+    admin_key: Option<VerifyingKey>,
+    certificate: Option<MembershipCertificate>,
+}
+
+impl Admission {
+    /// Loads `membership.cert` from `data_dir`. If `admin_key` is set, it must
+    /// exist and be valid for `identity`, or this node could never join.
+    pub fn load(data_dir: &Path, admin_key: Option<VerifyingKey>, identity: &NodeIdentity) -> Result<Self> {
+        let path = data_dir.join(MEMBERSHIP_FILE);
+        let certificate = if path.exists() {
+            let bytes = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            Some(MembershipCertificate::from_bytes(&bytes).with_context(|| format!("Invalid {}", path.display()))?)
+        } else {
+            None
+        };
+
+        if let Some(admin_key) = &admin_key {
+            let certificate = certificate
+                .as_ref()
+                .with_context(|| format!("--admin-key is set but {} is missing", path.display()))?;
+            certificate.verify(admin_key, &identity.node_id(), now())?;
+        }
+        Ok(Self { admin_key, certificate })
+    }
+}
+
+/// What each side signs: the channel binding plus its role in the connection,
+/// so a peer can't reflect our own admission back at us.
+fn admission_binding(binding: &[u8], dialer: bool) -> Vec<u8> {
+    let mut bytes = binding.to_vec();
+    bytes.extend_from_slice(if dialer { b"dialer" } else { b"listener" });
+    bytes
+}
+
+impl SentinelNode {
+    /// Exchanges membership certificates over a freshly secured channel.
+    ///
+    /// Both sides always send an admission message (empty if they hold no
+    /// certificate). If this node has an admin key, the peer's certificate must
+    /// be issued by it, unexpired, proven on this channel and, when the channel
+    /// already authenticated a node ID, issued for that ID.
+    pub async fn admit<T: SentinelTransport>(
+        &self,
+        transport: &mut T,
+        authenticated: Option<&str>,
+        dialer: bool,
+    ) -> Result<Option<MembershipCertificate>> {
+        let binding = transport.channel_binding();
+
+        let outgoing = match (&self.admission.certificate, &binding) {
+            (Some(cert), Some(binding)) => {
+                let mut bytes = cert.to_bytes();
+                bytes.extend_from_slice(&self.identity.sign_admission(&admission_binding(binding, dialer)));
+                bytes
+            }
+            _ => Vec::new(),
+        };
+        if outgoing.len() > MAX_ADMISSION_LEN {
+            anyhow::bail!("Admission message too large ({} bytes)", outgoing.len());
+        }
+        let len = u16::try_from(outgoing.len()).context("Admission message length overflows u16")?;
+        transport.write_all(&len.to_be_bytes()).await?;
+        transport.write_all(&outgoing).await?;
+        transport.flush().await?;
+
+        let len = transport.read_u16().await? as usize;
+        if len > MAX_ADMISSION_LEN {
+            anyhow::bail!("Admission message too large ({} bytes)", len);
+        }
+        let mut incoming = vec![0u8; len];
+        transport.read_exact(&mut incoming).await?;
+
+        let Some(admin_key) = &self.admission.admin_key else { return Ok(None) };
+        let binding = binding.context("Transport has no channel binding; cannot verify admission")?;
+        if incoming.len() <= PROOF_LEN {
+            anyhow::bail!("Peer presented no membership certificate");
+        }
+
+        let (cert, proof) = incoming.split_at(incoming.len() - PROOF_LEN);
+        let cert = MembershipCertificate::from_bytes(cert)?;
+        let node_id = cert.node_id();
+        if let Some(authenticated) = authenticated {
+            if authenticated != node_id {
+                anyhow::bail!("Membership certificate is for {}, but the peer authenticated as {}", node_id, authenticated);
+            }
+        }
+        cert.verify(admin_key, &node_id, now())?;
+        cert.verify_admission(&admission_binding(&binding, !dialer), proof)?;
+        Ok(Some(cert))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MembershipCommand {
+    /// Sign a membership certificate for a node with an admin key
+    Issue {
+        /// Admin key file (raw seed, PKCS#8 DER or PEM)
+        #[arg(long)]
+        admin_key_file: PathBuf,
+        /// Node ID to admit
+        node_id: String,
+        #[arg(long, default_value_t = 365)]
+        valid_days: u64,
+        /// Role granted to the node; repeatable
+        #[arg(long = "role")]
+        roles: Vec<String>,
+        /// Where to write the certificate (default: <node-id>.cert)
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Install a certificate issued for this node
+    Install {
+        path: PathBuf,
+    },
+    /// Show this node's certificate
+    Show,
+}
+
+pub fn run(command: MembershipCommand, data_dir: &Path, keystore: &dyn KeyStore) -> Result<()> {
+    match command {
+        MembershipCommand::Issue { admin_key_file, node_id, valid_days, roles, out } => {
+            let bytes = Zeroizing::new(
+                std::fs::read(&admin_key_file)
+                    .with_context(|| format!("Failed to read {}", admin_key_file.display()))?,
+            );
+            let admin = NodeIdentity::from_bytes(&bytes)?;
+            let node_key = public_key_from_node_id(&node_id)?;
+            let expires_at = now() + valid_days * 24 * 60 * 60;
+
+            let cert = admin.issue_membership(&node_key, expires_at, roles)?;
+            let out = out.unwrap_or_else(|| PathBuf::from(format!("{}.cert", node_id)));
+            std::fs::write(&out, cert.to_bytes())
+                .with_context(|| format!("Failed to write {}", out.display()))?;
+            println!("Wrote {} (admin key {})", out.display(), admin.node_id());
+        }
+        MembershipCommand::Install { path } => {
+            let bytes = std::fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+            let cert = MembershipCertificate::from_bytes(&bytes)?;
+            let identity = keystore.load_or_generate()?;
+            cert.verify(cert.admin_key(), &identity.node_id(), now())?;
+
+            std::fs::create_dir_all(data_dir)?;
+            std::fs::write(data_dir.join(MEMBERSHIP_FILE), &bytes)?;
+            println!("Installed membership for {} (admin key {})", cert.node_id(), node_id_from_public_key(cert.admin_key()));
+        }
+        MembershipCommand::Show => {
+            let path = data_dir.join(MEMBERSHIP_FILE);
+            let bytes = std::fs::read(&path).with_context(|| format!("No certificate at {}", path.display()))?;
+            let cert = MembershipCertificate::from_bytes(&bytes)?;
+            println!("node:    {}", cert.node_id());
+            println!("admin:   {}", node_id_from_public_key(cert.admin_key()));
+            println!("expires: {}", cert.expires_at());
+            println!("roles:   {}", cert.roles().join(", "));
+        }
+    }
+    Ok(())
+}
+
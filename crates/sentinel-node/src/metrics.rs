@@ -0,0 +1,231 @@
+use crate::engine::{SentinelNode, HANDSHAKE_TIMEOUT};
+use anyhow::Result;
+use sentinel_transport::{HandshakeFailure, TransportMetrics};
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Longest request head the metrics endpoint reads before giving up on a client.
+const MAX_REQUEST_LEN: usize = 8 * 1024;
+
+/// Counters for the engine and discovery, plus the transport metrics shared
+/// with the acceptor and connector. Gauges such as the peer count are read
+/// from the node when rendering instead.
+#[derive(Default)]
+pub struct NodeMetrics {
+    pub transport: Arc<TransportMetrics>,
+    messages_received: AtomicU64,
+    messages_sent: AtomicU64,
+    duplicates_dropped: AtomicU64,
+    invalid_signatures: AtomicU64,
+    gossip_rounds: AtomicU64,
+    peers_discovered: AtomicU64,
+    dial_failures: AtomicU64,
+    circuits_relayed: AtomicU64,
+    relayed_bytes: AtomicU64,
+}
+
+impl NodeMetrics {
+    /// A message passed deduplication and verification and was dispatched.
+    pub fn message_received(&self) {
+        self.messages_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A message was written to a peer.
+    pub fn message_sent(&self) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn duplicates_dropped(&self, count: usize) {
+        self.duplicates_dropped.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn invalid_signature(&self) {
+        self.invalid_signatures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn gossip_round(&self) {
+        self.gossip_rounds.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn peer_discovered(&self) {
+        self.peers_discovered.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dial_failed(&self) {
+        self.dial_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn circuit_relayed(&self) {
+        self.circuits_relayed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn relayed_bytes(&self, count: usize) {
+        self.relayed_bytes.fetch_add(count as u64, Ordering::Relaxed);
+    }
+}
+
+/// Prometheus text exposition format, version 0.0.4.
+struct Exposition(String);
+
+impl Exposition {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.0, "# HELP sentinel_{} {}", name, help);
+        let _ = writeln!(self.0, "# TYPE sentinel_{} {}", name, kind);
+    }
+
+    fn metric(&mut self, name: &str, kind: &str, help: &str, value: impl std::fmt::Display) {
+        self.header(name, kind, help);
+        let _ = writeln!(self.0, "sentinel_{} {}", name, value);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, counter: &AtomicU64) {
+        self.metric(name, "counter", help, counter.load(Ordering::Relaxed));
+    }
+}
+
+impl SentinelNode {
+    /// Renders every node metric in the Prometheus text format.
+    pub fn render_metrics(&self) -> String {
+        let m = &self.metrics;
+        let mut out = Exposition(String::new());
+
+        out.metric("peers", "gauge", "Peers with an open connection.", self.peers.len());
+        out.metric("storage_bytes", "gauge", "Size of the message store on disk.", self.db.size_on_disk().unwrap_or(0));
+        out.counter("messages_received_total", "Messages accepted from peers.", &m.messages_received);
+        out.counter("messages_sent_total", "Messages written to peers.", &m.messages_sent);
+        out.counter("messages_duplicate_total", "Messages dropped as already seen.", &m.duplicates_dropped);
+        out.counter("messages_invalid_total", "Messages dropped for an invalid signature.", &m.invalid_signatures);
+        out.counter("gossip_rounds_total", "Peer lists gossiped to neighbours.", &m.gossip_rounds);
+        out.counter("discovery_peers_total", "Peers found through mDNS.", &m.peers_discovered);
+        out.counter("discovery_dial_failures_total", "Failed dials to discovered peers.", &m.dial_failures);
+        let open_circuits = self.relay.as_ref().map_or(0, |relay| relay.open_circuits());
+        out.metric("relay_circuits", "gauge", "Circuits currently relayed for other peers.", open_circuits);
+        out.counter("relay_circuits_total", "Circuits relayed for other peers.", &m.circuits_relayed);
+        out.counter("relay_bytes_total", "Circuit bytes relayed for other peers.", &m.relayed_bytes);
+
+        let t = m.transport.snapshot();
+        out.metric("transport_connections_total", "counter", "Secure connections established.", t.total_connections);
+        out.metric("transport_connections_active", "gauge", "Secure connections currently open.", t.active_connections);
+        out.metric("transport_connections_refused_total", "counter", "Inbound connections refused by the limits.", t.connections_refused);
+        out.metric("transport_sent_bytes_total", "counter", "Application bytes sent.", t.bytes_sent);
+        out.metric("transport_received_bytes_total", "counter", "Application bytes received.", t.bytes_received);
+        out.metric("transport_resumed_handshakes_total", "counter", "TLS handshakes that resumed an earlier session.", t.handshakes_resumed);
+        out.metric("transport_early_data_bytes_total", "counter", "TLS 0-RTT bytes accepted from peers.", t.early_data_accepted);
+        out.metric("transport_sent_frames_total", "counter", "Frames sent.", t.frames_sent);
+        out.metric("transport_received_frames_total", "counter", "Frames received.", t.frames_received);
+
+        out.header("transport_handshake_failures_total", "counter", "Failed handshakes by reason.");
+        for reason in HandshakeFailure::ALL {
+            let _ = writeln!(
+                out.0,
+                "sentinel_transport_handshake_failures_total{{reason=\"{}\"}} {}",
+                reason.label(),
+                t.handshakes_failed(reason)
+            );
+        }
+
+        let latency = &t.handshake_latency;
+        out.header("transport_handshake_seconds", "histogram", "Duration of completed handshakes.");
+        for (bound, count) in &latency.buckets {
+            let _ = writeln!(out.0, "sentinel_transport_handshake_seconds_bucket{{le=\"{}\"}} {}", bound.as_secs_f64(), count);
+        }
+        let _ = writeln!(out.0, "sentinel_transport_handshake_seconds_bucket{{le=\"+Inf\"}} {}", latency.count);
+        let _ = writeln!(out.0, "sentinel_transport_handshake_seconds_sum {}", latency.sum.as_secs_f64());
+        let _ = writeln!(out.0, "sentinel_transport_handshake_seconds_count {}", latency.count);
+        out.0
+    }
+
+    /// Serves [`render_metrics`](Self::render_metrics) over HTTP at `/metrics` until the listener fails.
+    pub async fn serve_metrics(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        println!("Metrics on http://{}/metrics", listener.local_addr()?);
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let node = Arc::clone(&self);
+            tokio::spawn(async move {
+                let _ = tokio::time::timeout(HANDSHAKE_TIMEOUT, node.answer_scrape(stream)).await;
+            });
+        }
+    }
+
+    /// Answers one HTTP request and closes the connection.
+    async fn answer_scrape(&self, mut stream: TcpStream) -> Result<()> {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 || request.len() + n > MAX_REQUEST_LEN {
+                return Ok(());
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+
+        let request_line = request.split(|b| *b == b'\r').next().unwrap_or_default();
+        let mut words = request_line.split(|b| *b == b' ');
+        let (method, path) = (words.next(), words.next());
+        let (status, body) = match (method, path) {
+            (Some(b"GET"), Some(b"/metrics")) => ("200 OK", self.render_metrics()),
+            (Some(b"GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+            _ => ("405 Method Not Allowed", "Only GET is supported\n".to_string()),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NodeConfig;
+    use sentinel_crypto::MemoryKeyStore;
+    use sentinel_protocol::messages::MessageContent;
+    use sentinel_transport::MemoryNetwork;
+
+    async fn scrape(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: test\r\n\r\n", path).as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn test_scrape_reports_engine_counters() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = Arc::new(SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::clone(&node).serve_metrics(listener));
+
+        // One good message delivered twice, and one with a broken signature.
+        let good = node.new_message(MessageContent::Chat("hello".into()));
+        let mut forged = node.new_message(MessageContent::Chat("forged".into()));
+        forged.signature[0] ^= 1;
+        node.handle_incoming_batch(vec![good.clone(), forged], "10.0.0.9:1").await;
+        node.handle_incoming_batch(vec![good], "10.0.0.9:1").await;
+
+        let response = scrape(addr, "/metrics").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        for line in [
+            "sentinel_messages_received_total 1",
+            "sentinel_messages_duplicate_total 1",
+            "sentinel_messages_invalid_total 1",
+            "sentinel_peers 0",
+            "sentinel_transport_handshake_failures_total{reason=\"timeout\"} 0",
+            "sentinel_transport_handshake_seconds_bucket{le=\"+Inf\"} 0",
+        ] {
+            assert!(response.lines().any(|l| l == line), "missing {:?} in\n{}", line, response);
+        }
+        assert!(scrape(addr, "/").await.starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,125 @@
+use crate::engine::{SentinelNode, HANDSHAKE_TIMEOUT};
+use anyhow::{Context, Result};
+use futures::{SinkExt, StreamExt};
+use sentinel_protocol::frame::Frame;
+use sentinel_transport::{Authenticated, Connection, MeteredCodec, QuicIncoming, QuicTransport};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+/// mDNS TXT key carrying the UDP port a node accepts QUIC on.
+pub const QUIC_TXT_KEY: &str = "quic";
+
+impl SentinelNode {
+    /// Accepts QUIC connections until the endpoint closes. Does nothing if QUIC is disabled.
+    pub async fn serve_quic(self: Arc<Self>) {
+        let Some(endpoint) = &self.quic else { return };
+        while let Some(incoming) = endpoint.accept().await {
+            let Some(permit) = self.admit_inbound(incoming.remote_address()) else {
+                incoming.refuse();
+                continue;
+            };
+            let node = Arc::clone(&self);
+            let addr = incoming.remote_address().to_string();
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Err(e) = node.accept_quic(incoming, addr.clone()).await {
+                    eprintln!("Inbound QUIC connection from {} failed: {}", addr, e);
+                }
+            });
+        }
+    }
+
+    async fn accept_quic(self: Arc<Self>, incoming: QuicIncoming, addr: String) -> Result<()> {
+        let conn = incoming.handshake().await?;
+        // The dialer opens the first stream for the membership exchange.
+        let control = tokio::time::timeout(HANDSHAKE_TIMEOUT, conn.accept_stream())
+            .await
+            .context("Peer opened no stream")??;
+        let node_id = conn.peer_node_id();
+        let control = self.admit_peer(Connection::new(control), node_id, &addr, false).await?;
+        self.run_quic_peer(control, addr, false).await
+    }
+
+    /// Dials `addr` over QUIC; `expected_node_id` is checked as in [`dial_peer`](Self::dial_peer).
+    pub async fn dial_quic(self: Arc<Self>, expected_node_id: Option<String>, addr: String) -> Result<()> {
+        let endpoint = self.quic.as_ref().context("QUIC is disabled")?;
+        let target = tokio::net::lookup_host(&addr)
+            .await?
+            .next()
+            .with_context(|| format!("{} did not resolve", addr))?;
+        let conn = match (&expected_node_id, &self.ca_path) {
+            (Some(node_id), None) => endpoint.connect_to_node(node_id, target).await?,
+            _ => endpoint.connect(target).await?,
+        };
+        let node_id = conn.peer_node_id();
+        self.check_tls_peer(&addr, expected_node_id.as_deref(), node_id.as_deref())?;
+
+        let control = conn.open_stream().await?;
+        let control = self.admit_peer(Connection::new(control), node_id, &addr, true).await?;
+        tokio::spawn(self.run_quic_peer(control, addr, true));
+        Ok(())
+    }
+
+    /// Runs the message pipeline over the QUIC connection `control` was admitted on.
+    /// Every message travels on its own unidirectional stream, so a lost packet
+    /// holds up only that message.
+    pub async fn run_quic_peer(
+        self: Arc<Self>,
+        control: Connection<QuicTransport, Authenticated>,
+        addr: String,
+        greet: bool,
+    ) -> Result<()> {
+        if let Some(node_id) = control.node_id() {
+            self.peer_ids.insert(node_id.to_string(), addr.clone());
+        }
+        let conn = control.transport().connection().clone();
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        self.peers.insert(addr.clone(), tx.clone());
+
+        let sender = conn.clone();
+        let addr_out = addr.clone();
+        let node = Arc::clone(&self);
+        tokio::spawn(async move {
+            while let Some(msg) = rx.recv().await {
+                let Ok(frame) = Frame::new(1, 0, msg.to_bytes().into()) else { continue };
+                let stream = match sender.open_uni().await {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        eprintln!("Write error to {}: {}", addr_out, e);
+                        break;
+                    }
+                };
+                let metrics = sender.metrics().clone();
+                let node = Arc::clone(&node);
+                tokio::spawn(async move {
+                    let mut sink = FramedWrite::new(stream, MeteredCodec::new(Some(metrics)));
+                    if sink.send(frame).await.is_ok() {
+                        node.metrics.message_sent();
+                    }
+                    let _ = sink.close().await;
+                });
+            }
+        });
+
+        let (frames_tx, frames) = futures::channel::mpsc::unbounded();
+        let receiver = conn.clone();
+        tokio::spawn(async move {
+            while let Ok(stream) = receiver.accept_uni().await {
+                let frames_tx = frames_tx.clone();
+                let metrics = receiver.metrics().clone();
+                tokio::spawn(async move {
+                    let mut frames = FramedRead::new(stream, MeteredCodec::new(Some(metrics)));
+                    while let Some(frame) = frames.next().await {
+                        if frames_tx.unbounded_send(frame).is_err() { break; }
+                    }
+                });
+            }
+        });
+
+        let result = self.serve_frames(frames, tx, addr, greet).await;
+        conn.close("closed");
+        result
+    }
+}
+
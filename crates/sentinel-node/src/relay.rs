@@ -0,0 +1,423 @@
+use crate::engine::SentinelNode;
+use anyhow::Result;
+use async_trait::async_trait;
+use dashmap::DashMap;
+use sentinel_crypto::public_key_from_node_id;
+use sentinel_protocol::messages::{MessageContent, SentinelMessage};
+use sentinel_transport::{Connection, NoiseTransport, SentinelTransport};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Largest piece of circuit traffic carried in one message.
+const MAX_CHUNK: usize = 16 * 1024;
+
+/// Most circuits other peers may have open to us at once, through any relays.
+/// Each runs a Noise handshake and then a full peer connection.
+const MAX_INBOUND_CIRCUITS: usize = 64;
+
+/// Most of those circuits one peer may have open.
+const MAX_INBOUND_CIRCUITS_PER_PEER: usize = 4;
+
+/// Limits on the circuits an opt-in relay carries between other peers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelayLimits {
+    /// Circuits open at once.
+    pub max_circuits: usize,
+    /// How long a circuit may stay open.
+    pub max_duration: Duration,
+    /// Bytes forwarded per second on each circuit, both directions together.
+    /// Faster senders are held back, which in turn slows their connection.
+    /// `0` leaves circuits unthrottled.
+    pub bytes_per_second: u64,
+}
+
+/// The circuits a relay node is carrying.
+pub struct Relay {
+    limits: RelayLimits,
+    circuits: DashMap<Uuid, RelayedCircuit>,
+}
+
+struct RelayedCircuit {
+    /// Node ID and connection address of the end that opened it, then of its target.
+    ends: [(String, String); 2],
+    budget: Mutex<Budget>,
+}
+
+/// A token bucket holding up to one second of traffic.
+struct Budget {
+    bytes: f64,
+    refilled: Instant,
+}
+
+impl Relay {
+    pub fn new(limits: RelayLimits) -> Self {
+        Self { limits, circuits: DashMap::new() }
+    }
+
+    pub fn open_circuits(&self) -> usize {
+        self.circuits.len()
+    }
+
+    /// Where traffic from `sender` at `addr` on `circuit` goes, and how long to
+    /// hold it first to keep the circuit under its rate.
+    fn route(&self, circuit: &Uuid, sender: &str, addr: &str, len: usize) -> Option<(String, Duration)> {
+        let relayed = self.circuits.get(circuit)?;
+        let from = relayed.ends.iter().position(|(node_id, end)| node_id == sender && end == addr)?;
+        let to = relayed.ends[1 - from].1.clone();
+        if self.limits.bytes_per_second == 0 {
+            return Some((to, Duration::ZERO));
+        }
+
+        let rate = self.limits.bytes_per_second as f64;
+        let mut budget = relayed.budget.lock().unwrap();
+        let now = Instant::now();
+        budget.bytes = (budget.bytes + now.duration_since(budget.refilled).as_secs_f64() * rate).min(rate);
+        budget.refilled = now;
+        budget.bytes -= len as f64;
+        let wait = if budget.bytes < 0.0 { Duration::from_secs_f64(-budget.bytes / rate) } else { Duration::ZERO };
+        Some((to, wait))
+    }
+}
+
+/// Our end of a circuit to `peer` through the relay connected at `via`.
+pub struct CircuitEnd {
+    peer: String,
+    via: String,
+    /// Whether `peer` opened the circuit, rather than us.
+    inbound: bool,
+    tx: mpsc::UnboundedSender<Vec<u8>>,
+}
+
+/// Key in [`SentinelNode::peers`] for a peer reached over a circuit rather than directly.
+pub fn relay_key(node_id: &str) -> String {
+    format!("relay:{}", node_id)
+}
+
+impl SentinelNode {
+    /// Opens a circuit to `target` through the relay connected at `via`, then
+    /// runs Noise over it so both ends prove their identity to each other; the
+    /// relay only ever sees ciphertext. The circuit is then served like any peer.
+    pub async fn dial_via_relay(self: Arc<Self>, via: String, target: String) -> Result<()> {
+        public_key_from_node_id(&target)?;
+        let circuit = Uuid::new_v4();
+        let stream = self.circuit_end(circuit, target.clone(), via.clone(), false);
+        if !self.send_via(&via, MessageContent::CircuitOpen { circuit, target: target.clone() }) {
+            anyhow::bail!("Not connected to relay {}", via);
+        }
+
+        let noise = self.noise_handshake(NoiseTransport::initiate(stream, &self.identity)).await?;
+        let node_id = noise.remote_node_id().to_string();
+        if node_id != target {
+            anyhow::bail!("Circuit through {} reached {}, not {}", via, node_id, target);
+        }
+        let key = relay_key(&target);
+        let conn = self.admit_peer(Connection::new(noise), Some(node_id), &key, true).await?;
+        println!("Circuit to {} open through {}", target, via);
+        tokio::spawn(self.run_peer(conn, key, true));
+        Ok(())
+    }
+
+    /// Responds on a circuit `peer` opened to us and serves it until it closes.
+    async fn serve_circuit(self: Arc<Self>, stream: CircuitStream, peer: String) -> Result<()> {
+        let noise = self.noise_handshake(NoiseTransport::respond(stream, &self.identity)).await?;
+        let node_id = noise.remote_node_id().to_string();
+        if node_id != peer {
+            anyhow::bail!("Circuit opened by {} was answered by {}", peer, node_id);
+        }
+        let key = relay_key(&peer);
+        let conn = self.admit_peer(Connection::new(noise), Some(node_id), &key, false).await?;
+        println!("Circuit from {} open", peer);
+        self.run_peer(conn, key, false).await
+    }
+
+    /// Handles a request from `msg.sender`, connected at `addr`, to open a circuit
+    /// to `target`: accepts it if we are the target, and carries it if we relay.
+    pub(crate) fn handle_circuit_open(self: &Arc<Self>, msg: &SentinelMessage, circuit: Uuid, target: &str, addr: &str) {
+        let refuse = |reason: &str| {
+            self.send_via(addr, MessageContent::CircuitClose { circuit, reason: reason.to_string() });
+        };
+        if target == self.identity.node_id() {
+            if self.circuits.contains_key(&circuit) {
+                return;
+            }
+            let (mut inbound, mut from_sender) = (0, 0);
+            for end in self.circuits.iter().filter(|end| end.inbound) {
+                inbound += 1;
+                from_sender += usize::from(end.peer == msg.sender);
+            }
+            if inbound >= MAX_INBOUND_CIRCUITS {
+                return refuse("target has as many circuits open as it accepts");
+            }
+            if from_sender >= MAX_INBOUND_CIRCUITS_PER_PEER {
+                return refuse("target has as many circuits open from you as it accepts");
+            }
+            let stream = self.circuit_end(circuit, msg.sender.clone(), addr.to_string(), true);
+            let (node, peer) = (Arc::clone(self), msg.sender.clone());
+            tokio::spawn(async move {
+                if let Err(e) = node.serve_circuit(stream, peer.clone()).await {
+                    eprintln!("Circuit from {} failed: {:#}", peer, e);
+                }
+            });
+            return;
+        }
+
+        let Some(relay) = &self.relay else { return refuse("not a relay") };
+        if relay.circuits.contains_key(&circuit) {
+            return;
+        }
+        if relay.circuits.len() >= relay.limits.max_circuits {
+            return refuse("relay is carrying as many circuits as it may");
+        }
+        let Some(target_addr) = self.peer_ids.get(target).map(|addr| addr.clone()) else {
+            return refuse("target is not connected to the relay");
+        };
+
+        let rate = relay.limits.bytes_per_second as f64;
+        relay.circuits.insert(circuit, RelayedCircuit {
+            ends: [(msg.sender.clone(), addr.to_string()), (target.to_string(), target_addr.clone())],
+            budget: Mutex::new(Budget { bytes: rate, refilled: Instant::now() }),
+        });
+        if let Some(tx) = self.peers.get(&target_addr) {
+            let _ = tx.send(msg.clone());
+        }
+        self.metrics.circuit_relayed();
+
+        let node = Arc::clone(self);
+        let max_duration = relay.limits.max_duration;
+        tokio::spawn(async move {
+            tokio::time::sleep(max_duration).await;
+            node.close_relayed(circuit, None, "circuit reached its time limit");
+        });
+    }
+
+    /// Passes circuit traffic on if we relay the circuit, or hands it to our end of it.
+    pub(crate) async fn handle_circuit_data(&self, msg: &SentinelMessage, circuit: Uuid, data: &[u8], addr: &str) {
+        if let Some(relay) = &self.relay {
+            if let Some((to, wait)) = relay.route(&circuit, &msg.sender, addr, data.len()) {
+                if !wait.is_zero() {
+                    tokio::time::sleep(wait).await;
+                }
+                if let Some(tx) = self.peers.get(&to) {
+                    let _ = tx.send(msg.clone());
+                    self.metrics.relayed_bytes(data.len());
+                }
+                return;
+            }
+        }
+        if let Some(end) = self.circuits.get(&circuit) {
+            if end.peer == msg.sender && end.via == addr {
+                let _ = end.tx.send(data.to_vec());
+            }
+        }
+    }
+
+    /// Closes a circuit at the request of either end, or of the relay.
+    pub(crate) fn handle_circuit_close(&self, msg: &SentinelMessage, circuit: Uuid, reason: &str, addr: &str) {
+        let ours = self.circuits.get(&circuit).is_some_and(|end| end.via == addr);
+        if ours {
+            self.circuits.remove(&circuit);
+            println!("Circuit closed by {}: {}", msg.sender, reason);
+            return;
+        }
+        let from_end = self.relay.as_ref().and_then(|relay| relay.circuits.get(&circuit)).is_some_and(|relayed| {
+            relayed.ends.iter().any(|(node_id, end)| *node_id == msg.sender && end == addr)
+        });
+        if from_end {
+            self.close_relayed(circuit, Some(addr), reason);
+        }
+    }
+
+    /// Forgets every circuit that ran over the connection at `addr`, which has closed.
+    pub(crate) fn close_circuits_via(&self, addr: &str) {
+        self.circuits.retain(|_, end| end.via != addr);
+        if let Some(relay) = &self.relay {
+            let through: Vec<Uuid> = relay
+                .circuits
+                .iter()
+                .filter(|relayed| relayed.ends.iter().any(|(_, end)| end == addr))
+                .map(|relayed| *relayed.key())
+                .collect();
+            for circuit in through {
+                self.close_relayed(circuit, Some(addr), "other end disconnected");
+            }
+        }
+    }
+
+    /// Drops a relayed circuit and tells its ends, other than the one at `skip`.
+    fn close_relayed(&self, circuit: Uuid, skip: Option<&str>, reason: &str) {
+        let Some((_, relayed)) = self.relay.as_ref().and_then(|relay| relay.circuits.remove(&circuit)) else { return };
+        for (_, end) in relayed.ends.iter().filter(|(_, end)| Some(end.as_str()) != skip) {
+            self.send_via(end, MessageContent::CircuitClose { circuit, reason: reason.to_string() });
+        }
+    }
+
+    fn circuit_end(self: &Arc<Self>, circuit: Uuid, peer: String, via: String, inbound: bool) -> CircuitStream {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.circuits.insert(circuit, CircuitEnd { peer, via: via.clone(), inbound, tx });
+        CircuitStream { node: Arc::clone(self), circuit, via, rx, pending: Vec::new(), read: 0, closed: false }
+    }
+
+    /// Queues a message of ours to the peer connected at `addr`. False if there is none.
+    fn send_via(&self, addr: &str, content: MessageContent) -> bool {
+        self.peers.get(addr).is_some_and(|tx| tx.send(self.new_message(content)).is_ok())
+    }
+}
+
+/// A byte stream over a circuit, carried in [`MessageContent::CircuitData`]
+/// messages to and from the relay. It ends when either side or the relay closes it.
+pub struct CircuitStream {
+    node: Arc<SentinelNode>,
+    circuit: Uuid,
+    via: String,
+    rx: mpsc::UnboundedReceiver<Vec<u8>>,
+    pending: Vec<u8>,
+    read: usize,
+    closed: bool,
+}
+
+impl CircuitStream {
+    fn close(&mut self) {
+        if !self.closed {
+            self.closed = true;
+            self.node.circuits.remove(&self.circuit);
+            let reason = "closed by peer".to_string();
+            self.node.send_via(&self.via, MessageContent::CircuitClose { circuit: self.circuit, reason });
+        }
+    }
+}
+
+impl Drop for CircuitStream {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[async_trait]
+impl SentinelTransport for CircuitStream {
+    /// The relay's address, which is where the traffic really goes.
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.via
+            .parse()
+            .map_err(|_| io::Error::new(io::ErrorKind::Unsupported, format!("relay {} has no socket address", self.via)))
+    }
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+impl AsyncRead for CircuitStream {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        while self.read == self.pending.len() {
+            match self.rx.poll_recv(cx) {
+                Poll::Ready(Some(data)) => {
+                    self.pending = data;
+                    self.read = 0;
+                }
+                Poll::Ready(None) => return Poll::Ready(Ok(())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        let n = buf.remaining().min(self.pending.len() - self.read);
+        buf.put_slice(&self.pending[self.read..self.read + n]);
+        self.read += n;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for CircuitStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = buf.len().min(MAX_CHUNK);
+        let data = MessageContent::CircuitData { circuit: self.circuit, data: buf[..n].to_vec() };
+        if self.closed || !self.node.send_via(&self.via, data) {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NodeConfig;
+    use sentinel_crypto::{MemoryKeyStore, NodeIdentity};
+    use sentinel_transport::MemoryNetwork;
+
+    #[test]
+    fn test_route_holds_traffic_beyond_one_second_of_budget() {
+        let relay = Relay::new(RelayLimits { max_circuits: 1, max_duration: Duration::from_secs(60), bytes_per_second: 1000 });
+        let circuit = Uuid::new_v4();
+        let ends = [("a".to_string(), "10.0.0.2:1".to_string()), ("b".to_string(), "10.0.0.3:1".to_string())];
+        let budget = Mutex::new(Budget { bytes: 1000.0, refilled: Instant::now() });
+        relay.circuits.insert(circuit, RelayedCircuit { ends, budget });
+
+        let (to, wait) = relay.route(&circuit, "a", "10.0.0.2:1", 1000).unwrap();
+        assert_eq!(to, "10.0.0.3:1");
+        assert_eq!(wait, Duration::ZERO);
+        let (to, wait) = relay.route(&circuit, "b", "10.0.0.3:1", 500).unwrap();
+        assert_eq!(to, "10.0.0.2:1");
+        assert!(wait > Duration::from_millis(450) && wait <= Duration::from_millis(500), "{:?}", wait);
+        // Only the ends, each from its own connection, may use the circuit.
+        assert!(relay.route(&circuit, "a", "10.0.0.3:1", 1).is_none());
+        assert!(relay.route(&circuit, "c", "10.0.0.4:1", 1).is_none());
+    }
+
+    #[test]
+    fn test_route_with_zero_rate_is_unthrottled() {
+        let relay = Relay::new(RelayLimits { max_circuits: 1, max_duration: Duration::from_secs(60), bytes_per_second: 0 });
+        let circuit = Uuid::new_v4();
+        let ends = [("a".to_string(), "10.0.0.2:1".to_string()), ("b".to_string(), "10.0.0.3:1".to_string())];
+        let budget = Mutex::new(Budget { bytes: 0.0, refilled: Instant::now() });
+        relay.circuits.insert(circuit, RelayedCircuit { ends, budget });
+
+        for _ in 0..3 {
+            let (_, wait) = relay.route(&circuit, "a", "10.0.0.2:1", MAX_CHUNK).unwrap();
+            assert_eq!(wait, Duration::ZERO);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inbound_circuits_are_capped_per_peer_and_overall() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = Arc::new(SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap());
+        let target = node.identity.node_id();
+        let open = |sender: &str| {
+            let circuit = Uuid::new_v4();
+            let msg = SentinelMessage::new(sender.to_string(), MessageContent::CircuitOpen { circuit, target: target.clone() });
+            node.handle_circuit_open(&msg, circuit, &target, "10.0.0.9:8443");
+            node.circuits.contains_key(&circuit)
+        };
+
+        let senders: Vec<String> = (0..MAX_INBOUND_CIRCUITS).map(|_| NodeIdentity::generate().node_id()).collect();
+        for _ in 0..MAX_INBOUND_CIRCUITS_PER_PEER {
+            assert!(open(&senders[0]));
+        }
+        assert!(!open(&senders[0]));
+
+        // Other peers fill the remaining slots, after which everyone is refused.
+        let (fill, late) = senders[1..].split_at(MAX_INBOUND_CIRCUITS - MAX_INBOUND_CIRCUITS_PER_PEER);
+        assert!(fill.iter().all(|sender| open(sender)));
+        assert!(!open(&late[0]));
+        assert_eq!(node.circuits.len(), MAX_INBOUND_CIRCUITS);
+    }
+}
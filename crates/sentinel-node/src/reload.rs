@@ -0,0 +1,158 @@
+use crate::engine::SentinelNode;
+use anyhow::Result;
+use sentinel_transport::{SentinelAcceptor, TlsCredentials};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// How often `node.crt`, `node.key` and the CA bundle are checked for changes.
+pub const CERT_POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+impl SentinelNode {
+    /// Re-reads `node.crt`, `node.key` and, under `--ca`, the CA bundle once, then
+    /// uses them for every later handshake in either direction: the acceptor, the
+    /// connector, QUIC and the acceptors added with [`reload_with`](Self::reload_with).
+    /// Every configuration is built before any is swapped in, so if the files don't
+    /// load, no longer carry this node's identity or don't suit one of them, all keep
+    /// the certificates in use. Open connections are unaffected.
+    pub fn reload_certificates(&self) -> Result<()> {
+        let credentials = self.tls_credentials()?;
+        let server = self.acceptor.prepare(&credentials)?;
+        let client = self.connector.prepare(&credentials)?;
+        let quic = self.quic.as_ref().map(|quic| quic.prepare(&server)).transpose()?;
+        let extra_acceptors = self.extra_acceptors.lock().unwrap();
+        let extra = extra_acceptors
+            .iter()
+            .map(|acceptor| acceptor.prepare(&credentials))
+            .collect::<Result<Vec<_>>>()?;
+
+        self.acceptor.install(server);
+        self.connector.install(client);
+        if let (Some(endpoint), Some(config)) = (&self.quic, quic) {
+            endpoint.install(config);
+        }
+        for (acceptor, config) in extra_acceptors.iter().zip(extra) {
+            acceptor.install(config);
+        }
+        Ok(())
+    }
+
+    /// The credentials [`reload_certificates`](Self::reload_certificates) would switch to.
+    pub fn tls_credentials(&self) -> Result<TlsCredentials> {
+        Self::load_credentials(&self.identity, &self.cert_path, &self.key_path, self.ca_path.as_deref())
+    }
+
+    /// Reloads `acceptor` whenever the node's own certificates are reloaded.
+    pub fn reload_with(&self, acceptor: &SentinelAcceptor) {
+        self.extra_acceptors.lock().unwrap().push(acceptor.clone());
+    }
+
+    /// Reloads the certificates on SIGHUP, and whenever one of their files changes.
+    pub async fn watch_certificates(self: Arc<Self>) {
+        let files: Vec<PathBuf> =
+            [Some(self.cert_path.clone()), Some(self.key_path.clone()), self.ca_path.clone()].into_iter().flatten().collect();
+        let mut modified = modification_times(&files);
+        let mut poll = tokio::time::interval(CERT_POLL_INTERVAL);
+        #[cfg(unix)]
+        let mut hangup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                eprintln!("SIGHUP reloads are off: {}", e);
+                None
+            }
+        };
+
+        loop {
+            #[cfg(unix)]
+            let hangup = async {
+                match hangup.as_mut() {
+                    Some(signal) => signal.recv().await,
+                    None => std::future::pending().await,
+                }
+            };
+            #[cfg(not(unix))]
+            let hangup = std::future::pending::<Option<()>>();
+
+            let trigger = tokio::select! {
+                _ = hangup => "SIGHUP",
+                _ = poll.tick() => {
+                    let now = modification_times(&files);
+                    if now == modified {
+                        continue;
+                    }
+                    modified = now;
+                    "file change"
+                }
+            };
+            match self.reload_certificates() {
+                Ok(()) => println!("Reloaded TLS certificates ({})", trigger),
+                Err(e) => eprintln!("Kept current TLS certificates ({}): {:#}", trigger, e),
+            }
+        }
+    }
+}
+
+fn modification_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files.iter().map(|file| std::fs::metadata(file).and_then(|meta| meta.modified()).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::{NodeConfig, HANDSHAKE_TIMEOUT};
+    use rustls::pki_types::CertificateDer;
+    use sentinel_crypto::cert::NODE_DNS_NAME;
+    use sentinel_crypto::{KeyStore, MemoryKeyStore, NodeIdentity};
+    use sentinel_transport::{peer_certificate, ClientAuth, MemoryNetwork, SentinelConnector};
+
+    #[tokio::test]
+    async fn test_reload_keeps_certificates_that_do_not_fit() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap();
+        assert!(!node.key_path.exists(), "derived TLS keys stay in memory");
+        node.reload_certificates().unwrap();
+
+        let stranger = MemoryKeyStore::new().load_or_generate().unwrap();
+        stranger.self_signed_certificate().unwrap().save(&node.cert_path, &node.key_path).unwrap();
+        let error = node.reload_certificates().unwrap_err();
+        assert!(error.to_string().contains("was not issued for node"), "{}", error);
+
+        std::fs::write(&node.key_path, "not a key").unwrap();
+        node.identity.self_signed_certificate().unwrap().save(&node.cert_path, dir.path().join("unused.key")).unwrap();
+        assert!(node.reload_certificates().is_err());
+
+        node.identity.self_signed_certificate().unwrap().save(&node.cert_path, &node.key_path).unwrap();
+        node.reload_certificates().unwrap();
+    }
+
+    /// The certificate `acceptor` presents to a dialing node.
+    async fn served(acceptor: &SentinelAcceptor) -> CertificateDer<'static> {
+        let (client, server) = tokio::io::duplex(16 * 1024);
+        let dialer = NodeIdentity::generate();
+        let connector = SentinelConnector::from_credentials(&TlsCredentials::from_identity(&dialer).unwrap()).unwrap();
+        let (tls, _) = tokio::join!(connector.connect(NODE_DNS_NAME, client), acceptor.accept(server));
+        peer_certificate(&tls.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_reload_switches_every_consumer_or_none() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap();
+        let derived = served(&node.acceptor).await;
+
+        // An acceptor requiring client certificates from a CA can't take credentials
+        // without one, so the reload fails and the node's own acceptor must not switch.
+        let supplied = node.identity.self_signed_certificate().unwrap();
+        supplied.save(&node.cert_path, &node.key_path).unwrap();
+        let with_ca = TlsCredentials::from_files(&node.cert_path, &node.key_path).unwrap().with_ca(&node.cert_path).unwrap();
+        node.reload_with(&SentinelAcceptor::from_credentials(&with_ca, ClientAuth::Ca, HANDSHAKE_TIMEOUT).unwrap());
+        assert!(node.reload_certificates().is_err());
+        assert_eq!(served(&node.acceptor).await, derived);
+
+        node.extra_acceptors.lock().unwrap().clear();
+        node.reload_certificates().unwrap();
+        assert_eq!(served(&node.acceptor).await.as_ref(), &supplied.cert_der[..]);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::engine::SentinelNode;
+use anyhow::{Context, Result};
+use sentinel_protocol::messages::MessageContent;
+use sentinel_transport::punch::{connect_reusable, simultaneous_open};
+use sentinel_transport::{BoxedTransport, RawTcpTransport};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Time both ends spend opening a punched connection before relaying instead.
+pub const PUNCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A publicly reachable peer that introduces NATed peers to each other.
+#[derive(Debug, Clone)]
+pub struct Rendezvous {
+    pub node_id: String,
+    pub addr: String,
+    /// Port our connection to it leaves from, which its NAT mapping, and so the
+    /// address it tells others, belongs to. `None` on an in-memory network.
+    pub local_port: Option<u16>,
+}
+
+impl SentinelNode {
+    /// Dials `addr`, which must prove `node_id`, from a port that later hole
+    /// punches can share, and makes it this node's rendezvous.
+    pub async fn dial_rendezvous(self: Arc<Self>, node_id: String, addr: String) -> Result<()> {
+        let (stream, local_port): (BoxedTransport, _) = match &self.network {
+            Some(_) => (self.open_stream(&addr).await?, None),
+            None => {
+                if self.connector.proxy().is_some() {
+                    anyhow::bail!("Hole punching needs direct TCP, so a rendezvous can't be dialed through a proxy");
+                }
+                let remote = tokio::net::lookup_host(&addr)
+                    .await?
+                    .next()
+                    .with_context(|| format!("{} did not resolve", addr))?;
+                let stream = connect_reusable(remote).await?;
+                let local_port = stream.local_addr()?.port();
+                (Box::new(RawTcpTransport::new(stream)), Some(local_port))
+            }
+        };
+        *self.rendezvous.lock().unwrap() = Some(Rendezvous { node_id: node_id.clone(), addr: addr.clone(), local_port });
+        self.dial_over(stream, Some(node_id), addr).await
+    }
+
+    /// Asks the rendezvous to introduce this node to `target`. Both are then
+    /// told each other's address and try to connect; if that fails, they open
+    /// a circuit through the rendezvous, which must be a relay.
+    pub async fn request_punch(&self, target: &str) -> Result<()> {
+        let rendezvous = self.rendezvous.lock().unwrap().clone().context("No rendezvous; start with --rendezvous")?;
+        if !self.peers.contains_key(&rendezvous.addr) {
+            anyhow::bail!("Not connected to rendezvous {}", rendezvous.addr);
+        }
+        self.send_to_peer(&rendezvous.addr, MessageContent::PunchRequest { target: target.to_string() }).await
+    }
+
+    /// Rendezvous side: tells `requester` and `target` where the other was seen,
+    /// at the same moment so their attempts overlap.
+    pub(crate) async fn introduce(&self, requester: &str, requester_addr: &str, target: &str) {
+        let Some(target_addr) = self.peer_ids.get(target).map(|addr| addr.clone()) else {
+            eprintln!("Cannot introduce {} to {}: not connected", requester, target);
+            return;
+        };
+        let offer = |peer: &str, address: &str| MessageContent::PunchOffer { peer: peer.to_string(), address: address.to_string() };
+        let _ = self.send_to_peer(&target_addr, offer(requester, requester_addr)).await;
+        let _ = self.send_to_peer(requester_addr, offer(target, &target_addr)).await;
+    }
+
+    /// Starts a hole punch towards `peer` if the offer came from our rendezvous,
+    /// falling back to a circuit through it.
+    pub(crate) fn accept_punch_offer(self: &Arc<Self>, sender: &str, peer: &str, address: &str) {
+        let Some(rendezvous) = self.rendezvous.lock().unwrap().clone() else { return };
+        if sender != rendezvous.node_id || peer == self.identity.node_id() || self.peer_ids.contains_key(peer) {
+            return;
+        }
+        let node = Arc::clone(self);
+        let (peer, address) = (peer.to_string(), address.to_string());
+        tokio::spawn(async move {
+            println!("Punching through to {} at {}", peer, address);
+            let Err(e) = Arc::clone(&node).punch(peer.clone(), address, rendezvous.local_port).await else { return };
+            // Both ends give up at about the same time; only the one that would
+            // have dialed opens a circuit, so there is just one.
+            if node.identity.node_id() > peer {
+                return;
+            }
+            println!("No direct path to {} ({:#}); opening a circuit through {}", peer, e, rendezvous.addr);
+            if let Err(e) = node.dial_via_relay(rendezvous.addr, peer.clone()).await {
+                eprintln!("Circuit to {} failed: {:#}", peer, e);
+            }
+        });
+    }
+
+    async fn punch(self: Arc<Self>, peer: String, address: String, local_port: Option<u16>) -> Result<()> {
+        let local_port = local_port.context("hole punching needs a TCP rendezvous")?;
+        let remote: SocketAddr = address.parse().with_context(|| format!("{} is not an IP address", address))?;
+        let stream = RawTcpTransport::new(simultaneous_open(local_port, remote, PUNCH_TIMEOUT).await?);
+        // Both ends now hold the same connection; the lower node ID secures it as the dialer.
+        if self.identity.node_id() < peer {
+            self.dial_over(stream, Some(peer), address).await
+        } else {
+            tokio::spawn(async move {
+                if let Err(e) = self.accept_connection(stream, address.clone()).await {
+                    eprintln!("Punched connection from {} failed: {}", address, e);
+                }
+            });
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use crate::engine::SentinelNode;
+use anyhow::Result;
+use sentinel_crypto::SuccessionRecord;
+use sentinel_protocol::messages::{MessageContent, SentinelMessage};
+
+const SUCCESSORS_TREE: &str = "successors";
+const PREDECESSORS_TREE: &str = "predecessors";
+
+/// Stores a verified record. Returns `false` if it was already known.
+///
+/// A second, different successor for the same old key means the old key
+/// signed two rotations, which only happens if it leaked; such records are refused.
+pub fn record_succession(db: &sled::Db, record: &SuccessionRecord) -> Result<bool> {
+    record.verify()?;
+
+    let successors = db.open_tree(SUCCESSORS_TREE)?;
+    let predecessors = db.open_tree(PREDECESSORS_TREE)?;
+    let old_id = record.old_node_id();
+    let new_id = record.new_node_id();
+    let bytes = record.to_bytes();
+
+    if let Some(existing) = successors.get(&old_id)? {
+        if existing.as_ref() == bytes.as_slice() {
+            return Ok(false);
+        }
+        let existing = SuccessionRecord::from_bytes(&existing)?;
+        anyhow::bail!(
+            "Conflicting succession for {}: already continued by {}, refusing {}",
+            old_id,
+            existing.new_node_id(),
+            new_id
+        );
+    }
+
+    successors.insert(&old_id, bytes)?;
+    predecessors.insert(&new_id, old_id.as_bytes())?;
+    Ok(true)
+}
+
+/// Records leading up to `node_id`, oldest first.
+pub fn lineage(db: &sled::Db, node_id: &str) -> Result<Vec<SuccessionRecord>> {
+    let successors = db.open_tree(SUCCESSORS_TREE)?;
+    let predecessors = db.open_tree(PREDECESSORS_TREE)?;
+
+    let mut chain = Vec::new();
+    let mut current = node_id.to_string();
+    // Bounded by the number of stored records so a corrupt store can't loop forever.
+    for _ in 0..=predecessors.len() {
+        let Some(old_id) = predecessors.get(&current)? else { break };
+        let old_id = String::from_utf8(old_id.to_vec())?;
+        if let Some(bytes) = successors.get(&old_id)? {
+            chain.push(SuccessionRecord::from_bytes(&bytes)?);
+        }
+        current = old_id;
+    }
+    chain.reverse();
+    Ok(chain)
+}
+
+/// The first identity in `node_id`'s succession chain, or `node_id` itself.
+pub fn identity_root(db: &sled::Db, node_id: &str) -> Result<String> {
+    Ok(lineage(db, node_id)?
+        .first()
+        .map(|record| record.old_node_id())
+        .unwrap_or_else(|| node_id.to_string()))
+}
+
+/// Whether `node_id` took over from `predecessor` through one or more recorded rotations.
+pub fn continues(db: &sled::Db, node_id: &str, predecessor: &str) -> Result<bool> {
+    Ok(lineage(db, node_id)?.iter().any(|record| record.old_node_id() == predecessor))
+}
+
+impl SentinelNode {
+    pub fn handle_succession(&self, msg: &SentinelMessage, bytes: &[u8], from_addr: &str) -> Result<()> {
+        let record = SuccessionRecord::from_bytes(bytes)?;
+        if !record_succession(&self.db, &record)? {
+            return Ok(());
+        }
+
+        println!(
+            "Identity rotation: {} is now {}",
+            record.old_node_id(),
+            record.new_node_id()
+        );
+
+        for peer in self.peers.iter() {
+            if peer.key() != from_addr {
+                let _ = peer.value().send(msg.clone());
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends every record in our own chain so a new peer can link us to old IDs.
+    pub async fn announce_successions(&self, addr: &str) -> Result<()> {
+        for record in lineage(&self.db, &self.identity.node_id())? {
+            self.send_to_peer(addr, MessageContent::KeySuccession(record.to_bytes())).await?;
+        }
+        Ok(())
+    }
+
+    /// Display name for a sender, noting the identity it continues if it rotated.
+    pub fn describe_sender(&self, node_id: &str) -> String {
+        match identity_root(&self.db, node_id) {
+            Ok(root) if root != node_id => format!("{} (continues {})", node_id, root),
+            _ => node_id.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::NodeConfig;
+    use sentinel_crypto::{MemoryKeyStore, NodeIdentity};
+    use sentinel_transport::MemoryNetwork;
+
+    fn temporary_db() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    #[test]
+    fn test_conflicting_succession_refused() {
+        let db = temporary_db();
+        let old = NodeIdentity::generate();
+        let (_, record) = old.rotate();
+        let (_, rival) = old.rotate();
+
+        assert!(record_succession(&db, &record).unwrap());
+        assert!(!record_succession(&db, &record).unwrap());
+        assert!(record_succession(&db, &rival).is_err());
+        // The first record stands.
+        assert_eq!(lineage(&db, &record.new_node_id()).unwrap(), vec![record.clone()]);
+        assert!(lineage(&db, &rival.new_node_id()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_lineage_across_two_rotations() {
+        let db = temporary_db();
+        let first = NodeIdentity::generate();
+        let (second, first_record) = first.rotate();
+        let (third, second_record) = second.rotate();
+        // Records may arrive in any order.
+        record_succession(&db, &second_record).unwrap();
+        record_succession(&db, &first_record).unwrap();
+
+        assert_eq!(
+            lineage(&db, &third.node_id()).unwrap(),
+            vec![first_record, second_record.clone()]
+        );
+        assert_eq!(lineage(&db, &second.node_id()).unwrap().len(), 1);
+        assert!(lineage(&db, &first.node_id()).unwrap().is_empty());
+
+        assert_eq!(identity_root(&db, &third.node_id()).unwrap(), first.node_id());
+        assert_eq!(identity_root(&db, &second.node_id()).unwrap(), first.node_id());
+        assert_eq!(identity_root(&db, &first.node_id()).unwrap(), first.node_id());
+
+        assert!(continues(&db, &third.node_id(), &first.node_id()).unwrap());
+        assert!(continues(&db, &third.node_id(), &second.node_id()).unwrap());
+        assert!(!continues(&db, &first.node_id(), &third.node_id()).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_handle_succession_rejects_foreign_signature() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = NodeConfig::for_test(dir.path(), &MemoryNetwork::new());
+        let node = SentinelNode::new(config, &MemoryKeyStore::new()).await.unwrap();
+
+        // An attacker signs over a victim's key to their own.
+        let victim = NodeIdentity::generate();
+        let attacker = NodeIdentity::generate();
+        let mut bytes = attacker.succession_to(&attacker.public_key()).to_bytes();
+        bytes[..32].copy_from_slice(victim.public_key().as_bytes());
+        let msg = node.new_message(MessageContent::KeySuccession(bytes.clone()));
+
+        assert!(node.handle_succession(&msg, &bytes, "10.0.0.9:8443").is_err());
+        assert_eq!(identity_root(&node.db, &attacker.node_id()).unwrap(), attacker.node_id());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::engine::{SentinelNode, HANDSHAKE_TIMEOUT};
+use anyhow::Result;
+use sentinel_transport::{Connection, RawTcpTransport, SentinelAcceptor, WebSocketAcceptor};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::{TcpListener, TcpStream};
+
+impl SentinelNode {
+    /// Accepts WebSocket peers (e.g. browsers) on `addr`. With `tls`, connections
+    /// are `wss://` using the node certificate; clients need no certificate.
+    pub async fn serve_websocket(self: Arc<Self>, addr: SocketAddr, tls: Option<SentinelAcceptor>) -> Result<()> {
+        let listener = TcpListener::bind(addr).await?;
+        let scheme = if tls.is_some() { "wss" } else { "ws" };
+        println!("WebSocket peers on {}://{}", scheme, addr);
+
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let Some(permit) = self.admit_inbound(peer) else { continue };
+            let node = Arc::clone(&self);
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let _permit = permit;
+                if let Err(e) = node.accept_websocket(stream, peer.to_string(), tls).await {
+                    eprintln!("WebSocket connection from {} failed: {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Upgrades the connection, then admits and serves it like any other inbound peer.
+    async fn accept_websocket(self: Arc<Self>, stream: TcpStream, addr: String, tls: Option<SentinelAcceptor>) -> Result<()> {
+        let websocket = WebSocketAcceptor::new(HANDSHAKE_TIMEOUT);
+        match tls {
+            Some(acceptor) => {
+                let tls = acceptor.accept(RawTcpTransport::new(stream)).await?;
+                let ws = websocket.accept(tls).await?;
+                let conn = self.admit_peer(Connection::new(ws), None, &addr, false).await?;
+                self.run_peer(conn, addr, false).await
+            }
+            None => {
+                let ws = websocket.accept(RawTcpTransport::new(stream)).await?;
+                let conn = self.admit_peer(Connection::new(ws), None, &addr, false).await?;
+                self.run_peer(conn, addr, false).await
+            }
+        }
+    }
+}
+
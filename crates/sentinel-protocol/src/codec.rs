@@ -3,6 +3,7 @@ use bytes::BytesMut;
 use crate::frame::Frame;
 use crate::error::ProtocolError;
 
+#[derive(Default)]
 pub struct SentinelCodec;
 
 impl SentinelCodec {
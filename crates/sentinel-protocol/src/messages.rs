@@ -1,14 +1,16 @@
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use std::net::SocketAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct PeerInfo {
     pub node_id: String,
-    pub address: SocketAddr,
+    /// `host:port`; the host may be an IP address or a Tor `.onion` name.
+    pub address: String,
     pub node_name: String,
     pub last_seen: u64,
+    /// Secure channel the node asks to be dialed over (`tls` or `noise`), if it said.
+    pub channel: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -19,8 +21,73 @@ pub enum MessageContent {
         node_name: String 
     },
     PeerDiscovery(Vec<PeerInfo>),
+    /// Encoded `sentinel_crypto::SuccessionRecord`, flooded to every peer.
+    KeySuccession(Vec<u8>),
+    /// Roster of a group channel, published by its owner. `revision` orders
+    /// updates; `epoch` advances when a member is removed and retires every
+    /// sender key of the previous epoch.
+    GroupMembership {
+        group: String,
+        revision: u64,
+        epoch: u64,
+        members: Vec<String>,
+    },
+    /// The sender's group chain key for `epoch`, sealed to `recipient`.
+    GroupKey {
+        group: String,
+        epoch: u64,
+        recipient: String,
+        sealed: Vec<u8>,
+    },
+    /// Group chat encrypted with the sender's chain for `epoch`. Relayed by
+    /// every node, readable only by members.
+    GroupMessage {
+        group: String,
+        epoch: u64,
+        iteration: u32,
+        ciphertext: Vec<u8>,
+    },
     Ping,
     Pong,
+    /// Asks a rendezvous peer to introduce the sender to node `target`, so the
+    /// two can punch a connection through their NATs.
+    PunchRequest { target: String },
+    /// From a rendezvous peer: node `peer` was seen at `address` and is being
+    /// told about the recipient at the same moment; both should open now.
+    PunchOffer { peer: String, address: String },
+    /// Opens circuit `circuit` to node `target` through a relay. The relay
+    /// passes the request on unchanged, so the target learns who asked.
+    CircuitOpen { circuit: Uuid, target: String },
+    /// Opaque traffic on a circuit, passed on by the relay to the other end.
+    CircuitData { circuit: Uuid, data: Vec<u8> },
+    /// Closes a circuit, from either end or from the relay.
+    CircuitClose { circuit: Uuid, reason: String },
+}
+
+impl MessageContent {
+    /// Whether a recipient that handles this message again ends up as it was
+    /// after the first time. Only these may travel as TLS 0-RTT data, which an
+    /// eavesdropper can replay.
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            // Stored by message ID, or ordered by revision and epoch.
+            MessageContent::Chat(_)
+            | MessageContent::KeySuccession(_)
+            | MessageContent::GroupMembership { .. }
+            | MessageContent::GroupKey { .. }
+            | MessageContent::GroupMessage { .. }
+            | MessageContent::Ping
+            | MessageContent::Pong => true,
+            // These make the recipient dial, introduce or carry traffic.
+            MessageContent::Handshake { .. }
+            | MessageContent::PeerDiscovery(_)
+            | MessageContent::PunchRequest { .. }
+            | MessageContent::PunchOffer { .. }
+            | MessageContent::CircuitOpen { .. }
+            | MessageContent::CircuitData { .. }
+            | MessageContent::CircuitClose { .. } => false,
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -29,6 +96,8 @@ pub struct SentinelMessage {
     pub sender: String,     
     pub timestamp: u64,     
     pub content: MessageContent,
+    /// Ed25519 signature by `sender` over [`SentinelMessage::signing_bytes`]; empty if unsigned.
+    pub signature: Vec<u8>,
 }
 
 impl SentinelMessage {
@@ -41,9 +110,16 @@ impl SentinelMessage {
                 .unwrap_or_default()
                 .as_secs(),
             content,
+            signature: Vec::new(),
         }
     }
 
+    /// The bytes covered by `signature`: every field except the signature itself.
+    pub fn signing_bytes(&self) -> Vec<u8> {
+        bincode::serialize(&(&self.id, &self.sender, self.timestamp, &self.content))
+            .expect("Serialization failed")
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         bincode::serialize(self).expect("Serialization failed")
     }
@@ -1,50 +1,378 @@
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
+use std::net::IpAddr;
 use std::path::Path;
-use std::time::Duration;
-use tokio::net::TcpStream;
+use std::time::{Duration, Instant};
+use std::io::Read;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio_rustls::TlsAcceptor;
-use tokio_rustls::rustls::ServerConfig;
+use anyhow::Context;
+use tokio_rustls::rustls::{HandshakeKind, ServerConfig};
+use tokio_rustls::rustls::server::{ServerSessionMemoryCache, WebPkiClientVerifier};
 
 use crate::tls::TlsTransport;
-use crate::tls_config::{load_certs, load_private_key};
+use crate::tls_config::{crypto_provider, CredentialFiles, TlsCredentials};
+use crate::verifier::NodeIdClientVerifier;
 use crate::error::{TransportError, TransportResult};
+use crate::limits::{ConnectionGate, ConnectionLimits, ConnectionPermit, Refusal};
+use crate::metrics::TransportMetrics;
+
+/// ALPN protocol spoken by Sentinel peers, over TLS and QUIC alike.
+pub const ALPN_PROTOCOL: &[u8] = b"sentinel-v1";
+
+/// Sessions an acceptor remembers for clients to resume. Each resumption uses
+/// up its ticket, so 0-RTT data cannot be replayed to this server while the
+/// session is cached.
+const SESSION_CACHE_SIZE: usize = 1024;
+
+/// How an acceptor authenticates clients.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClientAuth {
+    None,
+    /// Certificates chaining to the CA bundle in the acceptor's [`TlsCredentials`].
+    Ca,
+    /// Identity certificates carrying a node ID.
+    NodeId,
+}
+
+/// Everything but the credentials an acceptor's configuration is built from.
+#[derive(Clone)]
+struct ServerSource {
+    /// `None` if the credentials were handed over in memory.
+    files: Option<CredentialFiles>,
+    client_auth: ClientAuth,
+    max_early_data_size: u32,
+}
+
+impl ServerSource {
+    fn build(&self, credentials: &TlsCredentials) -> anyhow::Result<ServerConfig> {
+        let provider = crypto_provider();
+
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()?;
+        let builder = match self.client_auth {
+            ClientAuth::Ca => {
+                let roots = credentials.roots().context("Mutual TLS needs a CA bundle")?;
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots, provider).build()?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            ClientAuth::NodeId => builder.with_client_cert_verifier(Arc::new(NodeIdClientVerifier::new(provider))),
+            ClientAuth::None => builder.with_no_client_auth(),
+        };
+        let mut config = builder.with_cert_resolver(credentials.resolver());
+
+        config.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        config.session_storage = ServerSessionMemoryCache::new(SESSION_CACHE_SIZE);
+        config.max_early_data_size = self.max_early_data_size;
+        Ok(config)
+    }
+}
+
+/// A server configuration built by [`SentinelAcceptor::prepare`] and not yet in use.
+pub struct PreparedServerConfig(Arc<ServerConfig>);
+
+impl PreparedServerConfig {
+    pub(crate) fn config(&self) -> &ServerConfig {
+        &self.0
+    }
+}
 
 #[derive(Clone)]
 pub struct SentinelAcceptor {
-    inner: TlsAcceptor,
+    source: ServerSource,
+    /// Shared by clones, so a reload reaches every listener using this acceptor.
+    config: Arc<RwLock<Arc<ServerConfig>>>,
     handshake_timeout: Duration,
+    metrics: Arc<TransportMetrics>,
+    gate: Arc<ConnectionGate>,
 }
 
 impl SentinelAcceptor {
+    /// Server-authenticated TLS; clients are not asked for a certificate.
     pub fn new(
         cert_path: &Path,
         key_path: &Path,
         handshake_timeout: Duration,
     ) -> anyhow::Result<Self> {
-        let certs = load_certs(cert_path)?;
-        let key = load_private_key(key_path)?;
+        Self::from_files(cert_path, key_path, None, ClientAuth::None, handshake_timeout)
+    }
 
-        let mut config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)?;
+    /// Mutual TLS: clients must present a certificate chaining to a CA in `ca_path`.
+    pub fn new_mtls(
+        cert_path: &Path,
+        key_path: &Path,
+        ca_path: &Path,
+        handshake_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        Self::from_files(cert_path, key_path, Some(ca_path), ClientAuth::Ca, handshake_timeout)
+    }
 
-        config.alpn_protocols = vec![b"sentinel-v1".to_vec()];
+    /// Mutual TLS without a CA: clients must present an identity certificate,
+    /// and their node ID is available from [`SentinelTransport::peer_node_id`](crate::SentinelTransport::peer_node_id).
+    pub fn new_node_auth(
+        cert_path: &Path,
+        key_path: &Path,
+        handshake_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        Self::from_files(cert_path, key_path, None, ClientAuth::NodeId, handshake_timeout)
+    }
+
+    /// An acceptor presenting `credentials`, which nothing is re-read from:
+    /// [`reload`](Self::reload) keeps them, and [`replace`](Self::replace) swaps them.
+    pub fn from_credentials(
+        credentials: &TlsCredentials,
+        client_auth: ClientAuth,
+        handshake_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let source = ServerSource { files: None, client_auth, max_early_data_size: 0 };
+        Self::build(source, credentials, handshake_timeout)
+    }
+
+    fn from_files(
+        cert_path: &Path,
+        key_path: &Path,
+        ca_path: Option<&Path>,
+        client_auth: ClientAuth,
+        handshake_timeout: Duration,
+    ) -> anyhow::Result<Self> {
+        let files = CredentialFiles::new(cert_path, key_path, ca_path);
+        let credentials = files.load()?;
+        let source = ServerSource { files: Some(files), client_auth, max_early_data_size: 0 };
+        Self::build(source, &credentials, handshake_timeout)
+    }
+
+    fn build(source: ServerSource, credentials: &TlsCredentials, handshake_timeout: Duration) -> anyhow::Result<Self> {
+        let config = source.build(credentials)?;
 
         Ok(Self {
-            inner: TlsAcceptor::from(Arc::new(config)),
+            source,
+            config: Arc::new(RwLock::new(Arc::new(config))),
             handshake_timeout,
+            metrics: Arc::default(),
+            gate: Arc::new(ConnectionGate::new(ConnectionLimits::default())),
         })
     }
 
-    pub async fn accept(&self, stream: TcpStream) -> TransportResult<TlsTransport<TcpStream>> {
-        let handshake_future = self.inner.accept(stream);
-        
-        match tokio::time::timeout(self.handshake_timeout, handshake_future).await {
-            Ok(result) => {
-                let tls_stream = result.map_err(TransportError::Tls)?;
-                Ok(TlsTransport::new(tls_stream.into()))
-            }
+    /// Re-reads the certificate, key and CA bundle, then swaps them in for every
+    /// later handshake. Established connections are unaffected. If anything fails
+    /// to load, the current configuration stays in place. Acceptors built
+    /// [`from_credentials`](Self::from_credentials) have nothing to re-read.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        match &self.source.files {
+            Some(files) => self.replace(&files.load()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Presents `credentials` in every later handshake. Established connections are
+    /// unaffected. If no configuration can be built from them, the current one stays.
+    pub fn replace(&self, credentials: &TlsCredentials) -> anyhow::Result<()> {
+        self.install(self.prepare(credentials)?);
+        Ok(())
+    }
+
+    /// Builds the configuration [`replace`](Self::replace) would switch to, without
+    /// switching. Lets a caller check several consumers can take new credentials
+    /// before [`install`](Self::install)ing them in any.
+    pub fn prepare(&self, credentials: &TlsCredentials) -> anyhow::Result<PreparedServerConfig> {
+        Ok(PreparedServerConfig(Arc::new(self.source.build(credentials)?)))
+    }
+
+    /// Uses a configuration from [`prepare`](Self::prepare) for every later handshake.
+    pub fn install(&self, prepared: PreparedServerConfig) {
+        *self.config.write().unwrap() = prepared.0;
+    }
+
+    /// Enforces `limits` in [`admit`](Self::admit). Clones of this acceptor share them.
+    pub fn with_limits(mut self, limits: ConnectionLimits) -> Self {
+        self.gate = Arc::new(ConnectionGate::new(limits));
+        self
+    }
+
+    /// Accepts up to `max_bytes` of 0-RTT data from clients resuming a session,
+    /// available from [`TlsTransport::take_early_data`]. Off by default.
+    pub fn with_early_data(mut self, max_bytes: u32) -> Self {
+        self.source.max_early_data_size = max_bytes;
+        let mut config = (*self.config()).clone();
+        config.max_early_data_size = max_bytes;
+        self.config = Arc::new(RwLock::new(Arc::new(config)));
+        self
+    }
+
+    /// Records handshakes and accepted connections into `metrics` instead of private counters.
+    pub fn with_metrics(mut self, metrics: Arc<TransportMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<TransportMetrics> {
+        &self.metrics
+    }
+
+    /// The server configuration, ALPN and client authentication included.
+    pub(crate) fn config(&self) -> Arc<ServerConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// How long [`accept`](Self::accept) waits for a handshake.
+    pub(crate) fn handshake_timeout(&self) -> Duration {
+        self.handshake_timeout
+    }
+
+    /// Checks a new connection from `peer` against the limits, before anything is read
+    /// from it. Keep the permit until the connection closes; it frees the connection's slot.
+    pub fn admit(&self, peer: IpAddr) -> Result<ConnectionPermit, Refusal> {
+        self.gate.admit(peer).inspect_err(|_| self.metrics.record_refusal())
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> TransportResult<TlsTransport<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let started = Instant::now();
+        let handshake_future = TlsAcceptor::from(self.config()).accept(stream);
+
+        let result = match tokio::time::timeout(self.handshake_timeout, handshake_future).await {
+            Ok(result) => result.map_err(TransportError::Tls),
             Err(_) => Err(TransportError::HandshakeTimeout),
+        };
+        self.metrics.record_handshake_result(started, &result);
+
+        let mut stream = result?;
+        let (_, session) = stream.get_mut();
+        if session.handshake_kind() == Some(HandshakeKind::Resumed) {
+            self.metrics.record_resumption();
+        }
+        let mut early_data = Vec::new();
+        if let Some(mut early) = session.early_data() {
+            early.read_to_end(&mut early_data).map_err(TransportError::Tls)?;
+            self.metrics.record_early_data(early_data.len());
         }
+        Ok(TlsTransport::new(stream.into()).with_early_data(early_data).metered(&self.metrics))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls_config::load_certs;
+    use crate::{RawTcpTransport, SentinelConnector, SentinelTransport};
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::client::TlsStream;
+
+    /// Writes `ca.crt` plus a CA-signed `<name>.crt`/`<name>.key` for each name.
+    fn write_pki(dir: &Path, names: &[&str]) {
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        std::fs::write(dir.join("ca.crt"), ca.pem()).unwrap();
+
+        for name in names {
+            let key = KeyPair::generate().unwrap();
+            let params = CertificateParams::new(vec!["sentinel-node.local".to_string()]).unwrap();
+            let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+            std::fs::write(dir.join(format!("{}.crt", name)), cert.pem()).unwrap();
+            std::fs::write(dir.join(format!("{}.key", name)), key.serialize_pem()).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_mtls_requires_client_certificate() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        write_pki(dir.path(), &["server", "client"]);
+
+        let acceptor = SentinelAcceptor::new_mtls(
+            &path("server.crt"),
+            &path("server.key"),
+            &path("ca.crt"),
+            Duration::from_secs(5),
+        )
+        .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut tls = acceptor.accept(RawTcpTransport::new(stream)).await.unwrap();
+            let client_cert = tls.peer_certificate().expect("client certificate");
+            tls.write_all(&(client_cert.len() as u32).to_be_bytes()).await.unwrap();
+            tls.flush().await.unwrap();
+
+            // A client without a certificate must not get through.
+            let (stream, _) = listener.accept().await.unwrap();
+            assert!(acceptor.accept(stream).await.is_err());
+        });
+
+        let connector =
+            SentinelConnector::new_mtls(&path("ca.crt"), &path("client.crt"), &path("client.key")).unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let mut tls = connector.connect("sentinel-node.local", stream).await.unwrap();
+        let expected = load_certs(&path("client.crt")).unwrap()[0].len() as u32;
+        assert_eq!(tls.read_u32().await.unwrap(), expected);
+
+        let anonymous = SentinelConnector::new_tofu().unwrap();
+        let stream = TcpStream::connect(addr).await.unwrap();
+        if let Ok(mut tls) = anonymous.connect("sentinel-node.local", stream).await {
+            // TLS 1.3 clients learn about the rejection on their first read.
+            assert!(tls.read_u8().await.is_err());
+        }
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_swaps_certificate_for_new_connections_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        write_pki(dir.path(), &["old", "new"]);
+        let install = |name: &str| {
+            std::fs::copy(path(&format!("{}.crt", name)), path("server.crt")).unwrap();
+            std::fs::copy(path(&format!("{}.key", name)), path("server.key")).unwrap();
+        };
+        install("old");
+
+        let acceptor = SentinelAcceptor::new(&path("server.crt"), &path("server.key"), Duration::from_secs(5)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server_acceptor = acceptor.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = server_acceptor.clone();
+                tokio::spawn(async move {
+                    let tls = acceptor.accept(stream).await.unwrap();
+                    let (mut reader, mut writer) = tokio::io::split(tls);
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+
+        let connector = SentinelConnector::new_tofu().unwrap();
+        let dial = || async {
+            let stream = TcpStream::connect(addr).await.unwrap();
+            connector.connect("sentinel-node.local", stream).await.unwrap()
+        };
+        let served = |tls: &TlsStream<TcpStream>| crate::peer_certificate(tls).unwrap();
+        let cert = |name: &str| load_certs(&path(&format!("{}.crt", name))).unwrap().remove(0);
+
+        let mut before = dial().await;
+        assert_eq!(served(&before), cert("old"));
+
+        install("new");
+        acceptor.reload().unwrap();
+        assert_eq!(served(&dial().await), cert("new"));
+
+        // The connection made before the reload is still open.
+        before.write_all(b"ping").await.unwrap();
+        let mut echo = [0u8; 4];
+        before.read_exact(&mut echo).await.unwrap();
+        assert_eq!(&echo, b"ping");
+
+        // A broken file is refused and the last good certificate stays in use.
+        std::fs::write(path("server.crt"), "not a certificate").unwrap();
+        assert!(acceptor.reload().is_err());
+        assert_eq!(served(&dial().await), cert("new"));
+    }
+}
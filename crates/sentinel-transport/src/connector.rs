@@ -1,48 +1,406 @@
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_rustls::{TlsConnector, client::TlsStream};
-use rustls::{ClientConfig, RootCertStore, pki_types::ServerName};
+use rustls::{ClientConfig, HandshakeKind, RootCertStore, pki_types::ServerName};
+use rustls::client::Resumption;
 use std::fs::File;
 use std::io::BufReader;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 use anyhow::{Result, Context};
+use rustls::pki_types::CertificateDer;
 
+use crate::metrics::TransportMetrics;
+use crate::tls_config::{crypto_provider, CredentialFiles, TlsCredentials};
+use crate::tcp::RawTcpTransport;
+use crate::tls::TlsTransport;
+use crate::SentinelTransport;
+use crate::socks::{is_onion, Socks5Proxy};
+use crate::verifier::{NodeIdServerVerifier, TofuServerVerifier};
+use sentinel_crypto::cert::NODE_DNS_NAME;
+use sentinel_crypto::public_key_from_node_id;
+
+/// Size of each node's session cache. Servers send two tickets per connection
+/// and each resumption uses one up. rustls keeps at most eight per server name,
+/// and nothing at all in a cache with room for fewer than two names' worth.
+const SESSIONS_PER_NODE: usize = 16;
+
+/// Nodes whose configuration, and so sessions, a connector keeps at once.
+/// Past this, all are dropped and rebuilt as nodes are dialed again.
+const MAX_CACHED_NODES: usize = 1024;
+
+/// How a connector verifies servers and which certificate it presents.
+#[derive(Clone)]
+enum ClientMode {
+    /// Native roots plus the node certificate at `cert_path`; no client certificate.
+    Roots { cert_path: PathBuf },
+    /// Servers chaining to the credentials' CA bundle.
+    Mtls,
+    Tofu,
+    /// Any server, checked per node by [`SentinelConnector::node_config`].
+    Node,
+}
+
+/// What a connector's configuration is built from, kept for [`SentinelConnector::reload`].
+#[derive(Clone)]
+struct ClientSource {
+    mode: ClientMode,
+    /// `None` if the credentials were handed over in memory, or none are presented.
+    files: Option<CredentialFiles>,
+}
+
+impl ClientSource {
+    fn build(&self, credentials: Option<&TlsCredentials>) -> Result<ClientConfig> {
+        let provider = crypto_provider();
+        let builder = ClientConfig::builder_with_provider(provider.clone()).with_safe_default_protocol_versions()?;
+        let client_certificate = || credentials.map(TlsCredentials::resolver).context("No client certificate to present");
+        let config = match &self.mode {
+            ClientMode::Roots { cert_path } => {
+                let mut root_store = RootCertStore::empty();
+
+                // 1. Load native certificates
+                let native_certs = rustls_native_certs::load_native_certs();
+                for cert in native_certs.certs {
+                    root_store.add(cert)?;
+                }
+
+                // 2. Load our node certificate to trust peers in our network
+                let cert_file = File::open(cert_path).context("Failed to open node.crt")?;
+                let mut reader = BufReader::new(cert_file);
+                let certs = rustls_pemfile::certs(&mut reader);
+                for cert in certs {
+                    root_store.add(cert?)?;
+                }
+
+                builder.with_root_certificates(root_store).with_no_client_auth()
+            }
+            ClientMode::Mtls => {
+                let roots = credentials.and_then(TlsCredentials::roots).context("Mutual TLS needs a CA bundle")?;
+                builder.with_root_certificates(roots).with_client_cert_resolver(client_certificate()?)
+            }
+            ClientMode::Tofu => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(TofuServerVerifier::new(provider)))
+                .with_no_client_auth(),
+            ClientMode::Node => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(TofuServerVerifier::new(provider)))
+                .with_client_cert_resolver(client_certificate()?),
+        };
+        Ok(config)
+    }
+}
+
+/// A client configuration built by [`SentinelConnector::prepare`] and not yet in use.
+pub struct PreparedClientConfig(ClientConfig);
+
+#[derive(Clone)]
 pub struct SentinelConnector {
-    config: Arc<ClientConfig>,
+    source: ClientSource,
+    /// Shared by clones, so a reload reaches every holder of this connector.
+    config: Arc<RwLock<Arc<ClientConfig>>>,
+    /// Per-node configurations from [`node_config`](Self::node_config), each
+    /// with the verifier and session cache its resumed handshakes depend on.
+    node_configs: Arc<Mutex<HashMap<String, Arc<ClientConfig>>>>,
+    early_data: bool,
+    metrics: Arc<TransportMetrics>,
+    proxy: Option<Socks5Proxy>,
 }
 
 impl SentinelConnector {
     pub fn new(cert_path: &Path) -> Result<Self> {
-        let mut root_store = RootCertStore::empty();
-        
-        // 1. Load native certificates
-        let native_certs = rustls_native_certs::load_native_certs();
-        for cert in native_certs.certs {
-            root_store.add(cert)?;
-        }
-        
-        // 2. Load our node certificate to trust peers in our network
-        let cert_file = File::open(cert_path).context("Failed to open node.crt")?;
-        let mut reader = BufReader::new(cert_file);
-        let certs = rustls_pemfile::certs(&mut reader);
-        for cert in certs {
-            root_store.add(cert?)?;
+        Self::build(ClientSource { mode: ClientMode::Roots { cert_path: cert_path.to_path_buf() }, files: None }, None)
+    }
+
+    /// Mutual TLS: trusts only servers chaining to a CA in `ca_path` and
+    /// presents `cert_path`/`key_path` as the client certificate.
+    pub fn new_mtls(ca_path: &Path, cert_path: &Path, key_path: &Path) -> Result<Self> {
+        Self::from_files(ClientMode::Mtls, CredentialFiles::new(cert_path, key_path, Some(ca_path)))
+    }
+
+    /// A connector that accepts any peer certificate, for callers that pin
+    /// peer keys themselves. Check [`peer_certificate`] after connecting.
+    pub fn new_tofu() -> Result<Self> {
+        Self::build(ClientSource { mode: ClientMode::Tofu, files: None }, None)
+    }
+
+    /// A connector that authenticates with the node's identity certificate and,
+    /// through [`connect_to_node`](Self::connect_to_node), checks that the server
+    /// is the node it was asked for. Plain [`connect`](Self::connect) accepts any server.
+    pub fn new_node(cert_path: &Path, key_path: &Path) -> Result<Self> {
+        Self::from_files(ClientMode::Node, CredentialFiles::new(cert_path, key_path, None))
+    }
+
+    /// A connector presenting `credentials`, which nothing is re-read from. With a
+    /// CA bundle it trusts servers as [`new_mtls`](Self::new_mtls) does, otherwise
+    /// as [`new_node`](Self::new_node) does.
+    pub fn from_credentials(credentials: &TlsCredentials) -> Result<Self> {
+        let mode = match credentials.roots() {
+            Some(_) => ClientMode::Mtls,
+            None => ClientMode::Node,
+        };
+        Self::build(ClientSource { mode, files: None }, Some(credentials))
+    }
+
+    fn from_files(mode: ClientMode, files: CredentialFiles) -> Result<Self> {
+        let credentials = files.load()?;
+        Self::build(ClientSource { mode, files: Some(files) }, Some(&credentials))
+    }
+
+    fn build(source: ClientSource, credentials: Option<&TlsCredentials>) -> Result<Self> {
+        let config = source.build(credentials)?;
+        Ok(Self {
+            source,
+            config: Arc::new(RwLock::new(Arc::new(config))),
+            node_configs: Arc::default(),
+            early_data: false,
+            metrics: Arc::default(),
+            proxy: None,
+        })
+    }
+
+    /// Re-reads the certificates and key this connector was built from and uses
+    /// them for every later dial. Established connections are unaffected. If
+    /// anything fails to load, the current configuration stays in place.
+    /// Sessions cached for resumption are dropped along with the old certificate.
+    /// Connectors built [`from_credentials`](Self::from_credentials) have nothing to re-read.
+    pub fn reload(&self) -> Result<()> {
+        match (&self.source.files, &self.source.mode) {
+            (Some(files), _) => self.replace(&files.load()?),
+            (None, ClientMode::Roots { .. } | ClientMode::Tofu) => {
+                self.install_config(self.source.build(None)?);
+                Ok(())
+            }
+            (None, _) => Ok(()),
         }
+    }
+
+    /// Presents `credentials` on every later dial, dropping sessions cached for
+    /// resumption. If no configuration can be built from them, the current one stays.
+    pub fn replace(&self, credentials: &TlsCredentials) -> Result<()> {
+        self.install(self.prepare(credentials)?);
+        Ok(())
+    }
+
+    /// Builds the configuration [`replace`](Self::replace) would switch to, without switching.
+    pub fn prepare(&self, credentials: &TlsCredentials) -> Result<PreparedClientConfig> {
+        Ok(PreparedClientConfig(self.source.build(Some(credentials))?))
+    }
+
+    /// Uses a configuration from [`prepare`](Self::prepare) for every later dial,
+    /// dropping sessions cached for resumption.
+    pub fn install(&self, prepared: PreparedClientConfig) {
+        self.install_config(prepared.0);
+    }
 
-        let config = ClientConfig::builder()
-            .with_root_certificates(root_store)
-            .with_no_client_auth(); 
+    fn install_config(&self, mut config: ClientConfig) {
+        config.enable_early_data = self.early_data;
+        *self.config.write().unwrap() = Arc::new(config);
+        self.node_configs.lock().unwrap().clear();
+    }
+
+    /// Offers 0-RTT data when resuming a session, for
+    /// [`connect_to_node_early`](Self::connect_to_node_early). Off by default.
+    pub fn with_early_data(mut self) -> Self {
+        self.early_data = true;
+        self.install_config((*self.config()).clone());
+        self
+    }
+
+    /// Records handshakes and dialed connections into `metrics` instead of private counters.
+    pub fn with_metrics(mut self, metrics: Arc<TransportMetrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    pub fn metrics(&self) -> &Arc<TransportMetrics> {
+        &self.metrics
+    }
+
+    /// Dials every connection through `proxy`, which also resolves host names.
+    pub fn with_proxy(mut self, proxy: Socks5Proxy) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn proxy(&self) -> Option<&Socks5Proxy> {
+        self.proxy.as_ref()
+    }
+
+    /// Opens a TCP connection to `addr` (a `host:port`), through the proxy if there is one.
+    /// Without a proxy, `.onion` addresses are refused rather than looked up.
+    pub async fn open(&self, addr: &str) -> Result<RawTcpTransport> {
+        let stream = match &self.proxy {
+            Some(proxy) => proxy.connect(addr).await?,
+            None if is_onion(addr) => anyhow::bail!("{} is an onion service; dial it through a Tor proxy", addr),
+            None => TcpStream::connect(addr).await?,
+        };
+        Ok(RawTcpTransport::new(stream))
+    }
+
+    /// Connects to `addr` and completes the handshake only if the server's
+    /// certificate carries `node_id`'s key.
+    pub async fn connect_to_node(&self, node_id: &str, addr: &str) -> Result<TlsTransport<RawTcpTransport>> {
+        let stream = self.open(addr).await?;
+        self.connect_to_node_over(node_id, stream).await
+    }
+
+    /// [`connect_to_node`](Self::connect_to_node) over a stream the caller has already opened.
+    pub async fn connect_to_node_over<S>(&self, node_id: &str, stream: S) -> Result<TlsTransport<S>>
+    where
+        S: SentinelTransport,
+    {
+        let (tls, _) = self.connect_to_node_early(node_id, stream, &[]).await?;
+        Ok(tls)
+    }
+
+    /// [`connect_to_node_over`](Self::connect_to_node_over), sending `early` as 0-RTT
+    /// data if the connector was built [`with_early_data`](Self::with_early_data) and
+    /// a session with the node can be resumed. Anyone who captures it can replay it,
+    /// so send only what is safe to act on twice. The server reads it apart from the
+    /// stream, with [`TlsTransport::take_early_data`]. Returns whether the server
+    /// accepted it; if not, none of `early` was delivered.
+    pub async fn connect_to_node_early<S>(&self, node_id: &str, stream: S, early: &[u8]) -> Result<(TlsTransport<S>, bool)>
+    where
+        S: SentinelTransport,
+    {
+        let config = self.node_config(node_id)?;
+        let server_name = ServerName::try_from(NODE_DNS_NAME).expect("valid DNS name");
+        let tls_stream = self.handshake(config, server_name, stream, early).await?;
+        let accepted = !early.is_empty() && tls_stream.get_ref().1.is_early_data_accepted();
+        Ok((TlsTransport::new(tls_stream.into()).metered(&self.metrics), accepted))
+    }
+
+    /// The client configuration used by [`connect`](Self::connect).
+    pub(crate) fn config(&self) -> Arc<ClientConfig> {
+        self.config.read().unwrap().clone()
+    }
 
-        Ok(Self { config: Arc::new(config) })
+    /// This connector's configuration, but accepting only servers that prove `node_id`.
+    /// Built once per node and reused, since rustls resumes a session only with
+    /// the verifier that checked it; its sessions are offered to that node alone.
+    pub(crate) fn node_config(&self, node_id: &str) -> Result<Arc<ClientConfig>> {
+        public_key_from_node_id(node_id)?;
+        let mut configs = self.node_configs.lock().unwrap();
+        if let Some(config) = configs.get(node_id) {
+            return Ok(config.clone());
+        }
+        if configs.len() >= MAX_CACHED_NODES {
+            configs.clear();
+        }
+        let mut config = (*self.config()).clone();
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(NodeIdServerVerifier::new(crypto_provider(), node_id)));
+        config.resumption = Resumption::in_memory_sessions(SESSIONS_PER_NODE);
+        let config = Arc::new(config);
+        configs.insert(node_id.to_string(), config.clone());
+        Ok(config)
     }
 
-    pub async fn connect(&self, domain: &str, stream: TcpStream) -> Result<TlsStream<TcpStream>> {
-        let connector = TlsConnector::from(self.config.clone());
+    pub async fn connect<S>(&self, domain: &str, stream: S) -> Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         let server_name = ServerName::try_from(domain.to_string())
             .map_err(|_| anyhow::anyhow!("Invalid DNS Name"))?
             .to_owned();
 
-        let tls_stream = connector.connect(server_name, stream).await?;
-        Ok(tls_stream)
+        Ok(self.handshake(self.config(), server_name, stream, &[]).await?)
+    }
+
+    /// Runs a client handshake, offering `early` as 0-RTT data if the session
+    /// allows all of it, and records how it went.
+    async fn handshake<S>(
+        &self,
+        config: Arc<ClientConfig>,
+        server_name: ServerName<'static>,
+        stream: S,
+        early: &[u8],
+    ) -> std::io::Result<TlsStream<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let started = Instant::now();
+        let connect = TlsConnector::from(config).connect_with(server_name, stream, |session| {
+            if let Some(mut early_data) = session.early_data().filter(|_| !early.is_empty()) {
+                if early_data.bytes_left() >= early.len() {
+                    let _ = early_data.write_all(early);
+                }
+            }
+        });
+        let result = connect.await;
+        self.metrics.record_handshake_result(started, &result);
+        if let Ok(stream) = &result {
+            if stream.get_ref().1.handshake_kind() == Some(HandshakeKind::Resumed) {
+                self.metrics.record_resumption();
+            }
+        }
+        result
+    }
+}
+
+/// The end-entity certificate the server presented during the handshake.
+pub fn peer_certificate<S>(stream: &TlsStream<S>) -> Option<CertificateDer<'static>> {
+    let (_, session) = stream.get_ref();
+    session.peer_certificates()?.first().map(|cert| cert.clone().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryTransport, SentinelAcceptor};
+    use sentinel_crypto::NodeIdentity;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_resumed_sessions_carry_early_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let server_id = NodeIdentity::generate();
+        server_id.self_signed_certificate().unwrap().save(path("server.crt"), path("server.key")).unwrap();
+        NodeIdentity::generate().self_signed_certificate().unwrap().save(path("client.crt"), path("client.key")).unwrap();
+
+        let acceptor = SentinelAcceptor::new_node_auth(&path("server.crt"), &path("server.key"), Duration::from_secs(5))
+            .unwrap()
+            .with_early_data(1024);
+        let connector = SentinelConnector::new_node(&path("client.crt"), &path("client.key")).unwrap().with_early_data();
+        // Dials the server with `early`, returning whether it was accepted and what the server got.
+        let dial = |early: &'static [u8]| {
+            let acceptor = acceptor.clone();
+            let connector = connector.clone();
+            let node_id = server_id.node_id();
+            async move {
+                let (client_io, server_io) =
+                    MemoryTransport::pair("10.0.0.1:1".parse().unwrap(), "10.0.0.2:2".parse().unwrap(), 4096);
+                let server = tokio::spawn(async move {
+                    let mut tls = acceptor.accept(server_io).await.unwrap();
+                    tls.write_all(b"ok").await.unwrap();
+                    tls.flush().await.unwrap();
+                    tls.take_early_data()
+                });
+                let (mut tls, accepted) = connector.connect_to_node_early(&node_id, client_io, early).await.unwrap();
+                // Reading takes in the session tickets sent after the handshake.
+                let mut reply = [0u8; 2];
+                tls.read_exact(&mut reply).await.unwrap();
+                (accepted, server.await.unwrap())
+            }
+        };
+
+        // The first connection has no session to resume.
+        assert_eq!(dial(b"hello").await, (false, Vec::new()));
+        assert_eq!(dial(b"hello").await, (true, b"hello".to_vec()));
+        let (client, server) = (connector.metrics().snapshot(), acceptor.metrics().snapshot());
+        assert_eq!((client.handshakes_resumed, server.handshakes_resumed), (1, 1));
+        assert_eq!(server.early_data_accepted, 5);
+
+        // New certificates start new sessions.
+        connector.reload().unwrap();
+        assert_eq!(dial(b"hello").await, (false, Vec::new()));
+        assert_eq!(connector.metrics().snapshot().handshakes_resumed, 1);
     }
-}
\ No newline at end of file
+}
@@ -15,4 +15,16 @@ pub enum TransportError {
     
     #[error("Handshake failed")]
     HandshakeFailed,
+
+    #[error("Noise error: {0}")]
+    Noise(String),
+
+    #[error("QUIC error: {0}")]
+    Quic(String),
+
+    #[error("WebSocket error: {0}")]
+    WebSocket(String),
+
+    #[error("SOCKS5 proxy error: {0}")]
+    Proxy(String),
 }
\ No newline at end of file
@@ -3,26 +3,100 @@ pub mod tls;
 pub mod tls_config;
 pub mod acceptor;
 pub mod error;
+pub mod limits;
+pub mod memory;
 pub mod metrics;
+pub mod noise;
+pub mod punch;
+pub mod quic;
+pub mod rewind;
+pub mod socks;
 pub mod state;
+#[cfg(unix)]
+pub mod unix;
 pub mod connector;
+pub mod verifier;
+pub mod websocket;
 
-pub use acceptor::SentinelAcceptor;
+pub use acceptor::{ClientAuth, PreparedServerConfig, SentinelAcceptor, ALPN_PROTOCOL};
 pub use error::{TransportError, TransportResult};
 pub use tcp::RawTcpTransport;
 pub use tls::TlsTransport;
-pub use state::{Connection, Unauthenticated};
-pub use connector::SentinelConnector;
+pub use limits::{Cidr, ConnectionLimits, ConnectionPermit, HandshakeRate, Refusal};
+pub use memory::{MemoryListener, MemoryNetwork, MemoryTransport};
+pub use metrics::{HandshakeFailure, Meter, Metered, MeteredCodec, MetricsSnapshot, TransportMetrics};
+pub use noise::NoiseTransport;
+#[cfg(unix)]
+pub use unix::UnixSocketTransport;
+pub use quic::{PreparedQuicConfig, QuicConnection, QuicEndpoint, QuicIncoming, QuicTransport};
+pub use rewind::Rewind;
+pub use socks::{is_onion, Socks5Proxy};
+pub use state::{Authenticated, Connection, FrameSink, FrameStream, Unauthenticated};
+pub use connector::{peer_certificate, PreparedClientConfig, SentinelConnector};
+pub use websocket::{WebSocketAcceptor, WebSocketConnector, WebSocketTransport, WS_SUBPROTOCOL};
+pub use tls_config::TlsCredentials;
 
 use async_trait::async_trait;
+use rustls::pki_types::CertificateDer;
 use tokio::io::{AsyncRead, AsyncWrite};
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 #[async_trait]
 pub trait SentinelTransport: AsyncRead + AsyncWrite + Unpin + Send {
     /// Returns the remote address of the peer.
     fn peer_addr(&self) -> Result<SocketAddr, std::io::Error>;
 
-    /// Returns true if the transport is encrypted (TLS).
+    /// Returns true if the transport is encrypted (TLS, Noise or QUIC, or WebSocket over TLS).
     fn is_secure(&self) -> bool;
-}
\ No newline at end of file
+
+    /// The certificate the peer presented and the handshake verified, if any.
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        None
+    }
+
+    /// The node ID the peer authenticated as, if the transport proves one.
+    fn peer_node_id(&self) -> Option<String> {
+        None
+    }
+
+    /// A value unique to this session that both ends compute identically, for
+    /// binding application-level proofs to the channel. `None` if unsupported.
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Where this connection's traffic is counted, if it is metered.
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        None
+    }
+}
+
+/// A transport whose concrete type is chosen at runtime, e.g. TCP or in-memory.
+pub type BoxedTransport = Box<dyn SentinelTransport>;
+
+impl<T: SentinelTransport + ?Sized> SentinelTransport for Box<T> {
+    fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        (**self).peer_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        (**self).is_secure()
+    }
+
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        (**self).peer_certificate()
+    }
+
+    fn peer_node_id(&self) -> Option<String> {
+        (**self).peer_node_id()
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        (**self).channel_binding()
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        (**self).metrics()
+    }
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use thiserror::Error;
+
+/// An IP network such as `10.0.0.0/8`. A bare address is a network of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+#[derive(Debug, Error)]
+#[error("invalid CIDR {0:?}, expected e.g. 192.168.1.0/24")]
+pub struct InvalidCidr(String);
+
+impl Cidr {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = InvalidCidr;
+
+    fn from_str(s: &str) -> Result<Self, InvalidCidr> {
+        let invalid = || InvalidCidr(s.to_string());
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let network = IpAddr::from_str(addr).map_err(|_| invalid())?.to_canonical();
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(invalid)?,
+            None => max,
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Handshakes allowed per second on average, and how many may start at once
+/// after a quiet spell.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HandshakeRate {
+    pub per_second: f64,
+    pub burst: u32,
+}
+
+/// Limits checked by [`SentinelAcceptor::admit`](crate::SentinelAcceptor::admit)
+/// before any handshake starts. The default limits nothing.
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionLimits {
+    /// Open connections across all peers.
+    pub max_connections: Option<usize>,
+    /// Open connections from any one IP address.
+    pub max_per_ip: Option<usize>,
+    pub handshake_rate: Option<HandshakeRate>,
+    /// If not empty, only these networks may connect.
+    pub allow: Vec<Cidr>,
+    /// Networks that may never connect, even if also allowed.
+    pub deny: Vec<Cidr>,
+}
+
+/// Why a connection was turned away before its handshake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum Refusal {
+    #[error("address is on the deny list")]
+    Denied,
+    #[error("address is not on the allow list")]
+    NotAllowed,
+    #[error("too many open connections")]
+    TooManyConnections,
+    #[error("too many open connections from this address")]
+    TooManyFromAddress,
+    #[error("handshake rate limit reached")]
+    RateLimited,
+}
+
+/// Tracks open connections and handshake tokens for one set of [`ConnectionLimits`].
+pub(crate) struct ConnectionGate {
+    limits: ConnectionLimits,
+    state: Mutex<GateState>,
+}
+
+struct GateState {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl ConnectionGate {
+    pub(crate) fn new(limits: ConnectionLimits) -> Self {
+        let tokens = limits.handshake_rate.map_or(0.0, |rate| rate.burst as f64);
+        Self {
+            limits,
+            state: Mutex::new(GateState { total: 0, per_ip: HashMap::new(), tokens, refilled: Instant::now() }),
+        }
+    }
+
+    /// Counts a new connection from `ip` if every limit allows it. Nothing is
+    /// counted, and no handshake token spent, for a connection that is refused.
+    pub(crate) fn admit(self: &Arc<Self>, ip: IpAddr) -> Result<ConnectionPermit, Refusal> {
+        let ip = ip.to_canonical();
+        let limits = &self.limits;
+        if limits.deny.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Refusal::Denied);
+        }
+        if !limits.allow.is_empty() && !limits.allow.iter().any(|cidr| cidr.contains(ip)) {
+            return Err(Refusal::NotAllowed);
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if limits.max_connections.is_some_and(|max| state.total >= max) {
+            return Err(Refusal::TooManyConnections);
+        }
+        let from_ip = state.per_ip.get(&ip).copied().unwrap_or(0);
+        if limits.max_per_ip.is_some_and(|max| from_ip >= max) {
+            return Err(Refusal::TooManyFromAddress);
+        }
+        if let Some(rate) = limits.handshake_rate {
+            let now = Instant::now();
+            let refill = now.duration_since(state.refilled).as_secs_f64() * rate.per_second;
+            state.tokens = (state.tokens + refill).min(rate.burst as f64);
+            state.refilled = now;
+            if state.tokens < 1.0 {
+                return Err(Refusal::RateLimited);
+            }
+            state.tokens -= 1.0;
+        }
+
+        state.total += 1;
+        *state.per_ip.entry(ip).or_insert(0) += 1;
+        Ok(ConnectionPermit { gate: Arc::clone(self), ip })
+    }
+}
+
+/// An admitted connection's place under the limits, given back when dropped.
+/// Hold it for as long as the connection is open.
+pub struct ConnectionPermit {
+    gate: Arc<ConnectionGate>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        let mut state = self.gate.state.lock().unwrap();
+        state.total -= 1;
+        if let Some(count) = state.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                state.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidr_matching() {
+        let lan: Cidr = "192.168.1.0/24".parse().unwrap();
+        assert!(lan.contains(ip("192.168.1.77")));
+        assert!(lan.contains(ip("::ffff:192.168.1.77")));
+        assert!(!lan.contains(ip("192.168.2.1")));
+        assert!(!lan.contains(ip("fe80::1")));
+
+        let host: Cidr = "fe80::1".parse().unwrap();
+        assert_eq!(host.to_string(), "fe80::1/128");
+        assert!(host.contains(ip("fe80::1")) && !host.contains(ip("fe80::2")));
+        assert!("0.0.0.0/0".parse::<Cidr>().unwrap().contains(ip("8.8.8.8")));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("lan".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_gate_enforces_limits_and_releases_permits() {
+        let gate = Arc::new(ConnectionGate::new(ConnectionLimits {
+            max_connections: Some(3),
+            max_per_ip: Some(2),
+            handshake_rate: None,
+            allow: vec!["10.0.0.0/8".parse().unwrap()],
+            deny: vec!["10.0.0.66".parse().unwrap()],
+        }));
+        assert_eq!(gate.admit(ip("10.0.0.66")).err(), Some(Refusal::Denied));
+        assert_eq!(gate.admit(ip("192.168.1.1")).err(), Some(Refusal::NotAllowed));
+
+        let first = gate.admit(ip("10.0.0.1")).unwrap();
+        let _second = gate.admit(ip("10.0.0.1")).unwrap();
+        assert_eq!(gate.admit(ip("10.0.0.1")).err(), Some(Refusal::TooManyFromAddress));
+        let _third = gate.admit(ip("10.0.0.2")).unwrap();
+        assert_eq!(gate.admit(ip("10.0.0.3")).err(), Some(Refusal::TooManyConnections));
+
+        drop(first);
+        assert!(gate.admit(ip("10.0.0.1")).is_ok());
+    }
+
+    #[test]
+    fn test_gate_rate_limits_handshakes() {
+        let gate = Arc::new(ConnectionGate::new(ConnectionLimits {
+            handshake_rate: Some(HandshakeRate { per_second: 50.0, burst: 2 }),
+            ..Default::default()
+        }));
+        // Permits are dropped at once, so only the token bucket can refuse.
+        assert!(gate.admit(ip("10.0.0.1")).is_ok());
+        assert!(gate.admit(ip("10.0.0.1")).is_ok());
+        assert_eq!(gate.admit(ip("10.0.0.1")).err(), Some(Refusal::RateLimited));
+        std::thread::sleep(std::time::Duration::from_millis(30));
+        assert!(gate.admit(ip("10.0.0.1")).is_ok());
+    }
+}
@@ -0,0 +1,209 @@
+use crate::SentinelTransport;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream, ReadBuf};
+use tokio::sync::mpsc;
+
+/// Bytes each direction of an in-memory connection buffers before writes wait for the reader.
+pub const DEFAULT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// First port handed to dialers that don't pick their own address.
+const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// One end of an in-process connection built on [`tokio::io::duplex`], with
+/// made-up socket addresses so it can stand in for TCP in tests and simulations.
+pub struct MemoryTransport {
+    inner: DuplexStream,
+    local_addr: SocketAddr,
+    peer_addr: SocketAddr,
+}
+
+impl MemoryTransport {
+    /// A connected pair; each end reports the other's address as its peer.
+    pub fn pair(a: SocketAddr, b: SocketAddr, buffer_size: usize) -> (Self, Self) {
+        let (a_stream, b_stream) = tokio::io::duplex(buffer_size);
+        (
+            Self { inner: a_stream, local_addr: a, peer_addr: b },
+            Self { inner: b_stream, local_addr: b, peer_addr: a },
+        )
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+#[async_trait]
+impl SentinelTransport for MemoryTransport {
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        Ok(self.peer_addr)
+    }
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+}
+
+impl AsyncRead for MemoryTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for MemoryTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+struct NetworkState {
+    listeners: HashMap<SocketAddr, mpsc::UnboundedSender<MemoryTransport>>,
+    next_port: u16,
+}
+
+/// An in-process stand-in for the network. Listeners bind made-up addresses,
+/// and dialers connect to them with [`MemoryTransport`] pairs. Clones share
+/// the same address space.
+#[derive(Clone)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<NetworkState>>,
+    buffer_size: usize,
+}
+
+impl Default for MemoryNetwork {
+    fn default() -> Self {
+        Self::with_buffer_size(DEFAULT_BUFFER_SIZE)
+    }
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A network whose connections buffer `buffer_size` bytes in each direction.
+    pub fn with_buffer_size(buffer_size: usize) -> Self {
+        let state = NetworkState { listeners: HashMap::new(), next_port: EPHEMERAL_PORT_START };
+        Self { state: Arc::new(Mutex::new(state)), buffer_size }
+    }
+
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<MemoryListener> {
+        let mut state = self.state.lock().unwrap();
+        if state.listeners.contains_key(&addr) {
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{} is already bound", addr)));
+        }
+        let (tx, incoming) = mpsc::unbounded_channel();
+        state.listeners.insert(addr, tx);
+        Ok(MemoryListener { addr, incoming, network: self.clone() })
+    }
+
+    /// Connects to `addr` from an ephemeral port on 127.0.0.1.
+    pub async fn connect(&self, addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let local = {
+            let mut state = self.state.lock().unwrap();
+            let port = state.next_port;
+            state.next_port = port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port))
+        };
+        self.connect_from(local, addr).await
+    }
+
+    /// Connects to `addr`, appearing to the listener as `local`.
+    pub async fn connect_from(&self, local: SocketAddr, addr: SocketAddr) -> io::Result<MemoryTransport> {
+        let (dialer, listener) = MemoryTransport::pair(local, addr, self.buffer_size);
+        let state = self.state.lock().unwrap();
+        let refused = || io::Error::new(io::ErrorKind::ConnectionRefused, format!("nothing listening on {}", addr));
+        let tx = state.listeners.get(&addr).ok_or_else(refused)?;
+        tx.send(listener).map_err(|_| refused())?;
+        Ok(dialer)
+    }
+}
+
+/// Accepts connections made to one address of a [`MemoryNetwork`]. The
+/// address is released when the listener is dropped.
+pub struct MemoryListener {
+    addr: SocketAddr,
+    incoming: mpsc::UnboundedReceiver<MemoryTransport>,
+    network: MemoryNetwork,
+}
+
+impl MemoryListener {
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub async fn accept(&mut self) -> io::Result<(MemoryTransport, SocketAddr)> {
+        let transport = self
+            .incoming
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotConnected, "network dropped"))?;
+        let peer = transport.peer_addr;
+        Ok((transport, peer))
+    }
+}
+
+impl Drop for MemoryListener {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.network.state.lock() {
+            state.listeners.remove(&self.addr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_network_connects_listeners_and_dialers() {
+        let network = MemoryNetwork::with_buffer_size(16);
+        let addr: SocketAddr = "10.0.0.1:8443".parse().unwrap();
+        let mut listener = network.bind(addr).unwrap();
+        assert!(network.bind(addr).is_err());
+
+        let from: SocketAddr = "10.0.0.2:50000".parse().unwrap();
+        let mut dialer = network.connect_from(from, addr).await.unwrap();
+        let (mut accepted, peer) = listener.accept().await.unwrap();
+        assert_eq!(peer, from);
+        assert_eq!(accepted.peer_addr().unwrap(), from);
+        assert_eq!(dialer.peer_addr().unwrap(), addr);
+
+        // More than the buffer holds: the writer waits for the reader instead of failing.
+        let payload = vec![9u8; 1000];
+        let writer = tokio::spawn(async move {
+            dialer.write_all(&payload).await.unwrap();
+            dialer
+        });
+        let mut received = vec![0u8; 1000];
+        accepted.read_exact(&mut received).await.unwrap();
+        assert!(received.iter().all(|&b| b == 9));
+        writer.await.unwrap();
+
+        drop(listener);
+        let refused = network.connect(addr).await.err().unwrap();
+        assert_eq!(refused.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
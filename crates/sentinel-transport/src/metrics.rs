@@ -1,15 +1,471 @@
+//! Transport counters shared by acceptors, connectors and the connections they make.
+//!
+//! Bytes are counted above the secure channel, so they are the application
+//! bytes a peer exchanged and leave out TLS, Noise and QUIC overhead.
+
+use crate::error::TransportError;
+use crate::SentinelTransport;
+use async_trait::async_trait;
+use bytes::BytesMut;
+use rustls::pki_types::CertificateDer;
+use rustls::AlertDescription;
+use sentinel_protocol::{Frame, ProtocolError, SentinelCodec};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Upper bounds of the handshake latency buckets. Slower handshakes land in a
+/// final, unbounded bucket.
+pub const LATENCY_BUCKETS: [Duration; 12] = [
+    Duration::from_millis(1),
+    Duration::from_micros(2_500),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(25),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(250),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_micros(2_500_000),
+    Duration::from_secs(5),
+];
+
+/// Why a handshake failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HandshakeFailure {
+    /// The peer did not finish within the handshake timeout.
+    Timeout,
+    /// One side rejected the other's certificate, e.g. for the wrong node ID.
+    Certificate,
+    /// Any other TLS, Noise or QUIC protocol error.
+    Protocol,
+    /// The connection broke during the handshake.
+    Io,
+}
+
+impl HandshakeFailure {
+    pub const ALL: [HandshakeFailure; 4] = [Self::Timeout, Self::Certificate, Self::Protocol, Self::Io];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Timeout => "timeout",
+            Self::Certificate => "certificate",
+            Self::Protocol => "protocol",
+            Self::Io => "io",
+        }
+    }
+
+    /// Classifies an error from a tokio-rustls handshake, which reports TLS errors as I/O errors.
+    pub fn from_io(e: &io::Error) -> Self {
+        match e.get_ref().and_then(|inner| inner.downcast_ref::<rustls::Error>()) {
+            Some(e) => Self::from_tls(e),
+            None if e.kind() == io::ErrorKind::TimedOut => Self::Timeout,
+            None => Self::Io,
+        }
+    }
+
+    pub(crate) fn from_tls(e: &rustls::Error) -> Self {
+        match e {
+            rustls::Error::InvalidCertificate(_) | rustls::Error::NoCertificatesPresented => Self::Certificate,
+            rustls::Error::AlertReceived(alert) => Self::from_alert(u8::from(*alert)),
+            _ => Self::Protocol,
+        }
+    }
+
+    /// Classifies a TLS alert, as carried in QUIC's crypto error codes.
+    pub(crate) fn from_alert(alert: u8) -> Self {
+        let certificate_alerts = [
+            AlertDescription::BadCertificate,
+            AlertDescription::UnsupportedCertificate,
+            AlertDescription::CertificateRevoked,
+            AlertDescription::CertificateExpired,
+            AlertDescription::CertificateUnknown,
+            AlertDescription::UnknownCA,
+            AlertDescription::CertificateRequired,
+        ];
+        if certificate_alerts.iter().any(|a| u8::from(*a) == alert) {
+            Self::Certificate
+        } else {
+            Self::Protocol
+        }
+    }
+}
 
+impl From<&io::Error> for HandshakeFailure {
+    fn from(e: &io::Error) -> Self {
+        Self::from_io(e)
+    }
+}
+
+impl From<&TransportError> for HandshakeFailure {
+    fn from(e: &TransportError) -> Self {
+        match e {
+            TransportError::Tls(e) => Self::from_io(e),
+            TransportError::HandshakeTimeout => Self::Timeout,
+            TransportError::Network(_) | TransportError::Proxy(_) => Self::Io,
+            TransportError::HandshakeFailed
+            | TransportError::Noise(_)
+            | TransportError::Quic(_)
+            | TransportError::WebSocket(_) => Self::Protocol,
+        }
+    }
+}
+
+#[derive(Default)]
 pub struct TransportMetrics {
-    pub total_connections: AtomicU64,
-    pub active_connections: AtomicU64,
-    pub bytes_sent: AtomicU64,
-    pub handshakes_failed: AtomicU64,
+    total_connections: AtomicU64,
+    active_connections: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+    frames_sent: AtomicU64,
+    frames_received: AtomicU64,
+    handshakes_completed: AtomicU64,
+    handshakes_resumed: AtomicU64,
+    early_data_accepted: AtomicU64,
+    connections_refused: AtomicU64,
+    handshakes_failed: [AtomicU64; HandshakeFailure::ALL.len()],
+    /// Per-bucket (not cumulative) counts, the last one unbounded.
+    handshake_latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    handshake_latency_micros: AtomicU64,
 }
 
 impl TransportMetrics {
-    pub fn connection_started(&self) {
-        self.total_connections.fetch_add(1, Ordering::Relaxed);
-        self.active_connections.fetch_add(1, Ordering::Relaxed);
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record_handshake(&self, latency: Duration) {
+        let bucket = LATENCY_BUCKETS.iter().position(|bound| latency <= *bound).unwrap_or(LATENCY_BUCKETS.len());
+        self.handshake_latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.handshake_latency_micros.fetch_add(latency.as_micros() as u64, Ordering::Relaxed);
+        self.handshakes_completed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_handshake_failure(&self, reason: HandshakeFailure) {
+        self.handshakes_failed[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a completed TLS handshake that resumed an earlier session.
+    pub fn record_resumption(&self) {
+        self.handshakes_resumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records `bytes` of 0-RTT data a server accepted before the handshake finished.
+    pub fn record_early_data(&self, bytes: usize) {
+        self.early_data_accepted.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Records a connection turned away by the acceptor's limits before its handshake.
+    pub fn record_refusal(&self) {
+        self.connections_refused.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records a handshake that began at `started` as completed or failed.
+    pub fn record_handshake_result<T, E>(&self, started: Instant, result: &Result<T, E>)
+    where
+        for<'e> HandshakeFailure: From<&'e E>,
+    {
+        match result {
+            Ok(_) => self.record_handshake(started.elapsed()),
+            Err(e) => self.record_handshake_failure(HandshakeFailure::from(e)),
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let mut cumulative = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(&self.handshake_latency)
+            .map(|(bound, count)| {
+                cumulative += load(count);
+                (*bound, cumulative)
+            })
+            .collect();
+
+        MetricsSnapshot {
+            total_connections: load(&self.total_connections),
+            active_connections: load(&self.active_connections),
+            bytes_sent: load(&self.bytes_sent),
+            bytes_received: load(&self.bytes_received),
+            frames_sent: load(&self.frames_sent),
+            frames_received: load(&self.frames_received),
+            handshakes_completed: load(&self.handshakes_completed),
+            handshakes_resumed: load(&self.handshakes_resumed),
+            early_data_accepted: load(&self.early_data_accepted),
+            connections_refused: load(&self.connections_refused),
+            handshakes_failed: HandshakeFailure::ALL
+                .iter()
+                .map(|reason| (*reason, load(&self.handshakes_failed[*reason as usize])))
+                .collect(),
+            handshake_latency: HistogramSnapshot {
+                buckets,
+                count: load(&self.handshakes_completed),
+                sum: Duration::from_micros(load(&self.handshake_latency_micros)),
+            },
+        }
+    }
+}
+
+/// A point-in-time copy of [`TransportMetrics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSnapshot {
+    pub total_connections: u64,
+    pub active_connections: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub frames_sent: u64,
+    pub frames_received: u64,
+    pub handshakes_completed: u64,
+    pub handshakes_resumed: u64,
+    pub early_data_accepted: u64,
+    pub connections_refused: u64,
+    pub handshakes_failed: Vec<(HandshakeFailure, u64)>,
+    pub handshake_latency: HistogramSnapshot,
+}
+
+impl MetricsSnapshot {
+    pub fn handshakes_failed(&self, reason: HandshakeFailure) -> u64 {
+        self.handshakes_failed.iter().find(|(r, _)| *r == reason).map_or(0, |(_, count)| *count)
+    }
+}
+
+/// Handshake latencies. Bucket counts are cumulative, as in Prometheus:
+/// each counts the handshakes at or under its bound, and `count` covers them all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramSnapshot {
+    pub buckets: Vec<(Duration, u64)>,
+    pub count: u64,
+    pub sum: Duration,
+}
+
+/// Counts one connection's traffic into a [`TransportMetrics`]. The connection
+/// stops counting as active when its meter is dropped.
+pub struct Meter {
+    metrics: Arc<TransportMetrics>,
+}
+
+impl Meter {
+    pub fn new(metrics: &Arc<TransportMetrics>) -> Self {
+        metrics.total_connections.fetch_add(1, Ordering::Relaxed);
+        metrics.active_connections.fetch_add(1, Ordering::Relaxed);
+        Self { metrics: Arc::clone(metrics) }
+    }
+
+    pub fn metrics(&self) -> &Arc<TransportMetrics> {
+        &self.metrics
+    }
+
+    /// Counts the bytes a `poll_read` that started with `filled` bytes in `buf` added.
+    pub(crate) fn read(&self, poll: &Poll<io::Result<()>>, buf: &ReadBuf<'_>, filled: usize) {
+        if let Poll::Ready(Ok(())) = poll {
+            self.metrics.bytes_received.fetch_add((buf.filled().len() - filled) as u64, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn wrote(&self, poll: &Poll<io::Result<usize>>) {
+        if let Poll::Ready(Ok(n)) = poll {
+            self.metrics.bytes_sent.fetch_add(*n as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for Meter {
+    fn drop(&mut self) {
+        self.metrics.active_connections.fetch_sub(1, Ordering::Relaxed);
     }
-}
\ No newline at end of file
+}
+
+/// A stream whose bytes count towards a connection's [`Meter`], for streams
+/// that are not a transport of their own, such as QUIC's unidirectional streams.
+pub struct Metered<S> {
+    inner: S,
+    meter: Arc<Meter>,
+}
+
+impl<S> Metered<S> {
+    pub fn new(inner: S, meter: Arc<Meter>) -> Self {
+        Self { inner, meter }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<S: SentinelTransport> SentinelTransport for Metered<S> {
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.inner.peer_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.inner.is_secure()
+    }
+
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        self.inner.peer_certificate()
+    }
+
+    fn peer_node_id(&self) -> Option<String> {
+        self.inner.peer_node_id()
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        self.inner.channel_binding()
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        Some(self.meter.metrics())
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Metered<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        self.meter.read(&poll, buf, filled);
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Metered<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        self.meter.wrote(&poll);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// [`SentinelCodec`] counting the frames it encodes and decodes.
+pub struct MeteredCodec {
+    inner: SentinelCodec,
+    metrics: Option<Arc<TransportMetrics>>,
+}
+
+impl MeteredCodec {
+    /// Counts into `metrics`, or nowhere for an unmetered stream.
+    pub fn new(metrics: Option<Arc<TransportMetrics>>) -> Self {
+        Self { inner: SentinelCodec::new(), metrics }
+    }
+}
+
+impl Decoder for MeteredCodec {
+    type Item = Frame;
+    type Error = ProtocolError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Frame>, ProtocolError> {
+        let frame = self.inner.decode(src)?;
+        if let (Some(metrics), Some(_)) = (&self.metrics, &frame) {
+            metrics.frames_received.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(frame)
+    }
+}
+
+impl Encoder<Frame> for MeteredCodec {
+    type Error = ProtocolError;
+
+    fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> Result<(), ProtocolError> {
+        self.inner.encode(item, dst)?;
+        if let Some(metrics) = &self.metrics {
+            metrics.frames_sent.fetch_add(1, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Connection, MemoryTransport, SentinelAcceptor, SentinelConnector};
+    use sentinel_crypto::NodeIdentity;
+
+    #[test]
+    fn test_latency_histogram_is_cumulative() {
+        let metrics = TransportMetrics::new();
+        for latency in [Duration::from_micros(500), Duration::from_millis(30), Duration::from_secs(10)] {
+            metrics.record_handshake(latency);
+        }
+        metrics.record_handshake_failure(HandshakeFailure::Timeout);
+
+        let snapshot = metrics.snapshot();
+        let at = |bound: Duration| snapshot.handshake_latency.buckets.iter().find(|(b, _)| *b == bound).unwrap().1;
+        assert_eq!(at(Duration::from_millis(1)), 1);
+        assert_eq!(at(Duration::from_millis(25)), 1);
+        assert_eq!(at(Duration::from_millis(50)), 2);
+        assert_eq!(at(Duration::from_secs(5)), 2);
+        assert_eq!(snapshot.handshake_latency.count, 3);
+        assert_eq!(snapshot.handshake_latency.sum, Duration::from_micros(10_030_500));
+        assert_eq!(snapshot.handshakes_failed(HandshakeFailure::Timeout), 1);
+        assert_eq!(snapshot.handshakes_failed(HandshakeFailure::Certificate), 0);
+    }
+
+    #[tokio::test]
+    async fn test_tls_handshakes_and_traffic_are_counted() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let server_id = NodeIdentity::generate();
+        let client_id = NodeIdentity::generate();
+        server_id.self_signed_certificate().unwrap().save(path("server.crt"), path("server.key")).unwrap();
+        client_id.self_signed_certificate().unwrap().save(path("client.crt"), path("client.key")).unwrap();
+
+        let server_metrics = Arc::new(TransportMetrics::new());
+        let client_metrics = Arc::new(TransportMetrics::new());
+        let acceptor = SentinelAcceptor::new_node_auth(&path("server.crt"), &path("server.key"), Duration::from_secs(5))
+            .unwrap()
+            .with_metrics(server_metrics.clone());
+        let connector = SentinelConnector::new_node(&path("client.crt"), &path("client.key"))
+            .unwrap()
+            .with_metrics(client_metrics.clone());
+        let pair = || MemoryTransport::pair("10.0.0.1:1".parse().unwrap(), "10.0.0.2:2".parse().unwrap(), 4096);
+
+        let (client_io, server_io) = pair();
+        let server = tokio::spawn({
+            let acceptor = acceptor.clone();
+            async move { acceptor.accept(server_io).await }
+        });
+        let tls = connector.connect_to_node_over(&server_id.node_id(), client_io).await.unwrap();
+        let mut client = Connection::new(tls).authenticate(None);
+        let mut server = Connection::new(server.await.unwrap().unwrap()).authenticate(None);
+        client.send_frame(Frame::new(1, 0, b"ping".to_vec().into()).unwrap()).await.unwrap();
+        assert_eq!(&server.next_frame().await.unwrap().unwrap().payload()[..], b"ping");
+
+        let sent = client_metrics.snapshot();
+        let received = server_metrics.snapshot();
+        assert_eq!((sent.handshakes_completed, received.handshakes_completed), (1, 1));
+        assert_eq!((sent.frames_sent, received.frames_received), (1, 1));
+        assert!(sent.bytes_sent > 0);
+        assert_eq!(sent.bytes_sent, received.bytes_received);
+        assert_eq!(sent.active_connections, 1);
+        drop(client);
+        let after = client_metrics.snapshot();
+        assert_eq!((after.total_connections, after.active_connections), (1, 0));
+
+        // A server that is not the node asked for fails the handshake on the certificate.
+        let (client_io, server_io) = pair();
+        let server = tokio::spawn(async move { acceptor.accept(server_io).await });
+        assert!(connector.connect_to_node_over(&client_id.node_id(), client_io).await.is_err());
+        assert!(server.await.unwrap().is_err());
+        assert_eq!(client_metrics.snapshot().handshakes_failed(HandshakeFailure::Certificate), 1);
+        // The listener only sees the dialer hang up.
+        let failed: u64 = server_metrics.snapshot().handshakes_failed.iter().map(|(_, count)| count).sum();
+        assert_eq!(failed, 1);
+        assert_eq!(client_metrics.snapshot().total_connections, 1);
+    }
+}
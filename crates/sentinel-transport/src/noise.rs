@@ -0,0 +1,371 @@
+use crate::metrics::Meter;
+use crate::{SentinelTransport, TransportMetrics};
+use async_trait::async_trait;
+use ed25519_dalek::{Signature, VerifyingKey, PUBLIC_KEY_LENGTH, SIGNATURE_LENGTH};
+use sentinel_crypto::{node_id_from_public_key, x25519_public_key, NodeIdentity};
+use snow::{HandshakeState, TransportState};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::error::{TransportError, TransportResult};
+
+pub const NOISE_PATTERN: &str = "Noise_XX_25519_ChaChaPoly_BLAKE2s";
+
+/// Largest Noise message allowed by the spec; each is sent behind a u16 length.
+const MAX_MESSAGE_LEN: usize = 65535;
+const TAG_LEN: usize = 16;
+const MAX_PLAINTEXT_LEN: usize = MAX_MESSAGE_LEN - TAG_LEN;
+const LENGTH_PREFIX: usize = 2;
+
+/// Signed into the identity payload so the signature can't be replayed elsewhere.
+const IDENTITY_CONTEXT: &[u8] = b"sentinel-noise-identity-v1";
+const IDENTITY_PAYLOAD_LEN: usize = PUBLIC_KEY_LENGTH + SIGNATURE_LENGTH;
+
+/// A secure channel using the Noise XX handshake, keyed by the node identity.
+///
+/// The Noise static key is the X25519 form of the node's Ed25519 key. Each side
+/// also sends its Ed25519 key and a signature over its static key inside the
+/// encrypted handshake payload, so the authenticated remote static key maps
+/// back to exactly one node ID without any certificates.
+pub struct NoiseTransport<S> {
+    inner: S,
+    session: TransportState,
+    remote_node_id: String,
+    handshake_hash: Vec<u8>,
+    read_buf: Vec<u8>,
+    plaintext: Vec<u8>,
+    plaintext_pos: usize,
+    write_buf: Vec<u8>,
+    write_pos: usize,
+    meter: Option<Meter>,
+}
+
+impl<S> NoiseTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Runs the handshake as the dialing side.
+    pub async fn initiate(mut inner: S, identity: &NodeIdentity) -> TransportResult<Self> {
+        let mut handshake = Self::handshake_state(identity, true)?;
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+
+        // -> e
+        let len = handshake.write_message(&[], &mut buf).map_err(noise_error)?;
+        write_message(&mut inner, &buf[..len]).await?;
+        // <- e, ee, s, es
+        let message = read_message(&mut inner).await?;
+        let len = handshake.read_message(&message, &mut buf).map_err(noise_error)?;
+        let remote_node_id = Self::verify_identity(&handshake, &buf[..len])?;
+        // -> s, se
+        let payload = Self::identity_payload(identity);
+        let len = handshake.write_message(&payload, &mut buf).map_err(noise_error)?;
+        write_message(&mut inner, &buf[..len]).await?;
+
+        Self::finish(inner, handshake, remote_node_id)
+    }
+
+    /// Runs the handshake as the accepting side.
+    pub async fn respond(mut inner: S, identity: &NodeIdentity) -> TransportResult<Self> {
+        let mut handshake = Self::handshake_state(identity, false)?;
+        let mut buf = vec![0u8; MAX_MESSAGE_LEN];
+
+        // -> e
+        let message = read_message(&mut inner).await?;
+        handshake.read_message(&message, &mut buf).map_err(noise_error)?;
+        // <- e, ee, s, es
+        let payload = Self::identity_payload(identity);
+        let len = handshake.write_message(&payload, &mut buf).map_err(noise_error)?;
+        write_message(&mut inner, &buf[..len]).await?;
+        // -> s, se
+        let message = read_message(&mut inner).await?;
+        let len = handshake.read_message(&message, &mut buf).map_err(noise_error)?;
+        let remote_node_id = Self::verify_identity(&handshake, &buf[..len])?;
+
+        Self::finish(inner, handshake, remote_node_id)
+    }
+
+    fn handshake_state(identity: &NodeIdentity, initiator: bool) -> TransportResult<HandshakeState> {
+        let params = NOISE_PATTERN.parse().map_err(noise_error)?;
+        let secret = identity
+            .x25519_secret()
+            .map_err(|e| TransportError::Noise(format!("{}; use the TLS channel instead", e)))?;
+        let builder = snow::Builder::new(params).local_private_key(secret.as_slice());
+        if initiator {
+            builder.build_initiator().map_err(noise_error)
+        } else {
+            builder.build_responder().map_err(noise_error)
+        }
+    }
+
+    fn identity_payload(identity: &NodeIdentity) -> Vec<u8> {
+        let mut signed = IDENTITY_CONTEXT.to_vec();
+        signed.extend_from_slice(&identity.x25519_public());
+
+        let mut payload = Vec::with_capacity(IDENTITY_PAYLOAD_LEN);
+        payload.extend_from_slice(identity.public_key().as_bytes());
+        payload.extend_from_slice(&identity.sign_detached(&signed));
+        payload
+    }
+
+    /// Checks that the peer's Ed25519 key signed, and maps to, its Noise static key.
+    fn verify_identity(handshake: &HandshakeState, payload: &[u8]) -> TransportResult<String> {
+        let remote_static = handshake.get_remote_static().ok_or(TransportError::HandshakeFailed)?;
+        if payload.len() != IDENTITY_PAYLOAD_LEN {
+            return Err(TransportError::HandshakeFailed);
+        }
+        let (key, signature) = payload.split_at(PUBLIC_KEY_LENGTH);
+        let key = VerifyingKey::from_bytes(key.try_into().expect("Length checked"))
+            .map_err(|_| TransportError::HandshakeFailed)?;
+        let signature = Signature::from_slice(signature).map_err(|_| TransportError::HandshakeFailed)?;
+
+        if x25519_public_key(&key).as_slice() != remote_static {
+            return Err(TransportError::HandshakeFailed);
+        }
+        let mut signed = IDENTITY_CONTEXT.to_vec();
+        signed.extend_from_slice(remote_static);
+        key.verify_strict(&signed, &signature)
+            .map_err(|_| TransportError::HandshakeFailed)?;
+
+        Ok(node_id_from_public_key(&key))
+    }
+
+    fn finish(inner: S, handshake: HandshakeState, remote_node_id: String) -> TransportResult<Self> {
+        Ok(Self {
+            inner,
+            handshake_hash: handshake.get_handshake_hash().to_vec(),
+            session: handshake.into_transport_mode().map_err(noise_error)?,
+            remote_node_id,
+            read_buf: Vec::new(),
+            plaintext: Vec::new(),
+            plaintext_pos: 0,
+            write_buf: Vec::new(),
+            write_pos: 0,
+            meter: None,
+        })
+    }
+
+    /// Node ID proven by the remote side during the handshake.
+    pub fn remote_node_id(&self) -> &str {
+        &self.remote_node_id
+    }
+
+    /// Noise handshake hash, identical on both ends of this session.
+    pub fn handshake_hash(&self) -> &[u8] {
+        &self.handshake_hash
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Counts this connection and its traffic into `metrics`.
+    pub fn metered(mut self, metrics: &Arc<TransportMetrics>) -> Self {
+        self.meter = Some(Meter::new(metrics));
+        self
+    }
+
+    fn poll_read_plaintext(&mut self, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            if self.plaintext_pos < self.plaintext.len() {
+                let available = &self.plaintext[self.plaintext_pos..];
+                let n = available.len().min(buf.remaining());
+                buf.put_slice(&available[..n]);
+                self.plaintext_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if self.decrypt_buffered()? {
+                continue;
+            }
+
+            let mut chunk = [0u8; 8192];
+            let mut chunk_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut self.inner).poll_read(cx, &mut chunk_buf))?;
+            if chunk_buf.filled().is_empty() {
+                return if self.read_buf.is_empty() {
+                    Poll::Ready(Ok(()))
+                } else {
+                    Poll::Ready(Err(std::io::ErrorKind::UnexpectedEof.into()))
+                };
+            }
+            self.read_buf.extend_from_slice(chunk_buf.filled());
+        }
+    }
+
+    /// Decrypts one complete message from `read_buf`, if there is one.
+    fn decrypt_buffered(&mut self) -> std::io::Result<bool> {
+        if self.read_buf.len() < LENGTH_PREFIX {
+            return Ok(false);
+        }
+        let len = u16::from_be_bytes([self.read_buf[0], self.read_buf[1]]) as usize;
+        if self.read_buf.len() < LENGTH_PREFIX + len {
+            return Ok(false);
+        }
+
+        self.plaintext.resize(MAX_MESSAGE_LEN, 0);
+        let n = self
+            .session
+            .read_message(&self.read_buf[LENGTH_PREFIX..LENGTH_PREFIX + len], &mut self.plaintext)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        self.plaintext.truncate(n);
+        self.plaintext_pos = 0;
+        self.read_buf.drain(..LENGTH_PREFIX + len);
+        Ok(true)
+    }
+
+    /// Pushes buffered ciphertext to the inner stream.
+    fn poll_drain(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while self.write_pos < self.write_buf.len() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.write_pos..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.write_pos += n;
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn write_message<S: AsyncWrite + Unpin>(stream: &mut S, message: &[u8]) -> TransportResult<()> {
+    stream.write_all(&(message.len() as u16).to_be_bytes()).await?;
+    stream.write_all(message).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+async fn read_message<S: AsyncRead + Unpin>(stream: &mut S) -> TransportResult<Vec<u8>> {
+    let mut len = [0u8; LENGTH_PREFIX];
+    stream.read_exact(&mut len).await?;
+    let mut message = vec![0u8; u16::from_be_bytes(len) as usize];
+    stream.read_exact(&mut message).await?;
+    Ok(message)
+}
+
+fn noise_error(e: snow::Error) -> TransportError {
+    TransportError::Noise(e.to_string())
+}
+
+#[async_trait]
+impl<S> SentinelTransport for NoiseTransport<S>
+where
+    S: SentinelTransport,
+{
+    fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        self.inner.peer_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    fn peer_node_id(&self) -> Option<String> {
+        Some(self.remote_node_id.clone())
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        Some(self.handshake_hash.clone())
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        self.meter.as_ref().map(Meter::metrics)
+    }
+}
+
+impl<S> AsyncRead for NoiseTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = self.poll_read_plaintext(cx, buf);
+        if let Some(meter) = &self.meter {
+            meter.read(&poll, buf, filled);
+        }
+        poll
+    }
+}
+
+impl<S> AsyncWrite for NoiseTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = &mut *self;
+        ready!(this.poll_drain(cx))?;
+
+        let n = buf.len().min(MAX_PLAINTEXT_LEN);
+        let mut message = vec![0u8; n + TAG_LEN];
+        let len = this
+            .session
+            .write_message(&buf[..n], &mut message)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        this.write_buf.extend_from_slice(&(len as u16).to_be_bytes());
+        this.write_buf.extend_from_slice(&message[..len]);
+
+        // The plaintext is accepted either way; anything left is sent on the next poll.
+        if let Poll::Ready(Err(e)) = this.poll_drain(cx) {
+            return Poll::Ready(Err(e));
+        }
+        let poll = Poll::Ready(Ok(n));
+        if let Some(meter) = &this.meter {
+            meter.wrote(&poll);
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.poll_drain(cx))?;
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handshake_authenticates_both_sides() {
+        let (client_io, server_io) = tokio::io::duplex(4096);
+        let client_id = NodeIdentity::generate();
+        let server_id = NodeIdentity::generate();
+
+        let (client, server) = tokio::join!(
+            NoiseTransport::initiate(client_io, &client_id),
+            NoiseTransport::respond(server_io, &server_id),
+        );
+        let (mut client, mut server) = (client.unwrap(), server.unwrap());
+        assert_eq!(client.remote_node_id(), server_id.node_id());
+        assert_eq!(server.remote_node_id(), client_id.node_id());
+        assert_eq!(client.handshake_hash(), server.handshake_hash());
+
+        // Larger than one Noise message, so it has to be split and reassembled.
+        let payload: Vec<u8> = (0..200_000u32).map(|i| i as u8).collect();
+        let expected = payload.clone();
+        let writer = tokio::spawn(async move {
+            client.write_all(&payload).await.unwrap();
+            client.shutdown().await.unwrap();
+        });
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(received, expected);
+    }
+}
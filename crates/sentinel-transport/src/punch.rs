@@ -0,0 +1,107 @@
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpSocket, TcpStream};
+use tokio::time::Instant;
+
+/// Pause between connection attempts while punching.
+pub const PUNCH_RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A socket on `port` (0 for any) that other sockets may share, so a hole can
+/// be punched from the port a rendezvous peer saw us connect from.
+fn reusable_socket(port: u16, remote: SocketAddr) -> io::Result<TcpSocket> {
+    let (socket, local) = match remote {
+        SocketAddr::V4(_) => (TcpSocket::new_v4()?, SocketAddr::from((Ipv4Addr::UNSPECIFIED, port))),
+        SocketAddr::V6(_) => (TcpSocket::new_v6()?, SocketAddr::from((Ipv6Addr::UNSPECIFIED, port))),
+    };
+    socket.set_reuseaddr(true)?;
+    #[cfg(unix)]
+    socket.set_reuseport(true)?;
+    socket.bind(local)?;
+    Ok(socket)
+}
+
+/// Connects to `remote` from a shareable port, e.g. to a rendezvous peer. The
+/// stream's local port can then be handed to [`simultaneous_open`].
+pub async fn connect_reusable(remote: SocketAddr) -> io::Result<TcpStream> {
+    reusable_socket(0, remote)?.connect(remote).await
+}
+
+/// Opens a TCP connection between `local_port` and `remote` while the peer
+/// does the same towards us, as arranged by a rendezvous peer. Both ends listen
+/// and dial at once: whichever SYN gets through each NAT first becomes the one
+/// connection, by an ordinary accept or by TCP simultaneous open. Gives up at
+/// `timeout`.
+pub async fn simultaneous_open(local_port: u16, remote: SocketAddr, timeout: Duration) -> io::Result<TcpStream> {
+    let listener = reusable_socket(local_port, remote)?.listen(1)?;
+    let deadline = Instant::now() + timeout;
+    tokio::time::timeout_at(deadline, async {
+        tokio::select! {
+            stream = accept_from(&listener, remote) => stream,
+            stream = dial_until_open(local_port, remote) => stream,
+        }
+    })
+    .await
+    .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, format!("no path to {} opened in time", remote)))?
+}
+
+/// Accepts the first connection from `remote`'s host, dropping any others.
+async fn accept_from(listener: &TcpListener, remote: SocketAddr) -> io::Result<TcpStream> {
+    loop {
+        let (stream, from) = listener.accept().await?;
+        if from.ip().to_canonical() == remote.ip().to_canonical() {
+            return Ok(stream);
+        }
+    }
+}
+
+/// Dials `remote` from `local_port` until an attempt connects. Early attempts
+/// are expected to fail: their SYNs only open our NAT for the peer's.
+async fn dial_until_open(local_port: u16, remote: SocketAddr) -> io::Result<TcpStream> {
+    loop {
+        let attempt = match reusable_socket(local_port, remote) {
+            Ok(socket) => tokio::time::timeout(PUNCH_RETRY_INTERVAL, socket.connect(remote)).await,
+            Err(e) => Ok(Err(e)),
+        };
+        if let Ok(Ok(stream)) = attempt {
+            return Ok(stream);
+        }
+        tokio::time::sleep(PUNCH_RETRY_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+    }
+
+    #[tokio::test]
+    async fn test_both_ends_punching_get_one_connection() {
+        let (a, b) = (free_port(), free_port());
+        let loopback = |port| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let timeout = Duration::from_secs(5);
+        let (mut a_stream, mut b_stream) = tokio::try_join!(
+            simultaneous_open(a, loopback(b), timeout),
+            simultaneous_open(b, loopback(a), timeout),
+        )
+        .unwrap();
+        assert_eq!(a_stream.local_addr().unwrap().port(), a);
+        assert_eq!(a_stream.peer_addr().unwrap().port(), b);
+
+        a_stream.write_all(b"knock").await.unwrap();
+        let mut heard = [0u8; 5];
+        b_stream.read_exact(&mut heard).await.unwrap();
+        assert_eq!(&heard, b"knock");
+    }
+
+    #[tokio::test]
+    async fn test_punch_gives_up_when_peer_never_answers() {
+        let remote = SocketAddr::from((Ipv4Addr::LOCALHOST, free_port()));
+        let error = simultaneous_open(free_port(), remote, Duration::from_millis(600)).await.unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
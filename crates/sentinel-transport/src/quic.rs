@@ -0,0 +1,454 @@
+use crate::acceptor::{PreparedServerConfig, ALPN_PROTOCOL};
+use crate::error::{TransportError, TransportResult};
+use crate::metrics::{HandshakeFailure, Meter, Metered, TransportMetrics};
+use crate::{SentinelAcceptor, SentinelConnector, SentinelTransport};
+use async_trait::async_trait;
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::{Connecting, ConnectionError, Endpoint, IdleTimeout, Incoming, RecvStream, SendStream, TransportConfig};
+use rustls::pki_types::CertificateDer;
+use rustls::ServerConfig;
+use sentinel_crypto::cert::NODE_DNS_NAME;
+use sentinel_crypto::node_id_from_certificate;
+use std::net::{SocketAddr, UdpSocket};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Same exporter label as TLS over TCP, so admission proofs work unchanged.
+const CHANNEL_BINDING_LABEL: &[u8] = b"EXPORTER-sentinel-channel-binding";
+
+/// Keeps NAT bindings open and notices a dead path well before the idle timeout.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(10);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+fn quic_error(e: impl std::fmt::Display) -> TransportError {
+    TransportError::Quic(e.to_string())
+}
+
+/// Classifies a failed handshake. QUIC carries TLS alerts as the crypto error codes `0x100 + alert`.
+fn handshake_failure(e: &ConnectionError) -> HandshakeFailure {
+    let code = match e {
+        ConnectionError::TimedOut => return HandshakeFailure::Timeout,
+        ConnectionError::Reset => return HandshakeFailure::Io,
+        ConnectionError::TransportError(e) => e.code,
+        ConnectionError::ConnectionClosed(close) => close.error_code,
+        _ => return HandshakeFailure::Protocol,
+    };
+    match u64::from(code) {
+        code @ 0x100..=0x1ff => HandshakeFailure::from_alert((code - 0x100) as u8),
+        _ => HandshakeFailure::Protocol,
+    }
+}
+
+/// Waits for `connecting` to finish within `timeout`, recording the outcome into `metrics`.
+async fn establish(
+    connecting: Connecting,
+    timeout: Duration,
+    metrics: &Arc<TransportMetrics>,
+) -> TransportResult<QuicConnection> {
+    let started = Instant::now();
+    match tokio::time::timeout(timeout, connecting).await {
+        Ok(Ok(inner)) => {
+            metrics.record_handshake(started.elapsed());
+            Ok(QuicConnection { inner, meter: Arc::new(Meter::new(metrics)) })
+        }
+        Ok(Err(e)) => {
+            metrics.record_handshake_failure(handshake_failure(&e));
+            Err(quic_error(e))
+        }
+        Err(_) => {
+            metrics.record_handshake_failure(HandshakeFailure::Timeout);
+            Err(TransportError::HandshakeTimeout)
+        }
+    }
+}
+
+fn transport_config() -> Arc<TransportConfig> {
+    let mut config = TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    config.max_idle_timeout(Some(IdleTimeout::try_from(IDLE_TIMEOUT).expect("idle timeout in range")));
+    Arc::new(config)
+}
+
+fn server_config(tls: &ServerConfig) -> TransportResult<quinn::ServerConfig> {
+    // rustls takes no partial early data limit over QUIC, and QUIC connections don't use 0-RTT.
+    let mut tls = tls.clone();
+    tls.max_early_data_size = 0;
+    let crypto = QuicServerConfig::try_from(Arc::new(tls)).map_err(quic_error)?;
+    let mut server = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    server.transport_config(transport_config());
+    server.migration(true);
+    Ok(server)
+}
+
+/// A QUIC server configuration built by [`QuicEndpoint::prepare`] and not yet in use.
+pub struct PreparedQuicConfig(quinn::ServerConfig);
+
+/// A UDP endpoint that accepts and dials QUIC connections with the same
+/// certificates and peer verification as the TCP [`SentinelAcceptor`] and
+/// [`SentinelConnector`] it was built from.
+///
+/// Servers accept connection migration, so a peer whose address changes (a
+/// laptop moving between networks) keeps its connection; a dialing node whose
+/// socket has gone stale can move onto a fresh one with [`rebind`](Self::rebind).
+pub struct QuicEndpoint {
+    endpoint: Endpoint,
+    connector: SentinelConnector,
+    handshake_timeout: Duration,
+    metrics: Arc<TransportMetrics>,
+}
+
+impl QuicEndpoint {
+    /// Binds `addr` for both inbound and outbound connections, recording into the acceptor's metrics.
+    pub fn bind(addr: SocketAddr, acceptor: &SentinelAcceptor, connector: &SentinelConnector) -> TransportResult<Self> {
+        let endpoint = Endpoint::server(server_config(&acceptor.config())?, addr).map_err(|e| TransportError::Network(e.to_string()))?;
+        Ok(Self {
+            endpoint,
+            connector: connector.clone(),
+            handshake_timeout: acceptor.handshake_timeout(),
+            metrics: acceptor.metrics().clone(),
+        })
+    }
+
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.endpoint.local_addr()
+    }
+
+    /// Waits for the next inbound connection attempt. `None` once the endpoint is closed.
+    pub async fn accept(&self) -> Option<QuicIncoming> {
+        let incoming = self.endpoint.accept().await?;
+        Some(QuicIncoming {
+            incoming,
+            handshake_timeout: self.handshake_timeout,
+            metrics: self.metrics.clone(),
+        })
+    }
+
+    /// Connects to `addr`, verifying the server as [`SentinelConnector::connect`] would.
+    pub async fn connect(&self, addr: SocketAddr) -> TransportResult<QuicConnection> {
+        self.dial(self.connector.config(), addr).await
+    }
+
+    /// Connects to `addr` and completes the handshake only if the server's
+    /// certificate carries `node_id`'s key.
+    pub async fn connect_to_node(&self, node_id: &str, addr: SocketAddr) -> TransportResult<QuicConnection> {
+        let tls = self.connector.node_config(node_id).map_err(quic_error)?;
+        self.dial(tls, addr).await
+    }
+
+    async fn dial(&self, tls: Arc<rustls::ClientConfig>, addr: SocketAddr) -> TransportResult<QuicConnection> {
+        let mut tls = (*tls).clone();
+        tls.alpn_protocols = vec![ALPN_PROTOCOL.to_vec()];
+        let crypto = QuicClientConfig::try_from(tls).map_err(quic_error)?;
+        let mut config = quinn::ClientConfig::new(Arc::new(crypto));
+        config.transport_config(transport_config());
+
+        let connecting = self.endpoint.connect_with(config, addr, NODE_DNS_NAME).map_err(quic_error)?;
+        establish(connecting, self.handshake_timeout, &self.metrics).await
+    }
+
+    /// Takes up the acceptor's current certificates, e.g. after [`SentinelAcceptor::reload`].
+    /// Open connections keep the ones they were handshaken with. Dialing needs no
+    /// such step, since it reads the connector's configuration on every dial.
+    pub fn reload(&self, acceptor: &SentinelAcceptor) -> TransportResult<()> {
+        self.endpoint.set_server_config(Some(server_config(&acceptor.config())?));
+        Ok(())
+    }
+
+    /// Builds the QUIC configuration for a server configuration the acceptor has
+    /// prepared but not installed, so both can switch together.
+    pub fn prepare(&self, server: &PreparedServerConfig) -> TransportResult<PreparedQuicConfig> {
+        Ok(PreparedQuicConfig(server_config(server.config())?))
+    }
+
+    /// Uses a configuration from [`prepare`](Self::prepare) for later inbound connections.
+    pub fn install(&self, prepared: PreparedQuicConfig) {
+        self.endpoint.set_server_config(Some(prepared.0));
+    }
+
+    /// Moves the endpoint onto a new UDP socket bound to `addr`. Open
+    /// connections migrate to it instead of being dropped.
+    pub fn rebind(&self, addr: SocketAddr) -> TransportResult<()> {
+        let socket = UdpSocket::bind(addr).map_err(|e| TransportError::Network(e.to_string()))?;
+        self.endpoint.rebind(socket).map_err(|e| TransportError::Network(e.to_string()))
+    }
+
+    /// Closes every connection and stops accepting new ones.
+    pub fn close(&self) {
+        self.endpoint.close(0u32.into(), b"shutdown");
+    }
+}
+
+/// An inbound connection attempt that has not been handshaken yet.
+pub struct QuicIncoming {
+    incoming: Incoming,
+    handshake_timeout: Duration,
+    metrics: Arc<TransportMetrics>,
+}
+
+impl QuicIncoming {
+    pub fn remote_address(&self) -> SocketAddr {
+        self.incoming.remote_address()
+    }
+
+    /// Turns the peer away without handshaking.
+    pub fn refuse(self) {
+        self.incoming.refuse();
+    }
+
+    /// Completes the handshake, bounded by the acceptor's handshake timeout.
+    pub async fn handshake(self) -> TransportResult<QuicConnection> {
+        let connecting = self.incoming.accept().map_err(|e| {
+            self.metrics.record_handshake_failure(handshake_failure(&e));
+            quic_error(e)
+        })?;
+        establish(connecting, self.handshake_timeout, &self.metrics).await
+    }
+}
+
+/// An authenticated QUIC connection. Each exchange runs on its own stream,
+/// so a lost packet only stalls the stream it belongs to.
+#[derive(Clone)]
+pub struct QuicConnection {
+    inner: quinn::Connection,
+    /// Shared by every handle and stream, so the connection stays active until the last is gone.
+    meter: Arc<Meter>,
+}
+
+impl QuicConnection {
+    /// Opens a bidirectional stream. The peer only sees it once something is written.
+    pub async fn open_stream(&self) -> TransportResult<QuicTransport> {
+        let (send, recv) = self.inner.open_bi().await.map_err(quic_error)?;
+        Ok(QuicTransport { connection: self.clone(), send, recv })
+    }
+
+    /// Waits for the peer to open a bidirectional stream.
+    pub async fn accept_stream(&self) -> TransportResult<QuicTransport> {
+        let (send, recv) = self.inner.accept_bi().await.map_err(quic_error)?;
+        Ok(QuicTransport { connection: self.clone(), send, recv })
+    }
+
+    /// Opens a one-way stream, e.g. to deliver a single message.
+    pub async fn open_uni(&self) -> TransportResult<Metered<SendStream>> {
+        let stream = self.inner.open_uni().await.map_err(quic_error)?;
+        Ok(Metered::new(stream, self.meter.clone()))
+    }
+
+    /// Waits for the peer to open a one-way stream.
+    pub async fn accept_uni(&self) -> TransportResult<Metered<RecvStream>> {
+        let stream = self.inner.accept_uni().await.map_err(quic_error)?;
+        Ok(Metered::new(stream, self.meter.clone()))
+    }
+
+    pub fn metrics(&self) -> &Arc<TransportMetrics> {
+        self.meter.metrics()
+    }
+
+    /// The peer's current address; it changes when the peer migrates.
+    pub fn remote_address(&self) -> SocketAddr {
+        self.inner.remote_address()
+    }
+
+    pub fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        let certs = self.inner.peer_identity()?.downcast::<Vec<CertificateDer<'static>>>().ok()?;
+        certs.first().cloned()
+    }
+
+    /// Derived from the peer certificate when it carries an Ed25519 identity key.
+    pub fn peer_node_id(&self) -> Option<String> {
+        node_id_from_certificate(&self.peer_certificate()?).ok()
+    }
+
+    pub fn channel_binding(&self) -> Option<Vec<u8>> {
+        let mut output = [0u8; 32];
+        self.inner.export_keying_material(&mut output, CHANNEL_BINDING_LABEL, &[]).ok()?;
+        Some(output.to_vec())
+    }
+
+    pub fn close(&self, reason: &str) {
+        self.inner.close(0u32.into(), reason.as_bytes());
+    }
+
+    /// Resolves once the connection is closed, by either side or by timeout.
+    pub async fn closed(&self) {
+        self.inner.closed().await;
+    }
+}
+
+/// One bidirectional stream of a [`QuicConnection`], usable wherever a byte-stream
+/// transport is expected. Shutting down the write half finishes the stream.
+pub struct QuicTransport {
+    connection: QuicConnection,
+    send: SendStream,
+    recv: RecvStream,
+}
+
+impl QuicTransport {
+    pub fn connection(&self) -> &QuicConnection {
+        &self.connection
+    }
+}
+
+#[async_trait]
+impl SentinelTransport for QuicTransport {
+    fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
+        Ok(self.connection.remote_address())
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        self.connection.peer_certificate()
+    }
+
+    fn peer_node_id(&self) -> Option<String> {
+        self.connection.peer_node_id()
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        self.connection.channel_binding()
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        Some(self.connection.metrics())
+    }
+}
+
+impl AsyncRead for QuicTransport {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.recv).poll_read(cx, buf);
+        self.connection.meter.read(&poll, buf, filled);
+        poll
+    }
+}
+
+impl AsyncWrite for QuicTransport {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let poll = AsyncWrite::poll_write(Pin::new(&mut self.send), cx, buf);
+        self.connection.meter.wrote(&poll);
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_flush(Pin::new(&mut self.send), cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        AsyncWrite::poll_shutdown(Pin::new(&mut self.send), cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sentinel_crypto::NodeIdentity;
+    use std::path::Path;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn endpoint(dir: &Path, name: &str) -> (QuicEndpoint, NodeIdentity) {
+        let identity = NodeIdentity::generate();
+        let (cert, key) = (dir.join(format!("{}.crt", name)), dir.join(format!("{}.key", name)));
+        identity.self_signed_certificate().unwrap().save(&cert, &key).unwrap();
+        let acceptor = SentinelAcceptor::new_node_auth(&cert, &key, Duration::from_secs(5)).unwrap();
+        let connector = SentinelConnector::new_node(&cert, &key).unwrap();
+        let endpoint = QuicEndpoint::bind("127.0.0.1:0".parse().unwrap(), &acceptor, &connector).unwrap();
+        (endpoint, identity)
+    }
+
+    #[tokio::test]
+    async fn test_quic_streams_are_independent_and_bound_to_node_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, server_id) = endpoint(dir.path(), "server");
+        let (client, client_id) = endpoint(dir.path(), "client");
+        let addr = server.local_addr().unwrap();
+
+        let expected_client = client_id.node_id();
+        let accepted = tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().handshake().await.unwrap();
+            assert_eq!(conn.peer_node_id(), Some(expected_client));
+            // Echo each stream back, in whatever order they arrive.
+            for _ in 0..2 {
+                let mut stream = conn.accept_stream().await.unwrap();
+                let mut buf = [0u8; 5];
+                stream.read_exact(&mut buf).await.unwrap();
+                stream.write_all(&buf).await.unwrap();
+                stream.shutdown().await.unwrap();
+            }
+            // The second client expects another node and aborts the handshake.
+            assert!(server.accept().await.unwrap().handshake().await.is_err());
+            // Dropping the last handle closes the connection, so hand it back.
+            (conn.channel_binding(), conn)
+        });
+
+        let conn = client.connect_to_node(&server_id.node_id(), addr).await.unwrap();
+        assert_eq!(conn.peer_node_id(), Some(server_id.node_id()));
+        let mut first = conn.open_stream().await.unwrap();
+        let mut second = conn.open_stream().await.unwrap();
+        second.write_all(b"world").await.unwrap();
+        first.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        first.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        second.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world");
+
+        let impostor = NodeIdentity::generate().node_id();
+        assert!(client.connect_to_node(&impostor, addr).await.is_err());
+
+        let (server_binding, _conn) = accepted.await.unwrap();
+        assert!(server_binding.is_some());
+        assert_eq!(first.channel_binding(), server_binding);
+    }
+
+    #[tokio::test]
+    async fn test_quic_connection_survives_rebind() {
+        let dir = tempfile::tempdir().unwrap();
+        let (server, server_id) = endpoint(dir.path(), "server");
+        let (client, _) = endpoint(dir.path(), "client");
+        let addr = server.local_addr().unwrap();
+
+        let accepted = tokio::spawn(async move {
+            let conn = server.accept().await.unwrap().handshake().await.unwrap();
+            let mut stream = conn.accept_stream().await.unwrap();
+            let before = conn.remote_address();
+            let mut buf = [0u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            let mut moved = [0u8; 5];
+            stream.read_exact(&mut moved).await.unwrap();
+            assert_eq!(&moved, b"moved");
+            assert_ne!(conn.remote_address(), before);
+            stream.write_all(b"ok").await.unwrap();
+            stream.shutdown().await.unwrap();
+            conn.closed().await;
+        });
+
+        let conn = client.connect_to_node(&server_id.node_id(), addr).await.unwrap();
+        let mut stream = conn.open_stream().await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+
+        // Simulate a network change: same connection, new local socket.
+        client.rebind("127.0.0.1:0".parse().unwrap()).unwrap();
+        stream.write_all(b"moved").await.unwrap();
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await.unwrap();
+        assert_eq!(reply, b"ok");
+        conn.close("done");
+        accepted.await.unwrap();
+    }
+}
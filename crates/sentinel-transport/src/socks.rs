@@ -0,0 +1,332 @@
+use crate::error::{TransportError, TransportResult};
+use std::net::{IpAddr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const VERSION: u8 = 0x05;
+const NO_AUTH: u8 = 0x00;
+const USERNAME_PASSWORD: u8 = 0x02;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// A SOCKS5 proxy (RFC 1928) to dial through, such as a corporate proxy or a
+/// local Tor daemon on `127.0.0.1:9050`. Host names are resolved by the proxy,
+/// never locally, so `.onion` addresses work and lookups don't leak.
+#[derive(Debug, Clone)]
+pub struct Socks5Proxy {
+    addr: String,
+    credentials: Option<(String, String)>,
+}
+
+impl Socks5Proxy {
+    pub fn new(addr: impl Into<String>) -> Self {
+        Self { addr: addr.into(), credentials: None }
+    }
+
+    /// Authenticates with a username and password (RFC 1929). Tor treats each
+    /// distinct pair as its own circuit.
+    pub fn with_credentials(mut self, username: impl Into<String>, password: impl Into<String>) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    pub fn addr(&self) -> &str {
+        &self.addr
+    }
+
+    /// Opens a TCP connection to the proxy and asks it to connect on to `target`, a `host:port`.
+    pub async fn connect(&self, target: &str) -> TransportResult<TcpStream> {
+        let mut stream = TcpStream::connect(&self.addr).await.map_err(|e| proxy_error(format!("{}: {}", self.addr, e)))?;
+        self.negotiate(&mut stream, target).await?;
+        Ok(stream)
+    }
+
+    /// Runs the SOCKS5 greeting, authentication and CONNECT request over `stream`.
+    async fn negotiate<S>(&self, stream: &mut S, target: &str) -> TransportResult<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let request = connect_request(target)?;
+
+        let method = if self.credentials.is_some() { USERNAME_PASSWORD } else { NO_AUTH };
+        stream.write_all(&[VERSION, 1, method]).await?;
+        let mut reply = [0u8; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("not a SOCKS5 proxy"));
+        }
+        match (reply[1], &self.credentials) {
+            (NO_AUTH, _) => {}
+            (USERNAME_PASSWORD, Some((username, password))) => {
+                let (username, password) = (username.as_bytes(), password.as_bytes());
+                if username.len() > 255 || password.len() > 255 {
+                    return Err(proxy_error("username and password must be at most 255 bytes"));
+                }
+                let mut auth = vec![0x01, username.len() as u8];
+                auth.extend_from_slice(username);
+                auth.push(password.len() as u8);
+                auth.extend_from_slice(password);
+                stream.write_all(&auth).await?;
+                stream.read_exact(&mut reply).await?;
+                if reply[1] != 0x00 {
+                    return Err(proxy_error("username or password rejected"));
+                }
+            }
+            (NO_ACCEPTABLE_METHOD, None) => return Err(proxy_error("proxy requires authentication")),
+            _ => return Err(proxy_error("proxy refused the authentication method")),
+        }
+
+        stream.write_all(&request).await?;
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        if head[1] != 0x00 {
+            return Err(proxy_error(format!("cannot reach {}: {}", target, reply_message(head[1]))));
+        }
+        // The address the proxy bound for us is of no use; skip it and its port.
+        let bound = match head[3] {
+            ATYP_IPV4 => 4,
+            ATYP_IPV6 => 16,
+            ATYP_DOMAIN => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("malformed reply")),
+        };
+        let mut skipped = vec![0u8; bound + 2];
+        stream.read_exact(&mut skipped).await?;
+        Ok(())
+    }
+}
+
+/// A CONNECT request for `target`. IP literals are sent as addresses and
+/// anything else as a name for the proxy to resolve.
+fn connect_request(target: &str) -> TransportResult<Vec<u8>> {
+    let invalid = || proxy_error(format!("{:?} is not a host:port", target));
+    let mut request = vec![VERSION, CONNECT, 0x00];
+    if let Ok(addr) = target.parse::<SocketAddr>() {
+        match addr.ip() {
+            IpAddr::V4(ip) => {
+                request.push(ATYP_IPV4);
+                request.extend_from_slice(&ip.octets());
+            }
+            IpAddr::V6(ip) => {
+                request.push(ATYP_IPV6);
+                request.extend_from_slice(&ip.octets());
+            }
+        }
+        request.extend_from_slice(&addr.port().to_be_bytes());
+        return Ok(request);
+    }
+
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let port: u16 = port.parse().map_err(|_| invalid())?;
+    if host.is_empty() || host.len() > 255 {
+        return Err(invalid());
+    }
+    request.push(ATYP_DOMAIN);
+    request.push(host.len() as u8);
+    request.extend_from_slice(host.as_bytes());
+    request.extend_from_slice(&port.to_be_bytes());
+    Ok(request)
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general proxy failure",
+        0x02 => "not allowed by the proxy's rules",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn proxy_error(message: impl Into<String>) -> TransportError {
+    TransportError::Proxy(message.into())
+}
+
+/// True if `addr` (a host or `host:port`) is a Tor onion service, which only
+/// a Tor proxy can reach.
+pub fn is_onion(addr: &str) -> bool {
+    let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+    host.to_ascii_lowercase().ends_with(".onion")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{SentinelAcceptor, SentinelConnector, SentinelTransport};
+    use sentinel_crypto::NodeIdentity;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+    use tokio::net::TcpListener;
+
+    /// A minimal SOCKS5 proxy: optional username/password, CONNECT only. It
+    /// resolves every name to `upstream` and records the targets it was asked for.
+    struct StandIn {
+        addr: SocketAddr,
+        requested: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl StandIn {
+        async fn start(credentials: Option<(&'static str, &'static str)>, upstream: SocketAddr) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let requested = Arc::new(Mutex::new(Vec::new()));
+            let log = Arc::clone(&requested);
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let log = Arc::clone(&log);
+                    tokio::spawn(async move {
+                        let _ = Self::serve(stream, credentials, upstream, log).await;
+                    });
+                }
+            });
+            Self { addr, requested }
+        }
+
+        async fn serve(
+            mut client: TcpStream,
+            credentials: Option<(&str, &str)>,
+            upstream: SocketAddr,
+            log: Arc<Mutex<Vec<String>>>,
+        ) -> std::io::Result<()> {
+            let mut head = [0u8; 2];
+            client.read_exact(&mut head).await?;
+            let mut methods = vec![0u8; head[1] as usize];
+            client.read_exact(&mut methods).await?;
+            let wanted = if credentials.is_some() { USERNAME_PASSWORD } else { NO_AUTH };
+            if !methods.contains(&wanted) {
+                return client.write_all(&[VERSION, NO_ACCEPTABLE_METHOD]).await;
+            }
+            client.write_all(&[VERSION, wanted]).await?;
+            if let Some((username, password)) = credentials {
+                client.read_u8().await?;
+                let mut given = vec![0u8; client.read_u8().await? as usize];
+                client.read_exact(&mut given).await?;
+                let mut secret = vec![0u8; client.read_u8().await? as usize];
+                client.read_exact(&mut secret).await?;
+                let ok = given == username.as_bytes() && secret == password.as_bytes();
+                client.write_all(&[0x01, if ok { 0x00 } else { 0x01 }]).await?;
+                if !ok {
+                    return Ok(());
+                }
+            }
+
+            let mut request = [0u8; 4];
+            client.read_exact(&mut request).await?;
+            let host = match request[3] {
+                ATYP_DOMAIN => {
+                    let mut name = vec![0u8; client.read_u8().await? as usize];
+                    client.read_exact(&mut name).await?;
+                    String::from_utf8_lossy(&name).into_owned()
+                }
+                ATYP_IPV4 => {
+                    let mut ip = [0u8; 4];
+                    client.read_exact(&mut ip).await?;
+                    IpAddr::from(ip).to_string()
+                }
+                _ => return Ok(()),
+            };
+            let port = client.read_u16().await?;
+            log.lock().unwrap().push(format!("{}:{}", host, port));
+
+            let reply = |code: u8| [VERSION, code, 0x00, ATYP_IPV4, 0, 0, 0, 0, 0, 0];
+            match TcpStream::connect(upstream).await {
+                Ok(mut server) => {
+                    client.write_all(&reply(0x00)).await?;
+                    tokio::io::copy_bidirectional(&mut client, &mut server).await?;
+                }
+                Err(_) => client.write_all(&reply(0x05)).await?,
+            }
+            Ok(())
+        }
+    }
+
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut reader, mut writer) = stream.into_split();
+                    let _ = tokio::io::copy(&mut reader, &mut writer).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_connect_request_encodes_names_and_addresses() {
+        assert_eq!(connect_request("10.0.0.1:443").unwrap(), [5, 1, 0, 1, 10, 0, 0, 1, 1, 187]);
+        assert_eq!(connect_request("[::1]:80").unwrap()[3], ATYP_IPV6);
+        let onion = connect_request("abc.onion:8443").unwrap();
+        assert_eq!(&onion[3..5], &[ATYP_DOMAIN, 9]);
+        assert_eq!(&onion[5..14], b"abc.onion");
+        assert!(connect_request("no-port").is_err());
+        assert!(is_onion("ABC.onion:8443") && is_onion("abc.onion") && !is_onion("10.0.0.1:8443"));
+    }
+
+    #[tokio::test]
+    async fn test_connects_through_proxy_with_remote_dns() {
+        let echo = echo_server().await;
+        let proxy = StandIn::start(Some(("alice", "secret")), echo).await;
+
+        let mut stream = Socks5Proxy::new(proxy.addr.to_string())
+            .with_credentials("alice", "secret")
+            .connect("peer.example.onion:8443")
+            .await
+            .unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut echoed = [0u8; 4];
+        stream.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"ping");
+        // The name reached the proxy unresolved.
+        assert_eq!(*proxy.requested.lock().unwrap(), ["peer.example.onion:8443"]);
+
+        let wrong = Socks5Proxy::new(proxy.addr.to_string()).with_credentials("alice", "guess");
+        assert!(matches!(wrong.connect("peer.example.onion:8443").await, Err(TransportError::Proxy(_))));
+        let anonymous = Socks5Proxy::new(proxy.addr.to_string());
+        assert!(anonymous.connect("peer.example.onion:8443").await.is_err());
+        assert_eq!(proxy.requested.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_connector_dials_onion_peer_through_proxy() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let server_id = NodeIdentity::generate();
+        let client_id = NodeIdentity::generate();
+        server_id.self_signed_certificate().unwrap().save(path("server.crt"), path("server.key")).unwrap();
+        client_id.self_signed_certificate().unwrap().save(path("client.crt"), path("client.key")).unwrap();
+
+        let acceptor =
+            SentinelAcceptor::new_node_auth(&path("server.crt"), &path("server.key"), Duration::from_secs(5)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut tls = acceptor.accept(stream).await.unwrap();
+            tls.write_all(b"hi").await.unwrap();
+            tls.flush().await.unwrap();
+        });
+
+        let onion = "sentinelexampleonionaddressxxxxxxxxxxxxxxxxxxxxxxxxxxxx.onion:8443";
+        let direct = SentinelConnector::new_node(&path("client.crt"), &path("client.key")).unwrap();
+        assert!(direct.connect_to_node(&server_id.node_id(), onion).await.is_err());
+
+        let proxy = StandIn::start(None, upstream).await;
+        let connector = direct.with_proxy(Socks5Proxy::new(proxy.addr.to_string()));
+        let mut tls = connector.connect_to_node(&server_id.node_id(), onion).await.unwrap();
+        assert_eq!(tls.peer_node_id(), Some(server_id.node_id()));
+        let mut greeting = [0u8; 2];
+        tls.read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hi");
+        assert_eq!(*proxy.requested.lock().unwrap(), [onion]);
+    }
+}
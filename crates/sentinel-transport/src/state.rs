@@ -1,59 +1,113 @@
-use std::marker::PhantomData;
-use async_trait::async_trait;
-use sentinel_protocol::Frame;
-use anyhow::Result;
-
-#[async_trait]
-pub trait SentinelTransport: Send + Sync {
-    async fn send_frame(&mut self, frame: Frame) -> Result<()>;
-    async fn next_frame(&mut self) -> Result<Option<Frame>>;
-}
+//! Framed connections over a [`SentinelTransport`], with the peer's
+//! authentication tracked in the type.
+//!
+//! The layers are: a byte-stream transport (TCP, TLS, Noise, QUIC stream, ...),
+//! then [`Connection`], which frames it with [`SentinelCodec`](sentinel_protocol::SentinelCodec),
+//! counting frames if the transport is metered. A connection starts
+//! [`Unauthenticated`]: only raw pre-frame exchanges such as admission are possible.
+//! Frames can be sent and received only after it becomes [`Authenticated`].
+
+use crate::{MeteredCodec, SentinelTransport};
+use futures_util::stream::{SplitSink, SplitStream};
+use futures_util::{SinkExt, StreamExt};
+use sentinel_protocol::{Frame, ProtocolError};
+use std::net::SocketAddr;
+use tokio_util::codec::Framed;
+
+/// Sending half of a split [`Connection`].
+pub type FrameSink<T> = SplitSink<Framed<T, MeteredCodec>, Frame>;
+/// Receiving half of a split [`Connection`].
+pub type FrameStream<T> = SplitStream<Framed<T, MeteredCodec>>;
 
+/// The peer has not been checked yet.
 pub struct Unauthenticated;
-pub struct Authenticated { pub user_id: String }
 
-pub struct Connection<T: SentinelTransport, S> {
-    pub transport: T,
-    pub state_data: S,
-    _state: PhantomData<S>,
+/// The peer passed the caller's checks.
+pub struct Authenticated {
+    node_id: Option<String>,
 }
 
-impl<T: SentinelTransport> Connection<T, Unauthenticated> {
-    pub fn new(transport: T) -> Self {
-        Self { 
-            transport, 
-            state_data: Unauthenticated,
-            _state: PhantomData 
-        }
+pub struct Connection<T, S> {
+    framed: Framed<T, MeteredCodec>,
+    state: S,
+}
+
+impl<T: SentinelTransport, S> Connection<T, S> {
+    pub fn transport(&self) -> &T {
+        self.framed.get_ref()
     }
 
-    pub async fn send_frame(&mut self, frame: Frame) -> Result<()> {
-        self.transport.send_frame(frame).await
+    pub fn peer_addr(&self) -> std::io::Result<SocketAddr> {
+        self.transport().peer_addr()
+    }
+}
+
+impl<T: SentinelTransport> Connection<T, Unauthenticated> {
+    pub fn new(transport: T) -> Self {
+        let codec = MeteredCodec::new(transport.metrics().cloned());
+        Self { framed: Framed::new(transport, codec), state: Unauthenticated }
     }
 
-    pub async fn next_frame(&mut self) -> Result<Option<Frame>> {
-        self.transport.next_frame().await
+    /// The underlying stream, for exchanges that happen before any frame (e.g. admission).
+    /// Nothing has been read through the codec yet, so no bytes are buffered away from it.
+    pub fn transport_mut(&mut self) -> &mut T {
+        self.framed.get_mut()
     }
 
-    pub fn into_authenticated(self, user_id: String) -> Connection<T, Authenticated> {
-        Connection {
-            transport: self.transport,
-            state_data: Authenticated { user_id },
-            _state: PhantomData,
-        }
+    /// Marks the peer as checked. `node_id` is the identity it proved, if any:
+    /// CA-issued certificates and local clients may not carry one.
+    pub fn authenticate(self, node_id: Option<String>) -> Connection<T, Authenticated> {
+        Connection { framed: self.framed, state: Authenticated { node_id } }
     }
 }
 
 impl<T: SentinelTransport> Connection<T, Authenticated> {
-    pub fn user_id(&self) -> &str {
-        &self.state_data.user_id
+    pub fn node_id(&self) -> Option<&str> {
+        self.state.node_id.as_deref()
+    }
+
+    pub async fn send_frame(&mut self, frame: Frame) -> Result<(), ProtocolError> {
+        self.framed.send(frame).await
     }
 
-    pub async fn send_frame(&mut self, frame: Frame) -> Result<()> {
-        self.transport.send_frame(frame).await
+    /// The next frame, or `None` once the peer has closed the stream.
+    pub async fn next_frame(&mut self) -> Result<Option<Frame>, ProtocolError> {
+        self.framed.next().await.transpose()
     }
 
-    pub async fn next_frame(&mut self) -> Result<Option<Frame>> {
-        self.transport.next_frame().await
+    /// Splits the connection so frames can be sent and received from separate tasks.
+    pub fn split(self) -> (FrameSink<T>, FrameStream<T>) {
+        self.framed.split()
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryTransport;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_raw_exchange_then_frames() {
+        let (a, b) = MemoryTransport::pair("10.0.0.1:1".parse().unwrap(), "10.0.0.2:2".parse().unwrap(), 1024);
+        let mut a = Connection::new(a);
+        let mut b = Connection::new(b);
+
+        // A pre-frame handshake on the raw streams, immediately followed by frames.
+        a.transport_mut().write_all(b"hi").await.unwrap();
+        let frame = Frame::new(1, 0, b"after".to_vec().into()).unwrap();
+        let mut a = a.authenticate(Some("b-says-a".into()));
+        a.send_frame(frame).await.unwrap();
+
+        let mut greeting = [0u8; 2];
+        b.transport_mut().read_exact(&mut greeting).await.unwrap();
+        assert_eq!(&greeting, b"hi");
+        let mut b = b.authenticate(None);
+        assert_eq!(&b.next_frame().await.unwrap().unwrap().payload()[..], b"after");
+        assert_eq!(a.node_id(), Some("b-says-a"));
+        assert_eq!(b.peer_addr().unwrap(), "10.0.0.1:1".parse().unwrap());
+
+        drop(a);
+        assert!(b.next_frame().await.unwrap().is_none());
+    }
+}
@@ -1,38 +1,85 @@
-use crate::SentinelTransport;
+use crate::metrics::Meter;
+use crate::{RawTcpTransport, SentinelTransport, TransportMetrics};
+use rustls::pki_types::CertificateDer;
+use sentinel_crypto::node_id_from_certificate;
 use async_trait::async_trait;
 use std::net::SocketAddr;
 use std::pin::Pin;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
 use tokio_rustls::TlsStream;
-use tokio::net::TcpStream;
 
-pub struct TlsTransport<S = TcpStream> {
+/// RFC 5705/8446 exporter label for [`SentinelTransport::channel_binding`].
+const CHANNEL_BINDING_LABEL: &[u8] = b"EXPORTER-sentinel-channel-binding";
+
+pub struct TlsTransport<S = RawTcpTransport> {
     pub(crate) inner: TlsStream<S>,
+    meter: Option<Meter>,
+    early_data: Vec<u8>,
 }
 
 impl<S> TlsTransport<S> {
     pub fn new(inner: TlsStream<S>) -> Self {
-        Self { inner }
+        Self { inner, meter: None, early_data: Vec::new() }
+    }
+
+    pub(crate) fn with_early_data(mut self, early_data: Vec<u8>) -> Self {
+        self.early_data = early_data;
+        self
+    }
+
+    /// What the client sent as 0-RTT data, on a server connection accepted with
+    /// [`SentinelAcceptor::with_early_data`](crate::SentinelAcceptor::with_early_data).
+    /// It is kept apart from the stream, and anyone who captured it can replay
+    /// it on a connection of their own, so act only on what is safe to repeat.
+    pub fn take_early_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.early_data)
+    }
+
+    /// Counts this connection and its traffic into `metrics`.
+    pub fn metered(mut self, metrics: &Arc<TransportMetrics>) -> Self {
+        self.meter = Some(Meter::new(metrics));
+        self
     }
 }
 
 #[async_trait]
 impl<S> SentinelTransport for TlsTransport<S> 
 where 
-    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    S: SentinelTransport,
 {
     fn peer_addr(&self) -> Result<SocketAddr, std::io::Error> {
         let (raw_stream, _) = self.inner.get_ref();
-        unsafe {
-            let tcp_ptr = raw_stream as *const S as *const TcpStream;
-            (*tcp_ptr).peer_addr()
-        }
+        raw_stream.peer_addr()
     }
 
     fn is_secure(&self) -> bool {
         true
     }
+
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        let (_, session) = self.inner.get_ref();
+        session.peer_certificates()?.first().map(|cert| cert.clone().into_owned())
+    }
+
+    /// Derived from the peer certificate when it carries an Ed25519 identity key.
+    fn peer_node_id(&self) -> Option<String> {
+        node_id_from_certificate(&self.peer_certificate()?).ok()
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        let output = [0u8; 32];
+        let exported = match &self.inner {
+            TlsStream::Client(stream) => stream.get_ref().1.export_keying_material(output, CHANNEL_BINDING_LABEL, None),
+            TlsStream::Server(stream) => stream.get_ref().1.export_keying_material(output, CHANNEL_BINDING_LABEL, None),
+        };
+        exported.ok().map(|binding| binding.to_vec())
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        self.meter.as_ref().map(Meter::metrics)
+    }
 }
 
 impl<S> AsyncRead for TlsTransport<S> 
@@ -43,7 +90,12 @@ where S: AsyncRead + AsyncWrite + Unpin
         cx: &mut Context<'_>,
         buf: &mut ReadBuf<'_>,
     ) -> Poll<std::io::Result<()>> {
-        Pin::new(&mut self.inner).poll_read(cx, buf)
+        let filled = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Some(meter) = &self.meter {
+            meter.read(&poll, buf, filled);
+        }
+        poll
     }
 }
 
@@ -55,7 +107,11 @@ where S: AsyncRead + AsyncWrite + Unpin
         cx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<std::io::Result<usize>> {
-        Pin::new(&mut self.inner).poll_write(cx, buf)
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Some(meter) = &self.meter {
+            meter.wrote(&poll);
+        }
+        poll
     }
 
     fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
@@ -1,6 +1,12 @@
-use std::path::Path;
-use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use anyhow::Context;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{alg_id, CertificateDer, PrivateKeyDer, SubjectPublicKeyInfoDer};
+use rustls::sign::{public_key_to_spki, CertifiedKey, Signer, SigningKey, SingleCertAndKey};
+use rustls::{RootCertStore, SignatureAlgorithm, SignatureScheme};
 use rustls_pemfile::{certs, private_key};
+use sentinel_crypto::{ExternalSigner, NodeIdentity, VerifyingKey};
 use std::fs::File;
 use std::io::BufReader;
 
@@ -13,4 +19,148 @@ pub fn load_private_key(path: &Path) -> std::io::Result<PrivateKeyDer<'static>>
     let mut reader = BufReader::new(File::open(path)?);
     private_key(&mut reader)?
         .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "No private key found"))
-}
\ No newline at end of file
+}
+
+/// The process-wide rustls provider if one is installed, ring otherwise.
+pub fn crypto_provider() -> Arc<CryptoProvider> {
+    CryptoProvider::get_default()
+        .cloned()
+        .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()))
+}
+
+/// The certificate an endpoint presents, the key that signs its handshakes and,
+/// for mutual TLS, the CA bundle peers must chain to. Loaded and checked once,
+/// then shared by every acceptor and connector built from it.
+#[derive(Clone, Debug)]
+pub struct TlsCredentials {
+    certified: Arc<CertifiedKey>,
+    roots: Option<Arc<RootCertStore>>,
+}
+
+impl TlsCredentials {
+    /// A PEM certificate chain and private key, e.g. issued by the operator's CA.
+    /// Fails if the key does not belong to the certificate.
+    pub fn from_files(cert_path: &Path, key_path: &Path) -> anyhow::Result<Self> {
+        let certs = load_certs(cert_path).with_context(|| format!("Failed to read {}", cert_path.display()))?;
+        if certs.is_empty() {
+            anyhow::bail!("No certificate found in {}", cert_path.display());
+        }
+        let key = load_private_key(key_path).with_context(|| format!("Failed to read {}", key_path.display()))?;
+        let certified = CertifiedKey::from_der(certs, key, &crypto_provider())
+            .with_context(|| format!("{} does not match {}", key_path.display(), cert_path.display()))?;
+        Ok(Self { certified: Arc::new(certified), roots: None })
+    }
+
+    /// A self-signed certificate derived from `identity`, held only in memory.
+    /// Handshakes are signed through [`NodeIdentity::signer`], so the private key
+    /// is never serialized and may stay in an external signer.
+    pub fn from_identity(identity: &NodeIdentity) -> anyhow::Result<Self> {
+        let cert = identity.self_signed_certificate()?;
+        let key = IdentitySigningKey { public_key: identity.public_key(), signer: identity.signer() };
+        let certified = CertifiedKey::new(vec![CertificateDer::from(cert.cert_der)], Arc::new(key));
+        Ok(Self { certified: Arc::new(certified), roots: None })
+    }
+
+    /// Adds the CA bundle at `ca_path`, which mutual TLS peers must chain to.
+    pub fn with_ca(mut self, ca_path: &Path) -> anyhow::Result<Self> {
+        let mut roots = RootCertStore::empty();
+        for cert in load_certs(ca_path).with_context(|| format!("Failed to read {}", ca_path.display()))? {
+            roots.add(cert)?;
+        }
+        self.roots = Some(Arc::new(roots));
+        Ok(self)
+    }
+
+    /// The certificate presented to peers.
+    pub fn end_entity(&self) -> &CertificateDer<'static> {
+        &self.certified.cert[0]
+    }
+
+    /// The CA bundle added by [`with_ca`](Self::with_ca), if any.
+    pub fn roots(&self) -> Option<Arc<RootCertStore>> {
+        self.roots.clone()
+    }
+
+    pub(crate) fn resolver(&self) -> Arc<SingleCertAndKey> {
+        Arc::new(SingleCertAndKey::from(self.certified.clone()))
+    }
+}
+
+/// The files credentials were read from, kept by acceptors and connectors to reload them.
+#[derive(Clone)]
+pub(crate) struct CredentialFiles {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    ca_path: Option<PathBuf>,
+}
+
+impl CredentialFiles {
+    pub(crate) fn new(cert_path: &Path, key_path: &Path, ca_path: Option<&Path>) -> Self {
+        Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            ca_path: ca_path.map(Path::to_path_buf),
+        }
+    }
+
+    pub(crate) fn load(&self) -> anyhow::Result<TlsCredentials> {
+        let credentials = TlsCredentials::from_files(&self.cert_path, &self.key_path)?;
+        match &self.ca_path {
+            Some(ca_path) => credentials.with_ca(ca_path),
+            None => Ok(credentials),
+        }
+    }
+}
+
+/// Signs TLS handshakes as a node identity, whether its key is local or external.
+struct IdentitySigningKey {
+    public_key: VerifyingKey,
+    signer: Arc<ExternalSigner>,
+}
+
+impl std::fmt::Debug for IdentitySigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentitySigningKey").field("public_key", &self.public_key).finish()
+    }
+}
+
+impl SigningKey for IdentitySigningKey {
+    fn choose_scheme(&self, offered: &[SignatureScheme]) -> Option<Box<dyn Signer>> {
+        offered.contains(&SignatureScheme::ED25519).then(|| {
+            Box::new(IdentitySigner { public_key: self.public_key, signer: self.signer.clone() }) as Box<dyn Signer>
+        })
+    }
+
+    fn public_key(&self) -> Option<SubjectPublicKeyInfoDer<'_>> {
+        Some(public_key_to_spki(&alg_id::ED25519, self.public_key.as_bytes()))
+    }
+
+    fn algorithm(&self) -> SignatureAlgorithm {
+        SignatureAlgorithm::ED25519
+    }
+}
+
+struct IdentitySigner {
+    public_key: VerifyingKey,
+    signer: Arc<ExternalSigner>,
+}
+
+impl std::fmt::Debug for IdentitySigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("IdentitySigner").field("public_key", &self.public_key).finish()
+    }
+}
+
+impl Signer for IdentitySigner {
+    fn sign(&self, message: &[u8]) -> Result<Vec<u8>, rustls::Error> {
+        let signature = self
+            .signer
+            .try_sign(message)
+            .map_err(|e| rustls::Error::General(format!("Identity signer failed: {}", e)))?;
+        Ok(signature.to_bytes().to_vec())
+    }
+
+    fn scheme(&self) -> SignatureScheme {
+        SignatureScheme::ED25519
+    }
+}
@@ -0,0 +1,258 @@
+use std::sync::Arc;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::{CertificateError, DigitallySignedStruct, DistinguishedName, OtherError, SignatureScheme};
+use sentinel_crypto::node_id_from_certificate;
+
+/// Accepts any server certificate whose handshake signature checks out,
+/// leaving the trust decision to the caller (trust-on-first-use pinning).
+///
+/// The peer still has to prove possession of the certificate's private key;
+/// only chain building and hostname checks are skipped.
+#[derive(Debug)]
+pub struct TofuServerVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl TofuServerVerifier {
+    pub fn new(provider: Arc<CryptoProvider>) -> Self {
+        Self { provider }
+    }
+}
+
+impl ServerCertVerifier for TofuServerVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("certificate belongs to node {presented}, expected {expected}")]
+struct NodeIdMismatch {
+    expected: String,
+    presented: String,
+}
+
+/// The node ID in an identity certificate, as a rustls certificate error if there isn't one.
+fn certificate_node_id(cert: &CertificateDer<'_>) -> Result<String, rustls::Error> {
+    node_id_from_certificate(cert)
+        .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+}
+
+/// Accepts a server only if its certificate carries the Ed25519 key of
+/// `expected_node_id`. Chains and hostnames are ignored: the node ID is the trust anchor.
+#[derive(Debug)]
+pub struct NodeIdServerVerifier {
+    expected_node_id: String,
+    tofu: TofuServerVerifier,
+}
+
+impl NodeIdServerVerifier {
+    pub fn new(provider: Arc<CryptoProvider>, expected_node_id: impl Into<String>) -> Self {
+        Self { expected_node_id: expected_node_id.into(), tofu: TofuServerVerifier::new(provider) }
+    }
+}
+
+impl ServerCertVerifier for NodeIdServerVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let presented = certificate_node_id(end_entity)?;
+        if presented != self.expected_node_id {
+            let mismatch = NodeIdMismatch { expected: self.expected_node_id.clone(), presented };
+            return Err(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(Arc::new(mismatch)))));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.tofu.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.tofu.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.tofu.supported_verify_schemes()
+    }
+}
+
+/// Requires clients to present an identity certificate (one carrying an
+/// Ed25519 node key) and prove they hold its key. Which node IDs are welcome
+/// is left to the caller, via [`SentinelTransport::peer_node_id`](crate::SentinelTransport::peer_node_id).
+#[derive(Debug)]
+pub struct NodeIdClientVerifier {
+    tofu: TofuServerVerifier,
+}
+
+impl NodeIdClientVerifier {
+    pub fn new(provider: Arc<CryptoProvider>) -> Self {
+        Self { tofu: TofuServerVerifier::new(provider) }
+    }
+}
+
+impl ClientCertVerifier for NodeIdClientVerifier {
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        certificate_node_id(end_entity)?;
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.tofu.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.tofu.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.tofu.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ClientAuth, RawTcpTransport, SentinelAcceptor, SentinelConnector, SentinelTransport, TlsCredentials};
+    use sentinel_crypto::NodeIdentity;
+    use std::path::Path;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn write_identity(dir: &Path, name: &str) -> NodeIdentity {
+        let identity = NodeIdentity::generate();
+        identity
+            .self_signed_certificate()
+            .unwrap()
+            .save(dir.join(format!("{}.crt", name)), dir.join(format!("{}.key", name)))
+            .unwrap();
+        identity
+    }
+
+    #[tokio::test]
+    async fn test_connect_to_node_binds_node_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let server_id = write_identity(dir.path(), "server");
+        let client_id = write_identity(dir.path(), "client");
+
+        let acceptor =
+            SentinelAcceptor::new_node_auth(&path("server.crt"), &path("server.key"), Duration::from_secs(5)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let expected_client = client_id.node_id();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut tls = acceptor.accept(RawTcpTransport::new(stream)).await.unwrap();
+            assert_eq!(tls.peer_node_id(), Some(expected_client));
+            tls.write_all(b"ok").await.unwrap();
+            tls.flush().await.unwrap();
+
+            // The second client expects another node and aborts the handshake.
+            let (stream, _) = listener.accept().await.unwrap();
+            assert!(acceptor.accept(stream).await.is_err());
+        });
+
+        let connector = SentinelConnector::new_node(&path("client.crt"), &path("client.key")).unwrap();
+        let mut tls = connector.connect_to_node(&server_id.node_id(), &addr).await.unwrap();
+        assert_eq!(tls.peer_node_id(), Some(server_id.node_id()));
+        let mut reply = [0u8; 2];
+        tls.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"ok");
+
+        let impostor = NodeIdentity::generate().node_id();
+        assert!(connector.connect_to_node(&impostor, &addr).await.is_err());
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_external_signer_identities_handshake_from_memory() {
+        let backing = NodeIdentity::generate();
+        let server_id = NodeIdentity::from_external_signer(backing.public_key(), backing.signer());
+        let client_id = NodeIdentity::generate();
+
+        let credentials = TlsCredentials::from_identity(&server_id).unwrap();
+        let acceptor = SentinelAcceptor::from_credentials(&credentials, ClientAuth::NodeId, Duration::from_secs(5)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let expected_client = client_id.node_id();
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let tls = acceptor.accept(RawTcpTransport::new(stream)).await.unwrap();
+            assert_eq!(tls.peer_node_id(), Some(expected_client));
+        });
+
+        let connector = SentinelConnector::from_credentials(&TlsCredentials::from_identity(&client_id).unwrap()).unwrap();
+        let tls = connector.connect_to_node(&server_id.node_id(), &addr).await.unwrap();
+        assert_eq!(tls.peer_node_id(), Some(backing.node_id()));
+        server.await.unwrap();
+    }
+}
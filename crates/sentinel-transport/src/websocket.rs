@@ -0,0 +1,274 @@
+use crate::error::{TransportError, TransportResult};
+use crate::{SentinelTransport, TransportMetrics};
+use async_trait::async_trait;
+use bytes::{Buf, Bytes};
+use futures_util::{Sink, Stream};
+use rustls::pki_types::CertificateDer;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::header::SEC_WEBSOCKET_PROTOCOL;
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::WebSocketStream;
+
+/// Subprotocol both ends must agree on, the WebSocket counterpart of ALPN `sentinel-v1`.
+pub const WS_SUBPROTOCOL: &str = "sentinel-v1";
+
+/// Buffered writes are sent once they reach this size, even before a flush.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+fn ws_error(e: impl std::fmt::Display) -> TransportError {
+    TransportError::WebSocket(e.to_string())
+}
+
+fn ws_io_error(e: impl std::fmt::Display) -> io::Error {
+    io::Error::other(e.to_string())
+}
+
+/// Upgrades inbound connections, plain or already TLS, to WebSocket.
+/// Clients must offer the [`WS_SUBPROTOCOL`] subprotocol.
+#[derive(Clone)]
+pub struct WebSocketAcceptor {
+    handshake_timeout: Duration,
+}
+
+impl WebSocketAcceptor {
+    pub fn new(handshake_timeout: Duration) -> Self {
+        Self { handshake_timeout }
+    }
+
+    pub async fn accept<S>(&self, stream: S) -> TransportResult<WebSocketTransport<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let upgrade = tokio_tungstenite::accept_hdr_async(stream, select_subprotocol);
+        match tokio::time::timeout(self.handshake_timeout, upgrade).await {
+            Ok(result) => Ok(WebSocketTransport::new(result.map_err(ws_error)?)),
+            Err(_) => Err(TransportError::HandshakeTimeout),
+        }
+    }
+}
+
+// The signature is fixed by tungstenite's handshake callback.
+#[allow(clippy::result_large_err)]
+fn select_subprotocol(request: &Request, mut response: Response) -> Result<Response, ErrorResponse> {
+    let offered = request
+        .headers()
+        .get_all(SEC_WEBSOCKET_PROTOCOL)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|protocol| protocol.trim() == WS_SUBPROTOCOL);
+    if !offered {
+        let mut error = ErrorResponse::new(Some(format!("WebSocket subprotocol {} required", WS_SUBPROTOCOL)));
+        *error.status_mut() = StatusCode::BAD_REQUEST;
+        return Err(error);
+    }
+    response
+        .headers_mut()
+        .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(WS_SUBPROTOCOL));
+    Ok(response)
+}
+
+/// Opens WebSocket connections over a stream the caller has already connected
+/// (and secured, for `wss://` URLs).
+#[derive(Clone)]
+pub struct WebSocketConnector {
+    handshake_timeout: Duration,
+}
+
+impl WebSocketConnector {
+    pub fn new(handshake_timeout: Duration) -> Self {
+        Self { handshake_timeout }
+    }
+
+    pub async fn connect<S>(&self, url: &str, stream: S) -> TransportResult<WebSocketTransport<S>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut request = url.into_client_request().map_err(ws_error)?;
+        request
+            .headers_mut()
+            .insert(SEC_WEBSOCKET_PROTOCOL, HeaderValue::from_static(WS_SUBPROTOCOL));
+
+        let upgrade = tokio_tungstenite::client_async(request, stream);
+        match tokio::time::timeout(self.handshake_timeout, upgrade).await {
+            Ok(result) => Ok(WebSocketTransport::new(result.map_err(ws_error)?.0)),
+            Err(_) => Err(TransportError::HandshakeTimeout),
+        }
+    }
+}
+
+/// A byte stream carried in binary WebSocket messages, so Sentinel frames can
+/// reach browsers. Message boundaries carry no meaning: a frame may span
+/// messages, and a message may hold several frames. Each flush sends one message.
+pub struct WebSocketTransport<S> {
+    inner: WebSocketStream<S>,
+    read_buf: Bytes,
+    write_buf: Vec<u8>,
+}
+
+impl<S> WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        Self { inner, read_buf: Bytes::new(), write_buf: Vec::new() }
+    }
+
+    /// Hands buffered writes to the WebSocket as one binary message.
+    fn poll_send_buffered(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if !self.write_buf.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(ws_io_error)?;
+            let payload = Bytes::from(std::mem::take(&mut self.write_buf));
+            Pin::new(&mut self.inner).start_send(Message::Binary(payload)).map_err(ws_io_error)?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[async_trait]
+impl<S> SentinelTransport for WebSocketTransport<S>
+where
+    S: SentinelTransport,
+{
+    fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.inner.get_ref().peer_addr()
+    }
+
+    fn is_secure(&self) -> bool {
+        self.inner.get_ref().is_secure()
+    }
+
+    fn peer_certificate(&self) -> Option<CertificateDer<'static>> {
+        self.inner.get_ref().peer_certificate()
+    }
+
+    fn peer_node_id(&self) -> Option<String> {
+        self.inner.get_ref().peer_node_id()
+    }
+
+    fn channel_binding(&self) -> Option<Vec<u8>> {
+        self.inner.get_ref().channel_binding()
+    }
+
+    fn metrics(&self) -> Option<&Arc<TransportMetrics>> {
+        self.inner.get_ref().metrics()
+    }
+}
+
+impl<S> AsyncRead for WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.read_buf.is_empty() {
+                let len = this.read_buf.len().min(buf.remaining());
+                buf.put_slice(&this.read_buf[..len]);
+                this.read_buf.advance(len);
+                return Poll::Ready(Ok(()));
+            }
+            match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => this.read_buf = data,
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "text WebSocket message; Sentinel frames travel in binary messages",
+                    )))
+                }
+                // Pings are answered by the WebSocket layer itself.
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => {}
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Err(e)) => return Poll::Ready(Err(ws_io_error(e))),
+            }
+        }
+    }
+}
+
+impl<S> AsyncWrite for WebSocketTransport<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if this.write_buf.len() >= MAX_MESSAGE_LEN {
+            ready!(this.poll_send_buffered(cx))?;
+        }
+        let len = buf.len().min(MAX_MESSAGE_LEN - this.write_buf.len());
+        this.write_buf.extend_from_slice(&buf[..len]);
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send_buffered(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx).map_err(ws_io_error)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_send_buffered(cx))?;
+        Pin::new(&mut this.inner).poll_close(cx).map_err(ws_io_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RawTcpTransport;
+    use futures::{SinkExt, StreamExt};
+    use sentinel_protocol::{Frame, SentinelCodec};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_util::codec::Framed;
+
+    #[tokio::test]
+    async fn test_frames_cross_websocket_messages() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let acceptor = WebSocketAcceptor::new(Duration::from_secs(5));
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let ws = acceptor.accept(RawTcpTransport::new(stream)).await.unwrap();
+            assert!(ws.peer_addr().unwrap().ip().is_loopback());
+            let mut framed = Framed::new(ws, SentinelCodec::new());
+            for _ in 0..2 {
+                let frame = framed.next().await.unwrap().unwrap();
+                framed.send(frame).await.unwrap();
+            }
+
+            // A client that doesn't speak the subprotocol is turned away.
+            let (stream, _) = listener.accept().await.unwrap();
+            assert!(acceptor.accept(RawTcpTransport::new(stream)).await.is_err());
+        });
+
+        let connector = WebSocketConnector::new(Duration::from_secs(5));
+        let stream = TcpStream::connect(addr).await.unwrap();
+        let url = format!("ws://{}/", addr);
+        let ws = connector.connect(&url, RawTcpTransport::new(stream)).await.unwrap();
+        let mut framed = Framed::new(ws, SentinelCodec::new());
+        let payloads: [&[u8]; 2] = [b"first", &[7u8; 100_000]];
+        for payload in payloads {
+            framed.send(Frame::new(1, 0, payload.to_vec().into()).unwrap()).await.unwrap();
+            let echoed = framed.next().await.unwrap().unwrap();
+            assert_eq!(&echoed.payload()[..], payload);
+        }
+
+        let stream = TcpStream::connect(addr).await.unwrap();
+        assert!(tokio_tungstenite::client_async(url.as_str(), stream).await.is_err());
+        server.await.unwrap();
+    }
+}